@@ -0,0 +1,560 @@
+use std::{collections::HashSet, fs, ops::Range, path::Path};
+
+use anchor_lang::{system_program, Discriminator};
+use anyhow::{anyhow, Context, Result};
+use gacha_machine_client::{
+    constants::KEYS_PER_PAGE, decode, instructions, pda, GachaState, KeyStorage, MultiPullState,
+    PaymentAccounts, PlayerState, RandomnessSource, Role,
+};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+
+use crate::config::CliConfig;
+
+/// Keys per `add_keys` transaction, well within the transaction size limit
+const MAX_KEYS_PER_BATCH: usize = 5;
+
+/// Offset of `gacha_state` in `PlayerState` and `MultiPullState` (after the
+/// discriminator and `user`)
+const PULL_GACHA_STATE_OFFSET: usize = 8 + 32;
+
+/// Offset of `user` in `PlayerState` and `MultiPullState`
+const PULL_USER_OFFSET: usize = 8;
+
+/// RPC connection, signer and machine the commands operate on
+pub struct Client {
+    rpc: RpcClient,
+    payer: Keypair,
+    gacha_state: Pubkey,
+    machine_id: u64,
+}
+
+impl Client {
+    pub fn new(config: &CliConfig) -> Result<Self> {
+        let payer = config.keypair()?;
+        let creator = config.machine_creator(&payer.pubkey())?;
+        let (gacha_state, _) = pda::find_gacha_state(&creator, config.machine_id);
+        Ok(Self {
+            rpc: RpcClient::new_with_commitment(
+                config.rpc_url.clone(),
+                CommitmentConfig::confirmed(),
+            ),
+            payer,
+            gacha_state,
+            machine_id: config.machine_id,
+        })
+    }
+
+    // ============ TRANSACTIONS ============
+
+    /// Signs `instructions` with the configured keypair and sends them in one transaction
+    fn send(&self, instructions: &[Instruction]) -> Result<Signature> {
+        let blockhash = self.rpc.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        );
+        self.rpc
+            .send_and_confirm_transaction(&transaction)
+            .context("transaction failed")
+    }
+
+    fn send_one(&self, instruction: Instruction, action: &str) -> Result<()> {
+        let signature = self.send(&[instruction])?;
+        println!("{action}. Tx: {signature}");
+        Ok(())
+    }
+
+    // ============ ACCOUNT FETCHING ============
+
+    fn fetch_gacha_state(&self) -> Result<GachaState> {
+        let account = self
+            .rpc
+            .get_account(&self.gacha_state)
+            .with_context(|| format!("gacha machine {} not found", self.gacha_state))?;
+        Ok(decode::gacha_state(&account.data)?)
+    }
+
+    /// Encrypted keys already stored in the machine's key pages
+    fn fetch_encrypted_keys(&self, gacha_state: &GachaState) -> Result<Vec<String>> {
+        let key_pages: Vec<Pubkey> = (0..gacha_state.key_page_count)
+            .map(|page_index| pda::find_key_page(&self.gacha_state, page_index).0)
+            .collect();
+        let mut keys = Vec::with_capacity(gacha_state.total_keys as usize);
+        for chunk in key_pages.chunks(100) {
+            for account in self.rpc.get_multiple_accounts(chunk)?.into_iter().flatten() {
+                keys.extend(decode::key_page(&account.data)?.keys);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Program accounts of type `discriminator` belonging to the machine
+    fn fetch_pull_accounts(
+        &self,
+        discriminator: &[u8],
+        user: Option<Pubkey>,
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>> {
+        let mut filters = vec![
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, discriminator.to_vec())),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                PULL_GACHA_STATE_OFFSET,
+                self.gacha_state.to_bytes().to_vec(),
+            )),
+        ];
+        if let Some(user) = user {
+            filters.push(RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                PULL_USER_OFFSET,
+                user.to_bytes().to_vec(),
+            )));
+        }
+        let accounts = self.rpc.get_program_accounts_with_config(
+            &gacha_machine_client::ID,
+            RpcProgramAccountsConfig {
+                filters: Some(filters),
+                account_config: RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    ..RpcAccountInfoConfig::default()
+                },
+                ..RpcProgramAccountsConfig::default()
+            },
+        )?;
+        Ok(accounts
+            .into_iter()
+            .map(|(address, account)| (address, account.data))
+            .collect())
+    }
+
+    // ============ MACHINE SETUP ============
+
+    pub fn init(&self) -> Result<()> {
+        let (gacha_state, _) = pda::find_gacha_state(&self.payer.pubkey(), self.machine_id);
+        if gacha_state != self.gacha_state {
+            return Err(anyhow!(
+                "machineCreator must be empty or the keypair's address to create a machine"
+            ));
+        }
+        self.send_one(
+            instructions::initialize(self.payer.pubkey(), self.machine_id),
+            &format!("Initialized gacha machine {gacha_state}"),
+        )
+    }
+
+    pub fn add_payment_config(
+        &self,
+        mint: Pubkey,
+        price: u64,
+        recipient: Option<Pubkey>,
+    ) -> Result<()> {
+        let payment = self.payment_accounts(mint)?;
+        let recipient = recipient.unwrap_or_else(|| payment.owner_account(&self.payer.pubkey()));
+
+        // SPL payments are held in the config's associated token account, which
+        // pulls require to exist
+        let mut config_instructions = vec![instructions::add_payment_config(
+            self.gacha_state,
+            self.payer.pubkey(),
+            mint,
+            price,
+            recipient,
+        )];
+        config_instructions.extend(instructions::create_payment_vault(
+            self.payer.pubkey(),
+            &payment,
+        ));
+        let signature = self.send(&config_instructions)?;
+        println!(
+            "Added payment config for {mint} (price {price}, recipient {recipient}, vault {}). Tx: {signature}",
+            payment.vault
+        );
+        Ok(())
+    }
+
+    /// Payment accounts of `mint`, reading the token program from the mint's owner
+    fn payment_accounts(&self, mint: Pubkey) -> Result<PaymentAccounts> {
+        if mint == system_program::ID {
+            return Ok(PaymentAccounts::sol(&self.gacha_state));
+        }
+        let mint_account = self
+            .rpc
+            .get_account(&mint)
+            .with_context(|| format!("mint {mint} not found"))?;
+        Ok(PaymentAccounts::spl(
+            &self.gacha_state,
+            &mint,
+            &mint_account.owner,
+        ))
+    }
+
+    /// Adds the keys of `file` in batches that never cross a key page boundary,
+    /// creating key pages as needed
+    pub fn add_keys(&self, file: &Path, tier: u8) -> Result<()> {
+        let contents = fs::read_to_string(file)
+            .with_context(|| format!("failed to read {}", file.display()))?;
+        let gacha_state = self.fetch_gacha_state()?;
+        if gacha_state.key_storage != KeyStorage::Paged {
+            return Err(anyhow!("keys can only be added to paged machines"));
+        }
+
+        let existing_keys = self.fetch_encrypted_keys(&gacha_state)?;
+        let (keys, already_present) = keys_to_add(&contents, &existing_keys)?;
+        println!(
+            "{} keys on the machine, {already_present} keys of the file already present, {} new keys to add.",
+            existing_keys.len(),
+            keys.len()
+        );
+
+        let first_index = gacha_state.total_keys as usize;
+        let mut key_page_count = gacha_state.key_page_count;
+        for indices in key_batches(first_index, keys.len()) {
+            let page_index = (indices.start / KEYS_PER_PAGE) as u16;
+            let batch = &keys[indices.start - first_index..indices.end - first_index];
+
+            let mut batch_instructions = Vec::with_capacity(2);
+            if page_index >= key_page_count {
+                batch_instructions.push(instructions::add_key_page(
+                    self.gacha_state,
+                    self.payer.pubkey(),
+                    page_index,
+                ));
+                key_page_count += 1;
+            }
+            batch_instructions.push(instructions::add_keys(
+                self.gacha_state,
+                self.payer.pubkey(),
+                page_index,
+                batch.to_vec(),
+                tier,
+            ));
+            let signature = self.send(&batch_instructions)?;
+            println!(
+                "Added keys {}-{} to page {page_index}. Tx: {signature}",
+                indices.start,
+                indices.end - 1
+            );
+        }
+        Ok(())
+    }
+
+    pub fn finalize(&self, server_seed_hash: Option<[u8; 32]>) -> Result<()> {
+        self.send_one(
+            instructions::finalize(self.gacha_state, self.payer.pubkey(), server_seed_hash),
+            "Finalized gacha machine",
+        )
+    }
+
+    // ============ MACHINE CONTROLS ============
+
+    pub fn set_paused(&self, paused: bool) -> Result<()> {
+        self.send_one(
+            instructions::set_paused(self.gacha_state, self.payer.pubkey(), paused),
+            if paused {
+                "Paused pulls"
+            } else {
+                "Unpaused pulls"
+            },
+        )
+    }
+
+    pub fn set_halted(&self, halted: bool) -> Result<()> {
+        self.send_one(
+            instructions::set_halted(self.gacha_state, self.payer.pubkey(), halted),
+            if halted {
+                "Halted settlements"
+            } else {
+                "Unhalted settlements"
+            },
+        )
+    }
+
+    pub fn transfer_admin(&self, new_admin: Pubkey) -> Result<()> {
+        self.send_one(
+            instructions::propose_admin(self.gacha_state, self.payer.pubkey(), new_admin),
+            &format!("Proposed {new_admin} as admin; it must run accept-admin"),
+        )
+    }
+
+    pub fn accept_admin(&self) -> Result<()> {
+        self.send_one(
+            instructions::accept_admin(self.gacha_state, self.payer.pubkey()),
+            "Accepted the admin transfer",
+        )
+    }
+
+    pub fn release_key(&self, decryption_key: String) -> Result<()> {
+        self.send_one(
+            instructions::release_decryption_key(
+                self.gacha_state,
+                self.payer.pubkey(),
+                decryption_key,
+            ),
+            "Released the decryption key",
+        )
+    }
+
+    // ============ VIEWS ============
+
+    pub fn inspect(&self) -> Result<()> {
+        let state = self.fetch_gacha_state()?;
+
+        println!("=== GACHA STATE {} ===", self.gacha_state);
+        println!("Admin: {}", state.admin);
+        if let Some(pending_admin) = state.pending_admin {
+            println!("Pending admin: {pending_admin}");
+        }
+        println!(
+            "Creator: {} (machine id {})",
+            state.creator, state.machine_id
+        );
+        println!(
+            "Finalized: {} | Paused: {} | Halted: {}",
+            state.is_finalized, state.is_paused, state.is_halted
+        );
+        println!(
+            "Pulls: {} | Settled: {} | Claimed: {} | Refunded: {}",
+            state.pull_count, state.settle_count, state.claim_count, state.refund_count
+        );
+        println!(
+            "Keys: {} total, {} remaining ({})",
+            state.total_keys,
+            state.remaining_keys(),
+            match state.key_storage {
+                KeyStorage::Paged => format!("{} key pages", state.key_page_count),
+                KeyStorage::Merkle => "Merkle commitment".to_string(),
+            }
+        );
+        println!(
+            "Randomness source: {}",
+            match state.randomness_source {
+                RandomnessSource::Switchboard => "Switchboard",
+                RandomnessSource::Deterministic => "Deterministic",
+                RandomnessSource::CommitReveal => "Commit-reveal",
+            }
+        );
+        println!(
+            "Refund timeout: {} slots | Pity threshold: {} | Crank tip: {} bps | Instant max prize value: {}",
+            state.refund_timeout_slots,
+            state.pity_threshold,
+            state.crank_tip_bps,
+            state.instant_max_prize_value
+        );
+        if !state.decryption_key.is_empty() {
+            println!("Decryption key: {}", state.decryption_key);
+        }
+
+        println!("\n=== TIERS ===");
+        for (tier_index, tier) in state.tiers.iter().enumerate() {
+            println!(
+                "Tier {tier_index}: weight {}, {} keys, {} remaining",
+                tier.weight,
+                tier.key_count,
+                tier.remaining_indices.len()
+            );
+        }
+
+        println!("\n=== ROLES ===");
+        for grant in &state.roles {
+            let role = match grant.role {
+                Role::Pauser => "Pauser",
+                Role::KeyManager => "KeyManager",
+                Role::PricingManager => "PricingManager",
+                Role::Treasurer => "Treasurer",
+            };
+            println!("{role}: {}", grant.account);
+        }
+
+        println!("\n=== PAYMENT CONFIGS ===");
+        for account in self
+            .rpc
+            .get_multiple_accounts(&state.payment_configs)?
+            .into_iter()
+            .flatten()
+        {
+            let payment_config = decode::payment_config(&account.data)?;
+            println!("Mint: {}", payment_config.mint);
+            match payment_config.usd_pricing {
+                Some(usd_pricing) => println!(
+                    "  Price: {} micro-USD (feed {})",
+                    usd_pricing.usd_price, usd_pricing.price_feed
+                ),
+                None => println!("  Price: {}", payment_config.price),
+            }
+            println!("  Recipient: {}", payment_config.admin_recipient_account);
+            println!(
+                "  Escrowed: {} | Settled: {} | Withdrawn: {} | Bulk discount: {} bps",
+                payment_config.escrowed_amount,
+                payment_config.settled_amount,
+                payment_config.withdrawn_amount,
+                payment_config.bulk_discount_bps
+            );
+        }
+        Ok(())
+    }
+
+    pub fn list_pulls(&self, user: Option<Pubkey>) -> Result<()> {
+        let mut pulls = self
+            .fetch_pull_accounts(PlayerState::DISCRIMINATOR, user)?
+            .into_iter()
+            .map(|(address, data)| Ok((address, decode::player_state(&data)?)))
+            .collect::<Result<Vec<_>>>()?;
+        pulls.sort_by_key(|(_, pull)| pull.nonce);
+
+        println!("=== PULLS ({}) ===", pulls.len());
+        for (address, pull) in &pulls {
+            let status = pull_status(pull.is_settled, pull.is_refunded);
+            println!(
+                "#{} {address} user {} paid {} ({}) {status}{}",
+                pull.nonce,
+                pull.user,
+                pull.price_paid,
+                pull.payment_mint,
+                if pull.is_settled {
+                    format!(
+                        " -> key {} (tier {}){}",
+                        pull.result_index,
+                        pull.result_tier,
+                        if pull.is_claimed { ", claimed" } else { "" }
+                    )
+                } else {
+                    String::new()
+                }
+            );
+        }
+
+        let mut multi_pulls = self
+            .fetch_pull_accounts(MultiPullState::DISCRIMINATOR, user)?
+            .into_iter()
+            .map(|(address, data)| Ok((address, decode::multi_pull_state(&data)?)))
+            .collect::<Result<Vec<_>>>()?;
+        multi_pulls.sort_by_key(|(_, multi_pull)| multi_pull.nonce);
+
+        println!("\n=== MULTI-PULLS ({}) ===", multi_pulls.len());
+        for (address, multi_pull) in &multi_pulls {
+            let status = pull_status(multi_pull.is_settled, multi_pull.is_refunded);
+            println!(
+                "#{} {address} user {} {} pulls paid {} ({}) {status}{}",
+                multi_pull.nonce,
+                multi_pull.user,
+                multi_pull.count,
+                multi_pull.price_paid,
+                multi_pull.payment_mint,
+                if multi_pull.is_settled {
+                    format!(" -> keys {:?}", multi_pull.result_indices)
+                } else {
+                    String::new()
+                }
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Keys of a key file still to be added (trimmed, non-empty lines not stored on
+/// the machine yet) and the number of file keys already stored, so an interrupted
+/// upload can be resumed. Keys repeated within the file are rejected: the program
+/// would store every copy as a separate prize.
+fn keys_to_add(contents: &str, existing_keys: &[String]) -> Result<(Vec<String>, usize)> {
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    for key in contents
+        .lines()
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+    {
+        if !seen.insert(key) && !duplicates.contains(&key) {
+            duplicates.push(key);
+        }
+    }
+    if !duplicates.is_empty() {
+        return Err(anyhow!(
+            "the key file repeats {} keys: {}",
+            duplicates.len(),
+            duplicates.join(", ")
+        ));
+    }
+
+    let existing: HashSet<&str> = existing_keys.iter().map(String::as_str).collect();
+    let mut already_present = 0;
+    let mut keys = Vec::new();
+    for key in contents
+        .lines()
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+    {
+        if existing.contains(key) {
+            already_present += 1;
+        } else {
+            keys.push(key.to_string());
+        }
+    }
+    Ok((keys, already_present))
+}
+
+/// Key index ranges of the `add_keys` batches adding `key_count` keys from
+/// `first_index` on: at most `MAX_KEYS_PER_BATCH` keys, never crossing a key page
+fn key_batches(first_index: usize, key_count: usize) -> Vec<Range<usize>> {
+    let end = first_index + key_count;
+    let mut batches = Vec::new();
+    let mut start = first_index;
+    while start < end {
+        let room_in_page = KEYS_PER_PAGE - start % KEYS_PER_PAGE;
+        let batch_end = end.min(start + MAX_KEYS_PER_BATCH.min(room_in_page));
+        batches.push(start..batch_end);
+        start = batch_end;
+    }
+    batches
+}
+
+fn pull_status(is_settled: bool, is_refunded: bool) -> &'static str {
+    match (is_settled, is_refunded) {
+        (true, _) => "settled",
+        (_, true) => "refunded",
+        _ => "pending",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_to_add_skips_stored_keys() {
+        let existing = vec!["key_a".to_string()];
+        let (keys, already_present) = keys_to_add("key_a\n  key_b \n\nkey_c\n", &existing).unwrap();
+        assert_eq!(keys, ["key_b", "key_c"]);
+        assert_eq!(already_present, 1);
+    }
+
+    #[test]
+    fn keys_to_add_rejects_repeated_keys() {
+        let error = keys_to_add("key_a\nkey_b\nkey_a\nkey_b\nkey_a", &[]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the key file repeats 2 keys: key_a, key_b"
+        );
+    }
+
+    #[test]
+    fn key_batches_never_cross_a_page() {
+        assert_eq!(key_batches(0, 12), [0..5, 5..10, 10..12]);
+        assert_eq!(key_batches(62, 6), [62..64, 64..68]);
+        assert!(key_batches(10, 0).is_empty());
+        for batch in key_batches(3, 300) {
+            assert!(batch.len() <= MAX_KEYS_PER_BATCH);
+            assert_eq!(batch.start / KEYS_PER_PAGE, (batch.end - 1) / KEYS_PER_PAGE);
+        }
+    }
+}
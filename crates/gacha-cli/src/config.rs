@@ -0,0 +1,61 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair},
+};
+
+/// Default location of the CLI config file
+pub const DEFAULT_CONFIG_PATH: &str = "config/cli.json";
+
+/// CLI config file (see `config/cli.example.json`)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CliConfig {
+    /// JSON RPC URL of the cluster
+    pub rpc_url: String,
+    /// Path of the keypair file signing and paying for transactions (`~` is expanded)
+    pub keypair_path: String,
+    /// Id of the machine to operate on
+    #[serde(default)]
+    pub machine_id: u64,
+    /// Creator of the machine; empty or missing for machines created by the keypair
+    #[serde(default)]
+    pub machine_creator: String,
+}
+
+impl CliConfig {
+    /// Reads the config file at `path`
+    pub fn load(path: &str) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("failed to read config {path}"))?;
+        serde_json::from_str(&contents).with_context(|| format!("invalid config {path}"))
+    }
+
+    /// Reads the configured keypair
+    pub fn keypair(&self) -> Result<Keypair> {
+        let path = expand_home(&self.keypair_path);
+        read_keypair_file(&path)
+            .map_err(|err| anyhow!("failed to read keypair {}: {err}", path.display()))
+    }
+
+    /// Creator of the machine, defaulting to the configured keypair
+    pub fn machine_creator(&self, payer: &Pubkey) -> Result<Pubkey> {
+        if self.machine_creator.is_empty() {
+            return Ok(*payer);
+        }
+        self.machine_creator
+            .parse()
+            .map_err(|err| anyhow!("invalid machineCreator {}: {err}", self.machine_creator))
+    }
+}
+
+/// Expands a leading `~/` to the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,140 @@
+// Gacha Machine CLI
+//
+// Command-line admin tool for the gacha machine program. The cluster URL, the
+// signing keypair and the machine to operate on are read from a config file
+// (`config/cli.json` by default, see `config/cli.example.json`).
+
+mod commands;
+mod config;
+
+use std::path::PathBuf;
+
+use anchor_lang::system_program;
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{commands::Client, config::CliConfig};
+
+#[derive(Parser)]
+#[command(name = "gacha-cli", about = "Admin tool for the gacha machine program")]
+struct Cli {
+    /// Path of the config file
+    #[arg(long, default_value = config::DEFAULT_CONFIG_PATH)]
+    config: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the configured machine, with the keypair as admin
+    Init,
+    /// Add a payment config
+    AddPaymentConfig {
+        /// Payment mint ("sol" for SOL)
+        #[arg(long, value_parser = parse_mint)]
+        mint: Pubkey,
+        /// Price in lamports (SOL) or smallest token units (SPL)
+        #[arg(long)]
+        price: u64,
+        /// Recipient of withdrawals (defaults to the keypair, or its token account for SPL)
+        #[arg(long)]
+        recipient: Option<Pubkey>,
+    },
+    /// Add encrypted keys from a file (one key per line), skipping keys already added
+    AddKeys {
+        /// File holding the encrypted keys
+        #[arg(long)]
+        file: PathBuf,
+        /// Rarity tier of the keys
+        #[arg(long, default_value_t = 0)]
+        tier: u8,
+    },
+    /// Finalize the machine to enable pulls
+    Finalize {
+        /// Hex-encoded server seed hash (commit-reveal machines only)
+        #[arg(long, value_parser = parse_hash)]
+        server_seed_hash: Option<[u8; 32]>,
+    },
+    /// Pause pulls
+    Pause,
+    /// Unpause pulls
+    Unpause,
+    /// Halt settlements
+    Halt,
+    /// Unhalt settlements
+    Unhalt,
+    /// Propose a new admin (the new admin then runs `accept-admin`)
+    TransferAdmin {
+        /// Proposed admin
+        #[arg(long)]
+        new_admin: Pubkey,
+    },
+    /// Accept a pending admin transfer with the configured keypair
+    AcceptAdmin,
+    /// Publish the key decrypting the machine's encrypted keys
+    ReleaseKey {
+        /// The decryption key
+        #[arg(long)]
+        decryption_key: String,
+    },
+    /// Print the machine state and its payment configs
+    Inspect,
+    /// List the pulls and multi-pulls of the machine
+    ListPulls {
+        /// Only list the pulls of this user
+        #[arg(long)]
+        user: Option<Pubkey>,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let client = Client::new(&CliConfig::load(&cli.config)?)?;
+
+    match cli.command {
+        Command::Init => client.init(),
+        Command::AddPaymentConfig {
+            mint,
+            price,
+            recipient,
+        } => client.add_payment_config(mint, price, recipient),
+        Command::AddKeys { file, tier } => client.add_keys(&file, tier),
+        Command::Finalize { server_seed_hash } => client.finalize(server_seed_hash),
+        Command::Pause => client.set_paused(true),
+        Command::Unpause => client.set_paused(false),
+        Command::Halt => client.set_halted(true),
+        Command::Unhalt => client.set_halted(false),
+        Command::TransferAdmin { new_admin } => client.transfer_admin(new_admin),
+        Command::AcceptAdmin => client.accept_admin(),
+        Command::ReleaseKey { decryption_key } => client.release_key(decryption_key),
+        Command::Inspect => client.inspect(),
+        Command::ListPulls { user } => client.list_pulls(user),
+    }
+}
+
+/// Parses a payment mint, accepting "sol" for SOL
+fn parse_mint(value: &str) -> Result<Pubkey> {
+    if value.eq_ignore_ascii_case("sol") {
+        return Ok(system_program::ID);
+    }
+    value
+        .parse()
+        .map_err(|err| anyhow!("invalid mint {value}: {err}"))
+}
+
+/// Parses a hex-encoded 32-byte hash
+fn parse_hash(value: &str) -> Result<[u8; 32]> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    if value.len() != 64 || !value.is_ascii() {
+        return Err(anyhow!("expected 64 hex characters"));
+    }
+    let mut hash = [0u8; 32];
+    for (byte, chunk) in hash.iter_mut().zip(value.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(chunk)?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|err| anyhow!("invalid hex: {err}"))?;
+    }
+    Ok(hash)
+}
@@ -0,0 +1,241 @@
+use anchor_lang::{AccountDeserialize, Result};
+use gacha_machine::{
+    GachaState, KeyPage, MultiPullState, PaymentConfig, PlayerState, RandomnessUsage, UserStats,
+};
+
+// ========================================
+// Account Decoders
+// ========================================
+//
+// Each decoder takes the raw account data (discriminator included) and fails if
+// the discriminator does not match the expected account type.
+
+/// Decodes a `GachaState` account
+pub fn gacha_state(data: &[u8]) -> Result<GachaState> {
+    GachaState::try_deserialize(&mut &data[..])
+}
+
+/// Decodes a `PaymentConfig` account
+pub fn payment_config(data: &[u8]) -> Result<PaymentConfig> {
+    PaymentConfig::try_deserialize(&mut &data[..])
+}
+
+/// Decodes a `PlayerState` account
+pub fn player_state(data: &[u8]) -> Result<PlayerState> {
+    PlayerState::try_deserialize(&mut &data[..])
+}
+
+/// Decodes a `MultiPullState` account
+pub fn multi_pull_state(data: &[u8]) -> Result<MultiPullState> {
+    MultiPullState::try_deserialize(&mut &data[..])
+}
+
+/// Decodes a `UserStats` account
+pub fn user_stats(data: &[u8]) -> Result<UserStats> {
+    UserStats::try_deserialize(&mut &data[..])
+}
+
+/// Decodes a `KeyPage` account
+pub fn key_page(data: &[u8]) -> Result<KeyPage> {
+    KeyPage::try_deserialize(&mut &data[..])
+}
+
+/// Decodes a `RandomnessUsage` account
+pub fn randomness_usage(data: &[u8]) -> Result<RandomnessUsage> {
+    RandomnessUsage::try_deserialize(&mut &data[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::prelude::Pubkey;
+    use anchor_lang::AccountSerialize;
+    use gacha_machine::{
+        KeyStorage, PullCommitment, RandomnessSource, RarityTier, Role, RoleGrant, UsdPricing,
+    };
+
+    fn serialize<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn decoders_round_trip_serialized_accounts() {
+        let gacha_state = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+
+        let stats = user_stats(&serialize(&UserStats {
+            user,
+            gacha_state,
+            pulls_since_top_tier: 4,
+            total_settled: 9,
+            bump: 253,
+        }))
+        .unwrap();
+        assert_eq!(stats.user, user);
+        assert_eq!(stats.gacha_state, gacha_state);
+        assert_eq!(stats.pulls_since_top_tier, 4);
+        assert_eq!(stats.total_settled, 9);
+        assert_eq!(stats.bump, 253);
+
+        let randomness_account = Pubkey::new_unique();
+        let usage = randomness_usage(&serialize(&RandomnessUsage {
+            randomness_account,
+            seed_slot: 1234,
+            bump: 254,
+        }))
+        .unwrap();
+        assert_eq!(usage.randomness_account, randomness_account);
+        assert_eq!(usage.seed_slot, 1234);
+        assert_eq!(usage.bump, 254);
+
+        let page = key_page(&serialize(&KeyPage {
+            gacha_state,
+            page_index: 3,
+            bump: 252,
+            keys: vec!["key_a".to_string(), "key_b".to_string()],
+        }))
+        .unwrap();
+        assert_eq!(page.gacha_state, gacha_state);
+        assert_eq!(page.page_index, 3);
+        assert_eq!(page.keys, ["key_a", "key_b"]);
+    }
+
+    #[test]
+    fn decoders_round_trip_machine_and_pull_accounts() {
+        let gacha_state_key = Pubkey::new_unique();
+        let admin = Pubkey::new_unique();
+        let payment_config_key = Pubkey::new_unique();
+        let treasurer = Pubkey::new_unique();
+        let tiers = vec![
+            RarityTier {
+                weight: 90,
+                key_count: 3,
+                remaining_indices: vec![0, 2],
+            },
+            RarityTier {
+                weight: 10,
+                key_count: 1,
+                remaining_indices: vec![3],
+            },
+        ];
+        let roles = vec![
+            RoleGrant {
+                account: treasurer,
+                role: Role::Treasurer,
+            },
+            RoleGrant {
+                account: admin,
+                role: Role::Pauser,
+            },
+        ];
+        // Vectors of variable length precede the last fields, so every field must line up
+        let data = serialize(&GachaState {
+            admin,
+            pending_admin: Some(treasurer),
+            creator: admin,
+            machine_id: 7,
+            bump: 251,
+            is_finalized: true,
+            is_paused: false,
+            is_halted: true,
+            pull_count: 12,
+            settle_count: 10,
+            refund_count: 1,
+            claim_count: 9,
+            refund_timeout_slots: 150,
+            pity_threshold: 5,
+            randomness_source: RandomnessSource::CommitReveal,
+            seed_commitment: [4; 32],
+            seed_epoch: 2,
+            crank_tip_bps: 50,
+            instant_max_prize_value: 1_000_000,
+            key_storage: KeyStorage::Merkle,
+            key_root: [6; 32],
+            total_keys: 4,
+            key_page_count: 0,
+            decryption_key: "decryption_key".to_string(),
+            tiers: tiers.clone(),
+            payment_configs: vec![payment_config_key, Pubkey::new_unique()],
+            roles: roles.clone(),
+        });
+        let machine = gacha_state(&data).unwrap();
+        assert_eq!(serialize(&machine), data);
+        assert_eq!(machine.admin, admin);
+        assert_eq!(machine.pending_admin, Some(treasurer));
+        assert!(machine.randomness_source == RandomnessSource::CommitReveal);
+        assert!(machine.key_storage == KeyStorage::Merkle);
+        assert_eq!(machine.decryption_key, "decryption_key");
+        assert!(machine.tiers == tiers);
+        assert_eq!(machine.payment_configs[0], payment_config_key);
+        assert!(machine.roles == roles);
+
+        let data = serialize(&PaymentConfig {
+            gacha_state: gacha_state_key,
+            mint: Pubkey::new_unique(),
+            price: 1_000,
+            admin_recipient_account: treasurer,
+            bump: 250,
+            escrowed_amount: 300,
+            settled_amount: 200,
+            withdrawn_amount: 100,
+            bulk_discount_bps: 500,
+            usd_pricing: Some(UsdPricing {
+                usd_price: 2_500_000,
+                price_feed: Pubkey::new_unique(),
+                max_staleness_slots: 25,
+                max_deviation_bps: 100,
+            }),
+        });
+        let config = payment_config(&data).unwrap();
+        assert_eq!(serialize(&config), data);
+        assert_eq!(config.gacha_state, gacha_state_key);
+        assert_eq!(config.admin_recipient_account, treasurer);
+        assert_eq!(config.withdrawable_amount(), 100);
+        assert_eq!(config.usd_pricing.unwrap().usd_price, 2_500_000);
+
+        let user = Pubkey::new_unique();
+        let data = serialize(&PlayerState {
+            user,
+            gacha_state: gacha_state_key,
+            randomness_account: Pubkey::new_unique(),
+            payment_mint: Pubkey::new_unique(),
+            is_settled: true,
+            is_refunded: false,
+            price_paid: 1_000,
+            is_claimed: true,
+            result_index: 3,
+            result_tier: 1,
+            winning_encrypted_key: "key_d".to_string(),
+            bump: 249,
+            pull_slot: 4_321,
+            nonce: 11,
+            commitment: PullCommitment {
+                seed_slot: 0,
+                seed_epoch: 1,
+                client_seed: [8; 32],
+                slot_hash: [9; 32],
+            },
+        });
+        let pull = player_state(&data).unwrap();
+        assert_eq!(serialize(&pull), data);
+        assert_eq!(pull.user, user);
+        assert_eq!(pull.result_index, 3);
+        assert_eq!(pull.winning_encrypted_key, "key_d");
+        assert_eq!(pull.nonce, 11);
+        assert!(pull.commitment.is_sealed());
+    }
+
+    #[test]
+    fn decoders_reject_other_account_types() {
+        let data = serialize(&RandomnessUsage {
+            randomness_account: Pubkey::new_unique(),
+            seed_slot: 1,
+            bump: 255,
+        });
+        assert!(user_stats(&data).is_err());
+        assert!(player_state(&data).is_err());
+        assert!(randomness_usage(&data[..8]).is_err());
+    }
+}
@@ -0,0 +1,864 @@
+use anchor_lang::prelude::{AccountMeta, Pubkey};
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::sysvar;
+use anchor_lang::system_program;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use gacha_machine::{accounts, instruction as ix, RandomnessSource, Role, UsdPricing};
+
+use crate::pda::*;
+
+// ========================================
+// Instruction Builders
+// ========================================
+//
+// One builder per program instruction. Builders take the machine's `gacha_state`
+// address and the signers, derive every PDA, and fill optional accounts (such as
+// `token_program` for SPL payments) from the given `PaymentAccounts`. Pull PDAs
+// are derived from the pull's `nonce`, the machine's `pull_count` when it was made.
+
+/// Builds an instruction from Anchor accounts and instruction data
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: gacha_machine::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+fn admin_action(gacha_state: Pubkey, admin: Pubkey) -> accounts::AdminAction {
+    accounts::AdminAction { gacha_state, admin }
+}
+
+// ============ MACHINE SETUP ============
+
+/// Creates the machine `machine_id` of `admin`
+pub fn initialize(admin: Pubkey, machine_id: u64) -> Instruction {
+    let (gacha_state, _) = find_gacha_state(&admin, machine_id);
+    build(
+        accounts::Initialize {
+            gacha_state,
+            admin,
+            system_program: system_program::ID,
+        },
+        ix::Initialize { machine_id },
+    )
+}
+
+/// Adds a payment config; `payment_recipient_account` is the recipient wallet for
+/// SOL or a token account of `payment_mint` for SPL
+pub fn add_payment_config(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    payment_mint: Pubkey,
+    payment_price: u64,
+    payment_recipient_account: Pubkey,
+) -> Instruction {
+    let (payment_config, _) = find_payment_config(&gacha_state, &payment_mint);
+    build(
+        accounts::AddPaymentConfig {
+            payment_config,
+            gacha_state,
+            authority,
+            payment_mint_account: payment_mint,
+            recipient_account: payment_recipient_account,
+            system_program: system_program::ID,
+        },
+        ix::AddPaymentConfig {
+            payment_mint,
+            payment_price,
+            payment_recipient_account,
+        },
+    )
+}
+
+/// Creates the associated token account holding the SPL payments of a payment
+/// config if it does not exist yet; `None` for SOL, held on the config itself
+pub fn create_payment_vault(payer: Pubkey, payment: &PaymentAccounts) -> Option<Instruction> {
+    let token_program = payment.token_program?;
+    Some(create_associated_token_account_idempotent(
+        &payer,
+        &payment.payment_config,
+        &payment.mint,
+        &token_program,
+    ))
+}
+
+/// Removes a payment config, closing the vault of SPL configs
+pub fn remove_payment_config(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    payment: &PaymentAccounts,
+) -> Instruction {
+    let spl = payment.token_program.is_some();
+    build(
+        accounts::RemovePaymentConfig {
+            payment_config: payment.payment_config,
+            gacha_state,
+            authority,
+            system_program: system_program::ID,
+            payment_vault: spl.then_some(payment.vault),
+            payment_mint_account: spl.then_some(payment.mint),
+            token_program: payment.token_program,
+        },
+        ix::RemovePaymentConfig {
+            payment_mint: payment.mint,
+        },
+    )
+}
+
+/// Updates the price and/or recipient of a payment config
+pub fn update_payment_config(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    payment_mint: Pubkey,
+    payment_price: Option<u64>,
+    new_recipient_account: Option<Pubkey>,
+) -> Instruction {
+    let (payment_config, _) = find_payment_config(&gacha_state, &payment_mint);
+    build(
+        accounts::UpdatePaymentConfig {
+            payment_config,
+            gacha_state,
+            authority,
+            new_recipient_account,
+        },
+        ix::UpdatePaymentConfig {
+            payment_mint,
+            payment_price,
+        },
+    )
+}
+
+/// Sets (or clears) the USD pricing of a payment config
+pub fn set_usd_pricing(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    payment_mint: Pubkey,
+    usd_pricing: Option<UsdPricing>,
+) -> Instruction {
+    let (payment_config, _) = find_payment_config(&gacha_state, &payment_mint);
+    build(
+        accounts::SetUsdPricing {
+            payment_config,
+            gacha_state,
+            authority,
+            price_feed: usd_pricing.map(|usd_pricing| usd_pricing.price_feed),
+        },
+        ix::SetUsdPricing {
+            payment_mint,
+            usd_pricing,
+        },
+    )
+}
+
+/// Sets the multi-pull discount of a payment config
+pub fn set_bulk_discount(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    payment_mint: Pubkey,
+    bulk_discount_bps: u16,
+) -> Instruction {
+    let (payment_config, _) = find_payment_config(&gacha_state, &payment_mint);
+    build(
+        accounts::SetBulkDiscount {
+            payment_config,
+            gacha_state,
+            authority,
+        },
+        ix::SetBulkDiscount {
+            payment_mint,
+            bulk_discount_bps,
+        },
+    )
+}
+
+/// Withdraws settled revenue to the payment config's recipient
+pub fn withdraw(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    payment: &PaymentAccounts,
+    admin_recipient_account: Pubkey,
+    amount: u64,
+) -> Instruction {
+    build(
+        accounts::Withdraw {
+            payment_config: payment.payment_config,
+            gacha_state,
+            authority,
+            payment_mint_account: payment.mint,
+            payment_vault: payment.vault,
+            admin_recipient_account,
+            token_program: payment.token_program,
+        },
+        ix::Withdraw {
+            payment_mint: payment.mint,
+            amount,
+        },
+    )
+}
+
+// ============ KEY MANAGEMENT ============
+
+/// Creates the next key page; `page_index` is the machine's `key_page_count`
+pub fn add_key_page(gacha_state: Pubkey, authority: Pubkey, page_index: u16) -> Instruction {
+    let (key_page, _) = find_key_page(&gacha_state, page_index);
+    build(
+        accounts::AddKeyPage {
+            key_page,
+            gacha_state,
+            authority,
+            system_program: system_program::ID,
+        },
+        ix::AddKeyPage {},
+    )
+}
+
+/// Adds one encrypted key to a tier, stored in key page `page_index`
+pub fn add_key(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    page_index: u16,
+    encrypted_key: String,
+    tier: u8,
+) -> Instruction {
+    let (key_page, _) = find_key_page(&gacha_state, page_index);
+    build(
+        accounts::AddKey {
+            key_page,
+            gacha_state,
+            authority,
+            system_program: system_program::ID,
+        },
+        ix::AddKey {
+            encrypted_key,
+            tier,
+        },
+    )
+}
+
+/// Adds a batch of encrypted keys to a tier, stored in key page `page_index`
+pub fn add_keys(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    page_index: u16,
+    encrypted_keys: Vec<String>,
+    tier: u8,
+) -> Instruction {
+    let (key_page, _) = find_key_page(&gacha_state, page_index);
+    build(
+        accounts::AddKeys {
+            key_page,
+            gacha_state,
+            authority,
+            system_program: system_program::ID,
+        },
+        ix::AddKeys {
+            encrypted_keys,
+            tier,
+        },
+    )
+}
+
+/// Sets the drop weight of each rarity tier
+pub fn set_tier_weights(gacha_state: Pubkey, authority: Pubkey, weights: Vec<u32>) -> Instruction {
+    build(
+        accounts::SetTierWeights {
+            gacha_state,
+            authority,
+            system_program: system_program::ID,
+        },
+        ix::SetTierWeights { weights },
+    )
+}
+
+/// Finalizes a paged machine (commit-reveal machines pass their server seed hash)
+pub fn finalize(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    server_seed_hash: Option<[u8; 32]>,
+) -> Instruction {
+    build(
+        accounts::Finalize {
+            gacha_state,
+            authority,
+        },
+        ix::Finalize { server_seed_hash },
+    )
+}
+
+/// Finalizes a Merkle machine with its key root and per-tier key counts
+pub fn finalize_with_root(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    key_root: [u8; 32],
+    total_keys: u16,
+    tier_key_counts: Vec<u16>,
+    server_seed_hash: Option<[u8; 32]>,
+) -> Instruction {
+    build(
+        accounts::FinalizeWithRoot {
+            gacha_state,
+            authority,
+            system_program: system_program::ID,
+        },
+        ix::FinalizeWithRoot {
+            key_root,
+            total_keys,
+            tier_key_counts,
+            server_seed_hash,
+        },
+    )
+}
+
+/// Reveals the next server seed of a commit-reveal machine
+pub fn reveal_server_seed(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    server_seed: [u8; 32],
+) -> Instruction {
+    build(
+        accounts::RevealServerSeed {
+            gacha_state,
+            authority,
+        },
+        ix::RevealServerSeed { server_seed },
+    )
+}
+
+/// Closes an empty key page (after the sale), returning its rent to the admin
+pub fn close_key_page(gacha_state: Pubkey, admin: Pubkey, page_index: u16) -> Instruction {
+    let (key_page, _) = find_key_page(&gacha_state, page_index);
+    build(
+        accounts::CloseKeyPage {
+            key_page,
+            gacha_state,
+            admin,
+        },
+        ix::CloseKeyPage {},
+    )
+}
+
+/// Closes a finished machine; `payment_methods` must follow `gacha_state.payment_configs`
+///
+/// SPL vaults are closed along with their payment configs.
+pub fn close_machine(
+    gacha_state: Pubkey,
+    admin: Pubkey,
+    payment_methods: &[PaymentAccounts],
+) -> Instruction {
+    let mut instruction = build(
+        accounts::CloseMachine { gacha_state, admin },
+        ix::CloseMachine {},
+    );
+    for payment in payment_methods {
+        instruction
+            .accounts
+            .push(AccountMeta::new(payment.payment_config, false));
+        if let Some(token_program) = payment.token_program {
+            instruction.accounts.extend([
+                AccountMeta::new(payment.vault, false),
+                AccountMeta::new(payment.mint, false),
+                AccountMeta::new_readonly(token_program, false),
+            ]);
+        }
+    }
+    instruction
+}
+
+// ============ MACHINE CONTROLS ============
+
+/// Pauses or unpauses pulls
+pub fn set_paused(gacha_state: Pubkey, authority: Pubkey, paused: bool) -> Instruction {
+    build(
+        accounts::PauserAction {
+            gacha_state,
+            authority,
+        },
+        ix::SetPaused { paused },
+    )
+}
+
+/// Halts or unhalts settlements
+pub fn set_halted(gacha_state: Pubkey, authority: Pubkey, halted: bool) -> Instruction {
+    build(
+        accounts::PauserAction {
+            gacha_state,
+            authority,
+        },
+        ix::SetHalted { halted },
+    )
+}
+
+/// Grants `role` to `account`
+pub fn grant_role(gacha_state: Pubkey, admin: Pubkey, account: Pubkey, role: Role) -> Instruction {
+    build(
+        accounts::GrantRole {
+            gacha_state,
+            admin,
+            system_program: system_program::ID,
+        },
+        ix::GrantRole { account, role },
+    )
+}
+
+/// Revokes `role` from `account`
+pub fn revoke_role(gacha_state: Pubkey, admin: Pubkey, account: Pubkey, role: Role) -> Instruction {
+    build(
+        accounts::RevokeRole {
+            gacha_state,
+            admin,
+            system_program: system_program::ID,
+        },
+        ix::RevokeRole { account, role },
+    )
+}
+
+/// Sets the slots after which users may refund their unsettled pulls (0 disables)
+pub fn set_refund_timeout(
+    gacha_state: Pubkey,
+    admin: Pubkey,
+    refund_timeout_slots: u64,
+) -> Instruction {
+    build(
+        admin_action(gacha_state, admin),
+        ix::SetRefundTimeout {
+            refund_timeout_slots,
+        },
+    )
+}
+
+/// Sets the randomness source (before finalization)
+pub fn set_randomness_source(
+    gacha_state: Pubkey,
+    admin: Pubkey,
+    randomness_source: RandomnessSource,
+) -> Instruction {
+    build(
+        admin_action(gacha_state, admin),
+        ix::SetRandomnessSource { randomness_source },
+    )
+}
+
+/// Sets the pity threshold (0 disables pity)
+pub fn set_pity_threshold(gacha_state: Pubkey, admin: Pubkey, pity_threshold: u32) -> Instruction {
+    build(
+        admin_action(gacha_state, admin),
+        ix::SetPityThreshold { pity_threshold },
+    )
+}
+
+/// Sets the crank tip paid to third parties settling pulls (0 disables)
+pub fn set_crank_tip(gacha_state: Pubkey, admin: Pubkey, crank_tip_bps: u16) -> Instruction {
+    build(
+        admin_action(gacha_state, admin),
+        ix::SetCrankTip { crank_tip_bps },
+    )
+}
+
+/// Enables instant pulls for a max prize value in micro-USD (0 disables)
+pub fn set_instant_mode(gacha_state: Pubkey, admin: Pubkey, max_prize_value: u64) -> Instruction {
+    build(
+        admin_action(gacha_state, admin),
+        ix::SetInstantMode { max_prize_value },
+    )
+}
+
+/// Proposes `new_admin` as admin (first step of the transfer)
+pub fn propose_admin(gacha_state: Pubkey, admin: Pubkey, new_admin: Pubkey) -> Instruction {
+    build(
+        admin_action(gacha_state, admin),
+        ix::ProposeAdmin { new_admin },
+    )
+}
+
+/// Accepts a pending admin transfer (signed by the proposed admin)
+pub fn accept_admin(gacha_state: Pubkey, new_admin: Pubkey) -> Instruction {
+    build(
+        accounts::AcceptAdmin {
+            gacha_state,
+            new_admin,
+        },
+        ix::AcceptAdmin {},
+    )
+}
+
+/// Cancels a pending admin transfer
+pub fn cancel_admin_transfer(gacha_state: Pubkey, admin: Pubkey) -> Instruction {
+    build(admin_action(gacha_state, admin), ix::CancelAdminTransfer {})
+}
+
+/// Publishes the key decrypting the machine's encrypted keys
+pub fn release_decryption_key(
+    gacha_state: Pubkey,
+    admin: Pubkey,
+    decryption_key: String,
+) -> Instruction {
+    build(
+        admin_action(gacha_state, admin),
+        ix::ReleaseDecryptionKey { decryption_key },
+    )
+}
+
+// ============ PULLS ============
+
+/// Usage marker passed to pulls: only Switchboard machines track their randomness
+/// accounts, so other sources create no marker
+fn randomness_usage(source: RandomnessSource, randomness_account: &Pubkey) -> Option<Pubkey> {
+    (source == RandomnessSource::Switchboard).then(|| find_randomness_usage(randomness_account).0)
+}
+
+/// Pulls once, bound to `randomness_account`; `nonce` is the machine's current
+/// `pull_count`, `randomness_source` the machine's source and `price_feed` is
+/// required on USD-priced payment configs
+#[allow(clippy::too_many_arguments)]
+pub fn pull(
+    gacha_state: Pubkey,
+    nonce: u64,
+    user: Pubkey,
+    payment: &PaymentAccounts,
+    randomness_source: RandomnessSource,
+    randomness_account: Pubkey,
+    price_feed: Option<Pubkey>,
+    max_price: u64,
+    client_seed: [u8; 32],
+) -> Instruction {
+    build(
+        accounts::Pull {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            user_stats: find_user_stats(&gacha_state, &user).0,
+            gacha_state,
+            payment_config: payment.payment_config,
+            user,
+            payment_mint: payment.mint,
+            user_payment_account: payment.owner_account(&user),
+            payment_vault: payment.vault,
+            randomness_account_data: randomness_account,
+            randomness_usage: randomness_usage(randomness_source, &randomness_account),
+            price_feed,
+            system_program: system_program::ID,
+            token_program: payment.token_program,
+        },
+        ix::Pull {
+            max_price,
+            client_seed,
+        },
+    )
+}
+
+/// Pulls and settles at once on instant-mode machines (must be the last
+/// instruction of its transaction)
+pub fn pull_instant(
+    gacha_state: Pubkey,
+    nonce: u64,
+    user: Pubkey,
+    payment: &PaymentAccounts,
+    price_feed: Option<Pubkey>,
+    max_price: u64,
+) -> Instruction {
+    build(
+        accounts::PullInstant {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            user_stats: find_user_stats(&gacha_state, &user).0,
+            gacha_state,
+            payment_config: payment.payment_config,
+            user,
+            payment_mint: payment.mint,
+            user_payment_account: payment.owner_account(&user),
+            payment_vault: payment.vault,
+            slot_hashes: sysvar::slot_hashes::ID,
+            instructions_sysvar: sysvar::instructions::ID,
+            price_feed,
+            system_program: system_program::ID,
+            token_program: payment.token_program,
+        },
+        ix::PullInstant { max_price },
+    )
+}
+
+/// Seals a commit-reveal pull with the hash of the first slot after it
+pub fn seal_pull(gacha_state: Pubkey, user: Pubkey, nonce: u64) -> Instruction {
+    build(
+        accounts::SealPull {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            slot_hashes: sysvar::slot_hashes::ID,
+        },
+        ix::SealPull {},
+    )
+}
+
+/// Settles a pull; a `caller` other than the user collects the crank tip into
+/// its own payment account when `collect_tip` is set
+#[allow(clippy::too_many_arguments)]
+pub fn settle(
+    gacha_state: Pubkey,
+    user: Pubkey,
+    nonce: u64,
+    payment: &PaymentAccounts,
+    randomness_account: Pubkey,
+    caller: Pubkey,
+    collect_tip: bool,
+) -> Instruction {
+    build(
+        accounts::Settle {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            payment_config: payment.payment_config,
+            user_stats: find_user_stats(&gacha_state, &user).0,
+            user,
+            caller,
+            randomness_account_data: randomness_account,
+            slot_hashes: Some(sysvar::slot_hashes::ID),
+            payment_mint: collect_tip.then_some(payment.mint),
+            payment_vault: collect_tip.then_some(payment.vault),
+            caller_payment_account: collect_tip.then(|| payment.owner_account(&caller)),
+            token_program: payment.token_program.filter(|_| collect_tip),
+        },
+        ix::Settle {},
+    )
+}
+
+/// Refunds an unsettled pull to its user (signed by the user or the admin);
+/// `randomness_account` is the one the pull was bound to
+pub fn refund(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    user: Pubkey,
+    nonce: u64,
+    payment: &PaymentAccounts,
+    randomness_account: Pubkey,
+) -> Instruction {
+    build(
+        accounts::Refund {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            payment_config: payment.payment_config,
+            authority,
+            user,
+            payment_mint: payment.mint,
+            user_payment_account: payment.owner_account(&user),
+            payment_vault: payment.vault,
+            randomness_account_data: randomness_account,
+            token_program: payment.token_program,
+        },
+        ix::Refund {},
+    )
+}
+
+/// Copies the winning key of a settled pull from the key page holding `result_index`
+pub fn claim_key(gacha_state: Pubkey, user: Pubkey, nonce: u64, result_index: u16) -> Instruction {
+    build(
+        accounts::ClaimKey {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            key_page: find_key_page_of_key(&gacha_state, result_index).0,
+        },
+        ix::ClaimKey {},
+    )
+}
+
+/// Claims the winning key of a settled pull on a Merkle machine
+pub fn claim_key_with_proof(
+    gacha_state: Pubkey,
+    user: Pubkey,
+    nonce: u64,
+    encrypted_key: String,
+    proof: Vec<[u8; 32]>,
+) -> Instruction {
+    build(
+        accounts::ClaimKeyWithProof {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+        },
+        ix::ClaimKeyWithProof {
+            encrypted_key,
+            proof,
+        },
+    )
+}
+
+/// Closes a finished pull, returning its rent to the user
+pub fn close_player_state(gacha_state: Pubkey, user: Pubkey, nonce: u64) -> Instruction {
+    build(
+        accounts::ClosePlayerState {
+            player_state: find_player_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            user,
+        },
+        ix::ClosePlayerState {},
+    )
+}
+
+// ============ MULTI-PULLS ============
+
+/// Buys `count` pulls at once; `nonce` is the machine's current `pull_count`
+/// (see `pull` for the other accounts)
+#[allow(clippy::too_many_arguments)]
+pub fn pull_many(
+    gacha_state: Pubkey,
+    nonce: u64,
+    user: Pubkey,
+    payment: &PaymentAccounts,
+    randomness_source: RandomnessSource,
+    randomness_account: Pubkey,
+    price_feed: Option<Pubkey>,
+    count: u8,
+    max_price: u64,
+    client_seed: [u8; 32],
+) -> Instruction {
+    build(
+        accounts::PullMany {
+            multi_pull_state: find_multi_pull_state(&gacha_state, &user, nonce).0,
+            user_stats: find_user_stats(&gacha_state, &user).0,
+            gacha_state,
+            payment_config: payment.payment_config,
+            user,
+            payment_mint: payment.mint,
+            user_payment_account: payment.owner_account(&user),
+            payment_vault: payment.vault,
+            randomness_account_data: randomness_account,
+            randomness_usage: randomness_usage(randomness_source, &randomness_account),
+            price_feed,
+            system_program: system_program::ID,
+            token_program: payment.token_program,
+        },
+        ix::PullMany {
+            count,
+            max_price,
+            client_seed,
+        },
+    )
+}
+
+/// Seals a commit-reveal multi-pull with the hash of the first slot after it
+pub fn seal_multi_pull(gacha_state: Pubkey, user: Pubkey, nonce: u64) -> Instruction {
+    build(
+        accounts::SealMultiPull {
+            multi_pull_state: find_multi_pull_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            slot_hashes: sysvar::slot_hashes::ID,
+        },
+        ix::SealMultiPull {},
+    )
+}
+
+/// Settles a multi-pull; see `settle` for the crank tip
+#[allow(clippy::too_many_arguments)]
+pub fn settle_many(
+    gacha_state: Pubkey,
+    user: Pubkey,
+    nonce: u64,
+    payment: &PaymentAccounts,
+    randomness_account: Pubkey,
+    caller: Pubkey,
+    collect_tip: bool,
+) -> Instruction {
+    build(
+        accounts::SettleMany {
+            multi_pull_state: find_multi_pull_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            payment_config: payment.payment_config,
+            user_stats: find_user_stats(&gacha_state, &user).0,
+            user,
+            caller,
+            randomness_account_data: randomness_account,
+            slot_hashes: Some(sysvar::slot_hashes::ID),
+            payment_mint: collect_tip.then_some(payment.mint),
+            payment_vault: collect_tip.then_some(payment.vault),
+            caller_payment_account: collect_tip.then(|| payment.owner_account(&caller)),
+            token_program: payment.token_program.filter(|_| collect_tip),
+        },
+        ix::SettleMany {},
+    )
+}
+
+/// Refunds an unsettled multi-pull to its user (signed by the user or the admin);
+/// `randomness_account` is the one the multi-pull was bound to
+pub fn refund_many(
+    gacha_state: Pubkey,
+    authority: Pubkey,
+    user: Pubkey,
+    nonce: u64,
+    payment: &PaymentAccounts,
+    randomness_account: Pubkey,
+) -> Instruction {
+    build(
+        accounts::RefundMany {
+            multi_pull_state: find_multi_pull_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            payment_config: payment.payment_config,
+            authority,
+            user,
+            payment_mint: payment.mint,
+            user_payment_account: payment.owner_account(&user),
+            payment_vault: payment.vault,
+            randomness_account_data: randomness_account,
+            token_program: payment.token_program,
+        },
+        ix::RefundMany {},
+    )
+}
+
+/// Copies the winning keys of a settled multi-pull from the key pages holding
+/// `result_indices`
+pub fn claim_many_keys(
+    gacha_state: Pubkey,
+    user: Pubkey,
+    nonce: u64,
+    result_indices: &[u16],
+) -> Instruction {
+    let mut instruction = build(
+        accounts::ClaimManyKeys {
+            multi_pull_state: find_multi_pull_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+        },
+        ix::ClaimManyKeys {},
+    );
+    let mut key_pages: Vec<Pubkey> = result_indices
+        .iter()
+        .map(|&key_index| find_key_page_of_key(&gacha_state, key_index).0)
+        .collect();
+    key_pages.sort();
+    key_pages.dedup();
+    instruction.accounts.extend(
+        key_pages
+            .into_iter()
+            .map(|key_page| AccountMeta::new_readonly(key_page, false)),
+    );
+    instruction
+}
+
+/// Claims the winning key at `position` of a settled multi-pull on a Merkle machine
+pub fn claim_many_key_with_proof(
+    gacha_state: Pubkey,
+    user: Pubkey,
+    nonce: u64,
+    position: u8,
+    encrypted_key: String,
+    proof: Vec<[u8; 32]>,
+) -> Instruction {
+    build(
+        accounts::ClaimManyKeyWithProof {
+            multi_pull_state: find_multi_pull_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+        },
+        ix::ClaimManyKeyWithProof {
+            position,
+            encrypted_key,
+            proof,
+        },
+    )
+}
+
+/// Closes a finished multi-pull, returning its rent to the user
+pub fn close_multi_pull_state(gacha_state: Pubkey, user: Pubkey, nonce: u64) -> Instruction {
+    build(
+        accounts::CloseMultiPullState {
+            multi_pull_state: find_multi_pull_state(&gacha_state, &user, nonce).0,
+            gacha_state,
+            user,
+        },
+        ix::CloseMultiPullState {},
+    )
+}
@@ -0,0 +1,19 @@
+// Gacha Machine Client
+//
+// Rust client for the gacha machine program:
+// - PDA derivation of every program account (`pda`)
+// - Instruction builders for every program instruction (`instructions`)
+// - Decoders of the program accounts (`decode`)
+//
+// Instructions are built from the program's own Anchor account and instruction
+// types, so account lists always match the deployed program's interface.
+
+pub mod decode;
+pub mod instructions;
+pub mod pda;
+
+pub use gacha_machine::{
+    constants, GachaState, KeyPage, KeyStorage, MultiPullState, PaymentConfig, PlayerState,
+    RandomnessSource, RandomnessUsage, RarityTier, Role, UsdPricing, UserStats, ID,
+};
+pub use pda::PaymentAccounts;
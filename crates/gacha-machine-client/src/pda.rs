@@ -0,0 +1,249 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::system_program;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use gacha_machine::constants::*;
+
+// ========================================
+// Program Derived Addresses
+// ========================================
+
+/// Gacha machine state of `creator` with the given machine id
+pub fn find_gacha_state(creator: &Pubkey, machine_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[GACHA_STATE, creator.as_ref(), &machine_id.to_le_bytes()],
+        &gacha_machine::ID,
+    )
+}
+
+/// Payment config of a machine for a payment mint (the system program id for SOL)
+pub fn find_payment_config(gacha_state: &Pubkey, payment_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PAYMENT_CONFIG, gacha_state.as_ref(), payment_mint.as_ref()],
+        &gacha_machine::ID,
+    )
+}
+
+/// Player state of a single pull; `nonce` is the machine's pull count at pull time
+pub fn find_player_state(gacha_state: &Pubkey, user: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PLAYER_STATE,
+            gacha_state.as_ref(),
+            user.as_ref(),
+            &nonce.to_le_bytes(),
+        ],
+        &gacha_machine::ID,
+    )
+}
+
+/// Multi-pull state; `nonce` is the machine's pull count at pull time
+pub fn find_multi_pull_state(gacha_state: &Pubkey, user: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            MULTI_PULL_STATE,
+            gacha_state.as_ref(),
+            user.as_ref(),
+            &nonce.to_le_bytes(),
+        ],
+        &gacha_machine::ID,
+    )
+}
+
+/// Statistics (pity counter) of a user on a machine
+pub fn find_user_stats(gacha_state: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[USER_STATS, gacha_state.as_ref(), user.as_ref()],
+        &gacha_machine::ID,
+    )
+}
+
+/// Key page holding keys `page_index * KEYS_PER_PAGE..(page_index + 1) * KEYS_PER_PAGE`
+pub fn find_key_page(gacha_state: &Pubkey, page_index: u16) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[KEY_PAGE, gacha_state.as_ref(), &page_index.to_le_bytes()],
+        &gacha_machine::ID,
+    )
+}
+
+/// Key page holding the key at `key_index`
+pub fn find_key_page_of_key(gacha_state: &Pubkey, key_index: u16) -> (Pubkey, u8) {
+    find_key_page(gacha_state, key_index / KEYS_PER_PAGE as u16)
+}
+
+/// Marker of the last commitment of a randomness account consumed by a pull
+pub fn find_randomness_usage(randomness_account: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[RANDOMNESS_USAGE, randomness_account.as_ref()],
+        &gacha_machine::ID,
+    )
+}
+
+// ========================================
+// Payment Accounts
+// ========================================
+
+/// Program accounts of one payment method of a machine
+///
+/// SOL is held as lamports on the payment config itself; SPL tokens are held in
+/// the payment config's associated token account under the mint's token program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaymentAccounts {
+    /// Payment mint (the system program id for SOL)
+    pub mint: Pubkey,
+    /// Payment config PDA of the mint
+    pub payment_config: Pubkey,
+    /// Vault holding the payments
+    pub vault: Pubkey,
+    /// Token program of the mint (None for SOL)
+    pub token_program: Option<Pubkey>,
+}
+
+impl PaymentAccounts {
+    /// Payment accounts of SOL payments
+    pub fn sol(gacha_state: &Pubkey) -> Self {
+        let (payment_config, _) = find_payment_config(gacha_state, &system_program::ID);
+        Self {
+            mint: system_program::ID,
+            payment_config,
+            vault: payment_config,
+            token_program: None,
+        }
+    }
+
+    /// Payment accounts of an SPL mint owned by `token_program` (Token or Token-2022)
+    pub fn spl(gacha_state: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Self {
+        let (payment_config, _) = find_payment_config(gacha_state, mint);
+        Self {
+            mint: *mint,
+            payment_config,
+            vault: get_associated_token_address_with_program_id(
+                &payment_config,
+                mint,
+                token_program,
+            ),
+            token_program: Some(*token_program),
+        }
+    }
+
+    /// Whether this is the SOL payment method
+    pub fn is_sol(&self) -> bool {
+        self.mint == system_program::ID
+    }
+
+    /// Account of `owner` paying or receiving this payment method: the owner
+    /// itself for SOL, its associated token account for SPL
+    pub fn owner_account(&self, owner: &Pubkey) -> Pubkey {
+        match self.token_program {
+            Some(token_program) => {
+                get_associated_token_address_with_program_id(owner, &self.mint, &token_program)
+            }
+            None => *owner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Seeds spelled out as in the program's account constraints and the TS client,
+    // so a renamed constant or a reordered seed fails here
+    fn program_address(seeds: &[&[u8]]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(seeds, &gacha_machine::ID)
+    }
+
+    #[test]
+    fn pdas_match_the_program_seeds() {
+        let creator = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let (gacha_state, _) = find_gacha_state(&creator, 7);
+
+        assert_eq!(
+            gacha_state,
+            program_address(&[b"gacha_state", creator.as_ref(), &7u64.to_le_bytes()]).0
+        );
+        assert_eq!(
+            find_payment_config(&gacha_state, &mint),
+            program_address(&[b"payment_config", gacha_state.as_ref(), mint.as_ref()])
+        );
+        assert_eq!(
+            find_player_state(&gacha_state, &user, 42),
+            program_address(&[
+                b"player_state",
+                gacha_state.as_ref(),
+                user.as_ref(),
+                &42u64.to_le_bytes()
+            ])
+        );
+        assert_eq!(
+            find_multi_pull_state(&gacha_state, &user, 42),
+            program_address(&[
+                b"multi_pull_state",
+                gacha_state.as_ref(),
+                user.as_ref(),
+                &42u64.to_le_bytes()
+            ])
+        );
+        assert_eq!(
+            find_user_stats(&gacha_state, &user),
+            program_address(&[b"user_stats", gacha_state.as_ref(), user.as_ref()])
+        );
+        assert_eq!(
+            find_key_page(&gacha_state, 2),
+            program_address(&[b"key_page", gacha_state.as_ref(), &2u16.to_le_bytes()])
+        );
+        assert_eq!(
+            find_randomness_usage(&mint),
+            program_address(&[b"randomness_usage", mint.as_ref()])
+        );
+    }
+
+    #[test]
+    fn key_page_of_key_follows_the_page_size() {
+        let (gacha_state, _) = find_gacha_state(&Pubkey::new_unique(), 0);
+        assert_eq!(
+            find_key_page_of_key(&gacha_state, 0),
+            find_key_page(&gacha_state, 0)
+        );
+        assert_eq!(
+            find_key_page_of_key(&gacha_state, 63),
+            find_key_page(&gacha_state, 0)
+        );
+        assert_eq!(
+            find_key_page_of_key(&gacha_state, 64),
+            find_key_page(&gacha_state, 1)
+        );
+    }
+
+    #[test]
+    fn payment_accounts_locate_the_vaults() {
+        let (gacha_state, _) = find_gacha_state(&Pubkey::new_unique(), 0);
+        let owner = Pubkey::new_unique();
+
+        let sol = PaymentAccounts::sol(&gacha_state);
+        assert!(sol.is_sol());
+        assert_eq!(sol.vault, sol.payment_config);
+        assert_eq!(sol.owner_account(&owner), owner);
+
+        let mint = Pubkey::new_unique();
+        let spl = PaymentAccounts::spl(&gacha_state, &mint, &anchor_spl::token_2022::ID);
+        assert!(!spl.is_sol());
+        assert_eq!(
+            spl.vault,
+            get_associated_token_address_with_program_id(
+                &spl.payment_config,
+                &mint,
+                &anchor_spl::token_2022::ID
+            )
+        );
+        assert_eq!(
+            spl.owner_account(&owner),
+            get_associated_token_address_with_program_id(
+                &owner,
+                &mint,
+                &anchor_spl::token_2022::ID
+            )
+        );
+    }
+}
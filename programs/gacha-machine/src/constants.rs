@@ -1,15 +1,72 @@
-/// Constants module for the Gacha Machine program
-///
-/// Contains all program-wide constants and configuration values.
+//! Constants module for the Gacha Machine program
+//!
+//! Contains all program-wide constants and configuration values.
+
+/// Number of encrypted keys stored in a single key page account
+pub const KEYS_PER_PAGE: usize = 64;
+
+/// Maximum number of key pages a gacha machine can own
+pub const MAX_KEY_PAGES: usize = 1000;
 
 /// Maximum number of keys that can be stored in a gacha machine
-pub const MAX_KEYS: usize = 500;
+/// (key indices are u16, so this must stay below u16::MAX)
+pub const MAX_KEYS: usize = KEYS_PER_PAGE * MAX_KEY_PAGES;
+
+/// Maximum number of keys a Merkle-committed machine can hold
+/// (the tier pools are allocated in a single instruction, limited to 10 KiB of growth)
+pub const MAX_COMMITTED_KEYS: usize = 5000;
+
+/// Maximum length of a single encrypted key (matches PlayerState.winning_encrypted_key)
+pub const MAX_KEY_LENGTH: usize = 120;
+
+/// Maximum number of rarity tiers a gacha machine can define
+pub const MAX_TIERS: usize = 8;
+
+/// Maximum number of role grants a gacha machine can hold
+pub const MAX_ROLE_GRANTS: usize = 16;
+
+/// Maximum number of pulls a single multi-pull can reserve
+pub const MAX_MULTI_PULL: u8 = 10;
+
+/// Denominator for basis point values (10_000 bps = 100%)
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Decimals of USD prices in `UsdPricing` (micro-USD)
+pub const USD_PRICE_DECIMALS: u32 = 6;
+
+/// Decimals of native SOL (lamports per SOL)
+pub const SOL_DECIMALS: u8 = 9;
+
+/// Whether the program was built with the deterministic `test-randomness` provider
+pub const TEST_RANDOMNESS_ENABLED: bool = cfg!(feature = "test-randomness");
+
+/// Domain separator of the deterministic test randomness
+pub const TEST_RANDOMNESS_DOMAIN: &[u8] = b"gacha-machine/test-randomness";
+
+/// Domain separator of commit-reveal pull randomness
+pub const COMMIT_REVEAL_DOMAIN: &[u8] = b"gacha-machine/commit-reveal";
+
+/// Domain separator of instant pull randomness
+pub const INSTANT_RANDOMNESS_DOMAIN: &[u8] = b"gacha-machine/instant";
+
+/// Highest prize value (micro-USD) a machine may declare to enable instant pulls
+/// (the declaration itself is advisory, see `set_instant_mode`)
+pub const MAX_INSTANT_PRIZE_VALUE: u64 = 10 * 10u64.pow(USD_PRICE_DECIMALS);
+
+/// Maximum number of hashes deriving the server seed of a commit-reveal pull
+/// from the latest revealed seed (one per later reveal), bounding the compute
+/// of settlement; older pulls can only be refunded
+pub const MAX_SERVER_SEED_DISTANCE: u32 = 128;
+
+/// Compute Budget program, whose instructions may accompany an instant pull
+pub const COMPUTE_BUDGET_PROGRAM_ID: anchor_lang::prelude::Pubkey =
+    anchor_lang::prelude::pubkey!("ComputeBudget111111111111111111111111111111");
 
 /// Maximum slot difference allowed for randomness validation
 /// This ensures randomness data is recent and valid
 pub const MAX_SLOT_DIFFERENCE: u64 = 20;
 
-/// Seeds for PDA derivation
+// Seeds for PDA derivation
 
 /// Seed for gacha state PDA
 pub const GACHA_STATE: &[u8] = b"gacha_state";
@@ -19,3 +76,15 @@ pub const PAYMENT_CONFIG: &[u8] = b"payment_config";
 
 /// Seed for player state PDA
 pub const PLAYER_STATE: &[u8] = b"player_state";
+
+/// Seed for multi-pull state PDA
+pub const MULTI_PULL_STATE: &[u8] = b"multi_pull_state";
+
+/// Seed for user stats PDA
+pub const USER_STATS: &[u8] = b"user_stats";
+
+/// Seed for randomness usage PDA (one per Switchboard randomness account)
+pub const RANDOMNESS_USAGE: &[u8] = b"randomness_usage";
+
+/// Seed for key page PDA
+pub const KEY_PAGE: &[u8] = b"key_page";
@@ -1,26 +1,29 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::Token;
-use switchboard_on_demand::get_switchboard_on_demand_program_id;
+use anchor_spl::token_interface::TokenInterface;
 
 use crate::{
     constants::*,
     errors::GachaError,
-    states::{GachaState, PaymentConfig, PlayerState},
+    states::{
+        GachaState, KeyPage, MultiPullState, PaymentConfig, PlayerState, RandomnessSource,
+        RandomnessUsage, RarityTier, Role, RoleGrant, UserStats,
+    },
 };
 
-/// ========================================
-/// Account Structs
-/// ========================================
+// ========================================
+// Account Structs
+// ========================================
 
 /// Accounts required for initializing a new gacha machine
 #[derive(Accounts)]
+#[instruction(machine_id: u64)]
 pub struct Initialize<'info> {
     /// The gacha machine state account (PDA)
     #[account(
         init,
         payer = admin,
         space = 8 + GachaState::INITIAL_SIZE,
-        seeds = [b"gacha_state".as_ref()],
+        seeds = [GACHA_STATE, admin.key().as_ref(), &machine_id.to_le_bytes()],
         bump
     )]
     pub gacha_state: Account<'info, GachaState>,
@@ -33,30 +36,40 @@ pub struct Initialize<'info> {
 
 /// Accounts required for adding a payment configuration
 #[derive(Accounts)]
-#[instruction(payment_mint: Pubkey)]
+#[instruction(payment_mint: Pubkey, payment_price: u64, payment_recipient_account: Pubkey)]
 pub struct AddPaymentConfig<'info> {
-    /// The payment config account to create (PDA)
+    /// The payment config account to create (PDA, rejected in the handler if it already exists)
     #[account(
-        init,
-        payer = admin,
+        init_if_needed,
+        payer = authority,
         space = 8 + PaymentConfig::INIT_SPACE,
-        seeds = [b"payment_config".as_ref(), gacha_state.key().as_ref(), payment_mint.as_ref()],
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_mint.as_ref()],
         bump
     )]
     pub payment_config: Account<'info, PaymentConfig>,
     /// The gacha machine state to add the config to
     #[account(
         mut,
-        has_one = admin,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::PricingManager) @ GachaError::Unauthorized,
         // Reallocate to accommodate new payment config reference(old heap + new account)
         realloc = gacha_state.to_account_info().data_len() + 32,
-        realloc::payer = admin,
+        realloc::payer = authority,
         realloc::zero = false,
     )]
     pub gacha_state: Account<'info, GachaState>,
-    /// Admin account (must match gacha_state.admin)
+    /// Admin or holder of the pricing manager role
     #[account(mut)]
-    pub admin: Signer<'info>,
+    pub authority: Signer<'info>,
+    /// Payment mint account (the System Program for SOL)
+    /// CHECK: Verified to be a token mint (or the System Program) in the handler
+    #[account(address = payment_mint @ GachaError::MintMismatch)]
+    pub payment_mint_account: UncheckedAccount<'info>,
+    /// Recipient of the payments
+    /// CHECK: Verified against the payment mint in the handler
+    #[account(address = payment_recipient_account @ GachaError::AccountMismatch)]
+    pub recipient_account: UncheckedAccount<'info>,
     /// System program for account operations
     pub system_program: Program<'info, System>,
 }
@@ -68,40 +81,425 @@ pub struct RemovePaymentConfig<'info> {
     /// The payment config account to close
     #[account(
         mut,
-        close = admin,
-        seeds = [b"payment_config", gacha_state.key().as_ref(), payment_mint.as_ref()],
+        close = authority,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_mint.as_ref()],
         bump = payment_config.bump,
+        // The vault must be drained before close (refundable escrow and unwithdrawn revenue)
+        constraint = payment_config.escrowed_amount == 0 @ GachaError::VaultNotEmpty,
+        constraint = payment_config.withdrawable_amount() == 0 @ GachaError::VaultNotEmpty,
     )]
     pub payment_config: Account<'info, PaymentConfig>,
 
     /// The gacha machine state to update
     #[account(
         mut,
-        has_one = admin @ GachaError::IncorrectOwner
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::PricingManager) @ GachaError::Unauthorized,
+        // Give back the space of the removed payment config reference
+        realloc = gacha_state.to_account_info().data_len() - 32,
+        realloc::payer = authority,
+        realloc::zero = false,
     )]
     pub gacha_state: Account<'info, GachaState>,
 
-    /// Admin account (must match gacha_state.admin)
+    /// Admin or holder of the pricing manager role
     #[account(mut)]
-    pub admin: Signer<'info>,
+    pub authority: Signer<'info>,
 
     /// System program
     pub system_program: Program<'info, System>,
+
+    /// Vault of an SPL config, closed along with it (required for SPL configs)
+    /// CHECK: Address is verified against the config's mint and token program
+    #[account(mut)]
+    pub payment_vault: Option<UncheckedAccount<'info>>,
+
+    /// Mint of an SPL config (required for SPL configs)
+    /// CHECK: Validated by comparing with payment_config.mint
+    #[account(mut)]
+    pub payment_mint_account: Option<UncheckedAccount<'info>>,
+
+    /// Token program (Token or Token-2022) of an SPL config's vault
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for updating the price and/or recipient of a payment configuration
+#[derive(Accounts)]
+#[instruction(payment_mint: Pubkey)]
+pub struct UpdatePaymentConfig<'info> {
+    /// The payment config to update
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_mint.as_ref()],
+        bump = payment_config.bump,
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+    /// The gacha machine state owning the config
+    #[account(
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        // The handler checks the role needed for each change (price or recipient)
+        constraint = gacha_state.has_role(&authority.key(), Role::PricingManager)
+            || gacha_state.has_role(&authority.key(), Role::Treasurer) @ GachaError::Unauthorized
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin, or holder of the pricing manager (price) or treasurer (recipient) role
+    pub authority: Signer<'info>,
+    /// New payment recipient (omit to keep the current one)
+    /// CHECK: Validated against the payment config mint in the handler
+    pub new_recipient_account: Option<UncheckedAccount<'info>>,
+}
+
+/// Accounts required for updating the bulk discount of a payment configuration
+#[derive(Accounts)]
+#[instruction(payment_mint: Pubkey)]
+pub struct SetBulkDiscount<'info> {
+    /// The payment config to update
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_mint.as_ref()],
+        bump = payment_config.bump,
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+    /// The gacha machine state owning the config
+    #[account(
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::PricingManager) @ GachaError::Unauthorized
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the pricing manager role
+    pub authority: Signer<'info>,
+}
+
+/// Accounts required for setting or clearing the USD pricing of a payment configuration
+#[derive(Accounts)]
+#[instruction(payment_mint: Pubkey)]
+pub struct SetUsdPricing<'info> {
+    /// The payment config to update
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_mint.as_ref()],
+        bump = payment_config.bump,
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+    /// The gacha machine state owning the config
+    #[account(
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::PricingManager) @ GachaError::Unauthorized
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the pricing manager role
+    pub authority: Signer<'info>,
+    /// Switchboard pull feed named in the new pricing (omit when clearing it)
+    /// CHECK: Owner, address and layout are validated in the handler
+    pub price_feed: Option<UncheckedAccount<'info>>,
+}
+
+/// Accounts required for withdrawing settled payments from a payment vault
+#[derive(Accounts)]
+#[instruction(payment_mint: Pubkey)]
+pub struct Withdraw<'info> {
+    /// The payment config owning the vault (also the SOL payment vault)
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_mint.as_ref()],
+        bump = payment_config.bump,
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+
+    /// The gacha machine state
+    #[account(
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::Treasurer) @ GachaError::Unauthorized
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+
+    /// Admin or holder of the treasurer role
+    pub authority: Signer<'info>,
+
+    /// Payment mint account
+    /// CHECK: Payment mint is validated by comparing with payment_config.mint
+    pub payment_mint_account: AccountInfo<'info>,
+
+    /// Vault holding the payments (payment_config for SOL, its ATA for SPL)
+    /// CHECK: Validated in withdrawal processing for address
+    #[account(mut)]
+    pub payment_vault: AccountInfo<'info>,
+
+    /// Recipient of the withdrawal (must match payment_config.admin_recipient_account)
+    /// CHECK: Address is verified against the payment config
+    #[account(
+        mut,
+        address = payment_config.admin_recipient_account @ GachaError::AccountMismatch
+    )]
+    pub admin_recipient_account: AccountInfo<'info>,
+
+    /// Token program (Token or Token-2022) for SPL token transfers (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for creating a new key page
+#[derive(Accounts)]
+pub struct AddKeyPage<'info> {
+    /// The key page account to create (PDA, next page index of the machine)
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + KeyPage::INITIAL_SIZE,
+        seeds = [KEY_PAGE, gacha_state.key().as_ref(), &gacha_state.key_page_count.to_le_bytes()],
+        bump
+    )]
+    pub key_page: Account<'info, KeyPage>,
+    /// The gacha machine state owning the page
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::KeyManager) @ GachaError::Unauthorized,
+        constraint = (gacha_state.key_page_count as usize) < MAX_KEY_PAGES @ GachaError::TooManyKeyPages
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the key manager role
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// System program for account creation
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts required for closing the last key page of a finished gacha machine
+#[derive(Accounts)]
+pub struct CloseKeyPage<'info> {
+    /// The key page account to close (PDA, last page of the machine)
+    #[account(
+        mut,
+        close = admin,
+        seeds = [KEY_PAGE, gacha_state.key().as_ref(), &key_page.page_index.to_le_bytes()],
+        bump = key_page.bump,
+        has_one = gacha_state,
+        constraint = key_page.page_index + 1 == gacha_state.key_page_count @ GachaError::InvalidKeyPage
+    )]
+    pub key_page: Account<'info, KeyPage>,
+    /// The gacha machine state owning the page
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        has_one = admin,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin account (must match gacha_state.admin), receives the page's rent
+    #[account(mut)]
+    pub admin: Signer<'info>,
 }
 
 /// Accounts required for adding a key to the gacha machine
 #[derive(Accounts)]
 #[instruction(encrypted_key: String)]
 pub struct AddKey<'info> {
+    /// The key page receiving the key (must be the page of the next key index)
+    #[account(
+        mut,
+        seeds = [KEY_PAGE, gacha_state.key().as_ref(), &key_page.page_index.to_le_bytes()],
+        bump = key_page.bump,
+        has_one = gacha_state,
+        // Reallocate to accommodate new key (4 bytes for string length + key data)
+        realloc = key_page.to_account_info().data_len() + 4 + encrypted_key.len(),
+        realloc::payer = authority,
+        realloc::zero = false,
+        constraint = key_page.page_index as usize == gacha_state.total_keys as usize / KEYS_PER_PAGE @ GachaError::InvalidKeyPage
+    )]
+    pub key_page: Account<'info, KeyPage>,
     /// The gacha machine state to add the key to
     #[account(
         mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::KeyManager) @ GachaError::Unauthorized,
+        // Reserve room for the key's entry in its tier's remaining_indices (2 bytes per index)
+        realloc = gacha_state.to_account_info().data_len() + 2,
+        realloc::payer = authority,
+        realloc::zero = false,
+        constraint = (gacha_state.total_keys as usize) < MAX_KEYS @ GachaError::KeyPoolFull
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the key manager role
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// System program for reallocation
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts required for adding a batch of keys to the gacha machine
+#[derive(Accounts)]
+#[instruction(encrypted_keys: Vec<String>)]
+pub struct AddKeys<'info> {
+    /// The key page receiving the keys (must be the page of the next key index)
+    #[account(
+        mut,
+        seeds = [KEY_PAGE, gacha_state.key().as_ref(), &key_page.page_index.to_le_bytes()],
+        bump = key_page.bump,
+        has_one = gacha_state,
+        // Reallocate once for the whole batch (4 bytes for string length + key data per key)
+        realloc = key_page.to_account_info().data_len()
+            + encrypted_keys.iter().map(|key| 4 + key.len()).sum::<usize>(),
+        realloc::payer = authority,
+        realloc::zero = false,
+        constraint = key_page.page_index as usize == gacha_state.total_keys as usize / KEYS_PER_PAGE @ GachaError::InvalidKeyPage
+    )]
+    pub key_page: Account<'info, KeyPage>,
+    /// The gacha machine state to add the keys to
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::KeyManager) @ GachaError::Unauthorized,
+        // Reserve room for the keys' entries in their tier's remaining_indices (2 bytes per index)
+        realloc = gacha_state.to_account_info().data_len() + encrypted_keys.len() * 2,
+        realloc::payer = authority,
+        realloc::zero = false,
+        constraint = gacha_state.total_keys as usize + encrypted_keys.len() <= MAX_KEYS @ GachaError::KeyPoolFull
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the key manager role
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// System program for reallocation
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts required for configuring the rarity tiers
+#[derive(Accounts)]
+#[instruction(weights: Vec<u32>)]
+pub struct SetTierWeights<'info> {
+    /// The gacha machine state to configure
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::KeyManager) @ GachaError::Unauthorized,
+        // Reallocate to accommodate new (empty) tiers
+        realloc = gacha_state.to_account_info().data_len()
+            + weights.len().saturating_sub(gacha_state.tiers.len()) * RarityTier::INITIAL_SIZE,
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the key manager role
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// System program for reallocation
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts required for finalizing the gacha machine
+#[derive(Accounts)]
+pub struct Finalize<'info> {
+    /// The gacha machine state to finalize
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        // Space for the tier pools was reserved while adding keys
+        constraint = gacha_state.has_role(&authority.key(), Role::KeyManager) @ GachaError::Unauthorized,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the key manager role
+    pub authority: Signer<'info>,
+}
+
+/// Accounts required for revealing the next server seed of a commit-reveal machine
+#[derive(Accounts)]
+pub struct RevealServerSeed<'info> {
+    /// The gacha machine state
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::KeyManager) @ GachaError::Unauthorized,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the key manager role
+    pub authority: Signer<'info>,
+}
+
+/// Accounts required for finalizing the gacha machine with a Merkle-committed key list
+#[derive(Accounts)]
+#[instruction(key_root: [u8; 32], total_keys: u16)]
+pub struct FinalizeWithRoot<'info> {
+    /// The gacha machine state to finalize
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::KeyManager) @ GachaError::Unauthorized,
+        // Reallocate to accommodate the tier pools (2 bytes per index)
+        realloc = gacha_state.to_account_info().data_len() + total_keys as usize * 2,
+        realloc::payer = authority,
+        realloc::zero = false,
+        constraint = total_keys as usize <= MAX_COMMITTED_KEYS @ GachaError::KeyPoolFull
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the key manager role
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// System program for reallocation
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts required for closing a finished gacha machine
+///
+/// Every payment config listed in `gacha_state.payment_configs` is passed as a
+/// writable remaining account, in the same order. An SPL payment config is
+/// followed by its vault and mint (both writable) and the token program owning them.
+#[derive(Accounts)]
+pub struct CloseMachine<'info> {
+    /// The gacha machine state to close
+    #[account(
+        mut,
+        close = admin,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
         has_one = admin,
-        // Reallocate to accommodate new key (4 bytes for string length + key data)
-        realloc = gacha_state.to_account_info().data_len() + 4 + encrypted_key.len(),
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin account (must match gacha_state.admin), receives all reclaimed rent
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+/// Accounts required for pausing or halting the machine
+#[derive(Accounts)]
+pub struct PauserAction<'info> {
+    /// The gacha machine state to modify
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        constraint = gacha_state.has_role(&authority.key(), Role::Pauser) @ GachaError::Unauthorized
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Admin or holder of the pauser role
+    pub authority: Signer<'info>,
+}
+
+/// Accounts required for granting a role
+#[derive(Accounts)]
+pub struct GrantRole<'info> {
+    /// The gacha machine state holding the role registry
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        has_one = admin,
+        // Reallocate to accommodate the new role grant (duplicate grants and grants
+        // beyond MAX_ROLE_GRANTS fail in the handler, reverting the realloc)
+        realloc = gacha_state.to_account_info().data_len() + RoleGrant::INIT_SPACE,
         realloc::payer = admin,
         realloc::zero = false,
-        constraint = gacha_state.encrypted_keys.len() < MAX_KEYS @ GachaError::KeyPoolFull
     )]
     pub gacha_state: Account<'info, GachaState>,
     /// Admin account (must match gacha_state.admin)
@@ -111,15 +509,17 @@ pub struct AddKey<'info> {
     pub system_program: Program<'info, System>,
 }
 
-/// Accounts required for finalizing the gacha machine
+/// Accounts required for revoking a role
 #[derive(Accounts)]
-pub struct Finalize<'info> {
-    /// The gacha machine state to finalize
+pub struct RevokeRole<'info> {
+    /// The gacha machine state holding the role registry
     #[account(
         mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
         has_one = admin,
-        // Reallocate to accommodate remaining_indices vector (2 bytes per index)
-        realloc = gacha_state.to_account_info().data_len() + (gacha_state.encrypted_keys.len() * 2),
+        // Give back the space of the revoked role grant (rent is refunded to the admin)
+        realloc = gacha_state.to_account_info().data_len() - RoleGrant::INIT_SPACE,
         realloc::payer = admin,
         realloc::zero = false,
     )]
@@ -131,16 +531,35 @@ pub struct Finalize<'info> {
     pub system_program: Program<'info, System>,
 }
 
-/// Accounts required for admin actions (pause, halt, transfer)
+/// Accounts required for admin actions (refund timeout, pity, randomness source, admin proposals)
 #[derive(Accounts)]
 pub struct AdminAction<'info> {
     /// The gacha machine state to modify
-    #[account(mut, has_one = admin)]
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+        has_one = admin
+    )]
     pub gacha_state: Account<'info, GachaState>,
     /// Admin account (must match gacha_state.admin)
     pub admin: Signer<'info>,
 }
 
+/// Accounts required for accepting a pending admin transfer
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    /// The gacha machine state to modify
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// The pending admin (must match gacha_state.pending_admin)
+    pub new_admin: Signer<'info>,
+}
+
 /// Accounts required for performing a gacha pull
 #[derive(Accounts)]
 pub struct Pull<'info> {
@@ -149,18 +568,33 @@ pub struct Pull<'info> {
         init,
         payer = user,
         space = 8 + PlayerState::INIT_SPACE,
-        seeds = [b"player_state", user.key().as_ref(), &gacha_state.pull_count.to_le_bytes()],
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &gacha_state.pull_count.to_le_bytes()],
         bump
     )]
     pub player_state: Account<'info, PlayerState>,
 
+    /// Statistics of the user for this machine (created on the user's first pull)
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserStats::INIT_SPACE,
+        seeds = [USER_STATS, gacha_state.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
     /// The gacha machine state
-    #[account(mut, seeds = [b"gacha_state".as_ref()], bump = gacha_state.bump)]
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
     pub gacha_state: Account<'info, GachaState>,
 
-    /// Payment configuration for this pull
+    /// Payment configuration for this pull (also the SOL payment vault)
     #[account(
-        seeds = [b"payment_config".as_ref(), gacha_state.key().as_ref(), payment_config.mint.key().as_ref()],
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_config.mint.key().as_ref()],
         bump = payment_config.bump
     )]
     pub payment_config: Account<'info, PaymentConfig>,
@@ -178,47 +612,629 @@ pub struct Pull<'info> {
     #[account(mut)]
     pub user_payment_account: AccountInfo<'info>,
 
-    /// Admin's recipient account for payments
-    /// CHECK: Validated in payment processing functions for owner and matching config
+    /// Vault receiving the payment (payment_config for SOL, its ATA for SPL)
+    /// CHECK: Validated in payment processing functions for owner and address
     #[account(mut)]
-    pub admin_recipient_account: AccountInfo<'info>,
+    pub payment_vault: AccountInfo<'info>,
+
+    /// Randomness account (a Switchboard randomness account on Switchboard machines)
+    /// CHECK: Validated according to the machine's randomness source
+    pub randomness_account_data: AccountInfo<'info>,
 
-    /// Switchboard randomness account for verifiable randomness
-    /// CHECK: Validated to be owned by Switchboard program
+    /// Marker of the last commitment of the randomness account consumed by a pull
+    /// (required on Switchboard machines, must be omitted otherwise)
     #[account(
-        owner = get_switchboard_on_demand_program_id() @ GachaError::InvalidRandomnessOwner
+        init_if_needed,
+        payer = user,
+        space = 8 + RandomnessUsage::INIT_SPACE,
+        seeds = [RANDOMNESS_USAGE, randomness_account_data.key().as_ref()],
+        bump,
+        constraint = gacha_state.randomness_source == RandomnessSource::Switchboard
+            @ GachaError::RandomnessSourceMismatch
     )]
-    pub randomness_account_data: AccountInfo<'info>,
+    pub randomness_usage: Option<Account<'info, RandomnessUsage>>,
+
+    /// Switchboard price feed of a USD-priced payment config (optional)
+    /// CHECK: Verified against payment_config.usd_pricing in price computation
+    pub price_feed: Option<UncheckedAccount<'info>>,
 
     /// System program for SOL transfers
     pub system_program: Program<'info, System>,
-    /// Token program for SPL token transfers (optional)
-    pub token_program: Option<Program<'info, Token>>,
+    /// Token program (Token or Token-2022) for SPL token transfers (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
 }
 
-/// Accounts required for settling a gacha pull
+/// Accounts required for an instant (single-transaction) gacha pull
 #[derive(Accounts)]
-pub struct Settle<'info> {
-    /// Player state account for this settlement
+pub struct PullInstant<'info> {
+    /// Player state account to create for this pull (PDA), settled immediately
     #[account(
-        mut,
-        seeds = [b"player_state".as_ref(), user.key().as_ref(), &player_state.nonce.to_le_bytes()],
-        bump = player_state.bump,
-        has_one = user,
-        has_one = gacha_state
+        init,
+        payer = user,
+        space = 8 + PlayerState::INIT_SPACE,
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &gacha_state.pull_count.to_le_bytes()],
+        bump
     )]
     pub player_state: Account<'info, PlayerState>,
+
+    /// Statistics of the user for this machine (created on the user's first pull)
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserStats::INIT_SPACE,
+        seeds = [USER_STATS, gacha_state.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
     /// The gacha machine state
-    #[account(mut, seeds = [b"gacha_state".as_ref()], bump = gacha_state.bump)]
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
     pub gacha_state: Account<'info, GachaState>,
-    /// User who performed the original pull
-    pub user: Signer<'info>,
 
-    /// Switchboard randomness account (must match the one used in pull)
-    /// CHECK: Address must match player_state.randomness_account and be owned by Switchboard
+    /// Payment configuration for this pull (also the SOL payment vault)
     #[account(
-        address = player_state.randomness_account @ GachaError::InvalidRandomnessPlayerAccount,
-        owner = get_switchboard_on_demand_program_id() @ GachaError::InvalidRandomnessOwner
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_config.mint.key().as_ref()],
+        bump = payment_config.bump
     )]
-    pub randomness_account_data: AccountInfo<'info>,
+    pub payment_config: Account<'info, PaymentConfig>,
+
+    /// User performing the pull
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// Payment mint account (validated in instruction logic)
+    /// CHECK: Payment mint is validated by comparing with payment_config.mint
+    pub payment_mint: AccountInfo<'info>,
+
+    /// User's payment account (SOL account or token account)
+    /// CHECK: Validated in payment processing functions for owner, balance, and mint
+    #[account(mut)]
+    pub user_payment_account: AccountInfo<'info>,
+
+    /// Vault receiving the payment (payment_config for SOL, its ATA for SPL)
+    /// CHECK: Validated in payment processing functions for owner and address
+    #[account(mut)]
+    pub payment_vault: AccountInfo<'info>,
+
+    /// SlotHashes sysvar providing the randomness of the pull
+    /// CHECK: Address is verified to be the SlotHashes sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::slot_hashes::ID @ GachaError::SlotHashesMissing)]
+    pub slot_hashes: UncheckedAccount<'info>,
+
+    /// Instructions sysvar, used to reject pulls that could be reverted on their result
+    /// CHECK: Address is verified to be the Instructions sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    /// Switchboard price feed of a USD-priced payment config (optional)
+    /// CHECK: Verified against payment_config.usd_pricing in price computation
+    pub price_feed: Option<UncheckedAccount<'info>>,
+
+    /// System program for SOL transfers
+    pub system_program: Program<'info, System>,
+    /// Token program (Token or Token-2022) for SPL token transfers (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for settling a gacha pull
+#[derive(Accounts)]
+pub struct Settle<'info> {
+    /// Player state account for this settlement
+    #[account(
+        mut,
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &player_state.nonce.to_le_bytes()],
+        bump = player_state.bump,
+        has_one = user,
+        has_one = gacha_state
+    )]
+    pub player_state: Account<'info, PlayerState>,
+    /// The gacha machine state
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Payment configuration used by the pull (escrow accounting)
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), player_state.payment_mint.as_ref()],
+        bump = payment_config.bump
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+    /// Statistics of the user (pity counter)
+    #[account(
+        mut,
+        seeds = [USER_STATS, gacha_state.key().as_ref(), user.key().as_ref()],
+        bump = user_stats.bump,
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    /// User who performed the original pull
+    /// CHECK: Address is verified against player_state.user via has_one
+    pub user: AccountInfo<'info>,
+    /// Anyone settling the pull (the user or a crank)
+    #[account(mut)]
+    pub caller: Signer<'info>,
+
+    /// Switchboard randomness account (must match the one used in pull)
+    /// CHECK: Address must match player_state.randomness_account; validated according to the randomness source
+    #[account(
+        address = player_state.randomness_account @ GachaError::InvalidRandomnessPlayerAccount,
+    )]
+    pub randomness_account_data: AccountInfo<'info>,
+
+    /// SlotHashes sysvar, sealing a commit-reveal pull not sealed yet (optional)
+    /// CHECK: Address is verified to be the SlotHashes sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::slot_hashes::ID @ GachaError::SlotHashesMissing)]
+    pub slot_hashes: Option<UncheckedAccount<'info>>,
+
+    /// Payment mint account (required to pay a crank tip)
+    /// CHECK: Payment mint is validated by comparing with payment_config.mint
+    pub payment_mint: Option<AccountInfo<'info>>,
+
+    /// Vault holding the payment (required to pay a crank tip)
+    /// CHECK: Validated in tip processing for owner and address
+    #[account(mut)]
+    pub payment_vault: Option<AccountInfo<'info>>,
+
+    /// Account receiving the crank tip (the caller for SOL, a caller token account for SPL)
+    /// CHECK: Validated in tip processing for owner, mint and authority
+    #[account(mut)]
+    pub caller_payment_account: Option<AccountInfo<'info>>,
+
+    /// Token program (Token or Token-2022) for SPL crank tips (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for refunding an unsettled gacha pull
+#[derive(Accounts)]
+pub struct Refund<'info> {
+    /// Player state of the pull to refund
+    #[account(
+        mut,
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &player_state.nonce.to_le_bytes()],
+        bump = player_state.bump,
+        has_one = user,
+        has_one = gacha_state
+    )]
+    pub player_state: Account<'info, PlayerState>,
+    /// The gacha machine state
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Payment configuration used by the pull (also the SOL payment vault)
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), player_state.payment_mint.as_ref()],
+        bump = payment_config.bump
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+    /// The pull's user or the gacha admin
+    pub authority: Signer<'info>,
+    /// User who performed the original pull
+    /// CHECK: Address is verified against player_state.user via has_one
+    #[account(mut)]
+    pub user: AccountInfo<'info>,
+
+    /// Payment mint account
+    /// CHECK: Payment mint is validated by comparing with payment_config.mint
+    pub payment_mint: AccountInfo<'info>,
+
+    /// Account receiving the refund (the user for SOL, a user token account for SPL)
+    /// CHECK: Validated in refund processing for owner, mint and authority
+    #[account(mut)]
+    pub user_payment_account: AccountInfo<'info>,
+
+    /// Vault holding the payment (payment_config for SOL, its ATA for SPL)
+    /// CHECK: Validated in refund processing for owner and address
+    #[account(mut)]
+    pub payment_vault: AccountInfo<'info>,
+
+    /// Randomness account of the pull (decides whether the user may refund)
+    /// CHECK: Address must match player_state.randomness_account; validated according to the randomness source
+    #[account(
+        address = player_state.randomness_account @ GachaError::InvalidRandomnessPlayerAccount,
+    )]
+    pub randomness_account_data: AccountInfo<'info>,
+
+    /// Token program (Token or Token-2022) for SPL token transfers (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for claiming the winning key of a settled pull
+#[derive(Accounts)]
+pub struct ClaimKey<'info> {
+    /// Player state of the settled pull
+    #[account(
+        mut,
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), player_state.user.as_ref(), &player_state.nonce.to_le_bytes()],
+        bump = player_state.bump,
+        has_one = gacha_state
+    )]
+    pub player_state: Account<'info, PlayerState>,
+    /// The gacha machine state (claim counter)
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Key page holding player_state.result_index
+    #[account(
+        seeds = [KEY_PAGE, gacha_state.key().as_ref(), &key_page.page_index.to_le_bytes()],
+        bump = key_page.bump,
+        has_one = gacha_state,
+        constraint = key_page.page_index as usize == player_state.result_index as usize / KEYS_PER_PAGE @ GachaError::InvalidKeyPage
+    )]
+    pub key_page: Account<'info, KeyPage>,
+}
+
+/// Accounts required for claiming the winning key of a settled pull with a Merkle proof
+#[derive(Accounts)]
+pub struct ClaimKeyWithProof<'info> {
+    /// Player state of the settled pull
+    #[account(
+        mut,
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), player_state.user.as_ref(), &player_state.nonce.to_le_bytes()],
+        bump = player_state.bump,
+        has_one = gacha_state
+    )]
+    pub player_state: Account<'info, PlayerState>,
+    /// The gacha machine state holding the key root (claim counter)
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+}
+
+/// Accounts required for sealing a commit-reveal pull with its slot hash
+#[derive(Accounts)]
+pub struct SealPull<'info> {
+    /// Player state of the pull to seal
+    #[account(
+        mut,
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), player_state.user.as_ref(), &player_state.nonce.to_le_bytes()],
+        bump = player_state.bump,
+        has_one = gacha_state
+    )]
+    pub player_state: Account<'info, PlayerState>,
+    /// The gacha machine state
+    #[account(
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// SlotHashes sysvar
+    /// CHECK: Address is verified to be the SlotHashes sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::slot_hashes::ID @ GachaError::SlotHashesMissing)]
+    pub slot_hashes: UncheckedAccount<'info>,
+}
+
+/// Accounts required for closing a finished player state
+#[derive(Accounts)]
+pub struct ClosePlayerState<'info> {
+    /// Player state to close (rent goes back to the user)
+    #[account(
+        mut,
+        close = user,
+        seeds = [PLAYER_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &player_state.nonce.to_le_bytes()],
+        bump = player_state.bump,
+        has_one = user,
+        has_one = gacha_state
+    )]
+    pub player_state: Account<'info, PlayerState>,
+    /// The gacha machine the pull belongs to (may already be closed)
+    /// CHECK: Only the address is used, verified against player_state.gacha_state via has_one
+    pub gacha_state: UncheckedAccount<'info>,
+    /// User who performed the pull
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+/// Accounts required for performing a multi-pull
+#[derive(Accounts)]
+pub struct PullMany<'info> {
+    /// Multi-pull state account to create for this multi-pull (PDA)
+    #[account(
+        init,
+        payer = user,
+        space = 8 + MultiPullState::INIT_SPACE,
+        seeds = [MULTI_PULL_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &gacha_state.pull_count.to_le_bytes()],
+        bump
+    )]
+    pub multi_pull_state: Account<'info, MultiPullState>,
+
+    /// Statistics of the user for this machine (created on the user's first pull)
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserStats::INIT_SPACE,
+        seeds = [USER_STATS, gacha_state.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    /// The gacha machine state
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+
+    /// Payment configuration for this multi-pull (also the SOL payment vault)
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), payment_config.mint.key().as_ref()],
+        bump = payment_config.bump
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+
+    /// User performing the multi-pull
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// Payment mint account (validated in instruction logic)
+    /// CHECK: Payment mint is validated by comparing with payment_config.mint
+    pub payment_mint: AccountInfo<'info>,
+
+    /// User's payment account (SOL account or token account)
+    /// CHECK: Validated in payment processing functions for owner, balance, and mint
+    #[account(mut)]
+    pub user_payment_account: AccountInfo<'info>,
+
+    /// Vault receiving the payment (payment_config for SOL, its ATA for SPL)
+    /// CHECK: Validated in payment processing functions for owner and address
+    #[account(mut)]
+    pub payment_vault: AccountInfo<'info>,
+
+    /// Randomness account (a Switchboard randomness account on Switchboard machines)
+    /// CHECK: Validated according to the machine's randomness source
+    pub randomness_account_data: AccountInfo<'info>,
+
+    /// Marker of the last commitment of the randomness account consumed by a pull
+    /// (required on Switchboard machines, must be omitted otherwise)
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + RandomnessUsage::INIT_SPACE,
+        seeds = [RANDOMNESS_USAGE, randomness_account_data.key().as_ref()],
+        bump,
+        constraint = gacha_state.randomness_source == RandomnessSource::Switchboard
+            @ GachaError::RandomnessSourceMismatch
+    )]
+    pub randomness_usage: Option<Account<'info, RandomnessUsage>>,
+
+    /// Switchboard price feed of a USD-priced payment config (optional)
+    /// CHECK: Verified against payment_config.usd_pricing in price computation
+    pub price_feed: Option<UncheckedAccount<'info>>,
+
+    /// System program for SOL transfers
+    pub system_program: Program<'info, System>,
+    /// Token program (Token or Token-2022) for SPL token transfers (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for settling a multi-pull
+#[derive(Accounts)]
+pub struct SettleMany<'info> {
+    /// Multi-pull state account for this settlement
+    #[account(
+        mut,
+        seeds = [MULTI_PULL_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &multi_pull_state.nonce.to_le_bytes()],
+        bump = multi_pull_state.bump,
+        has_one = user,
+        has_one = gacha_state
+    )]
+    pub multi_pull_state: Account<'info, MultiPullState>,
+    /// The gacha machine state
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Payment configuration used by the multi-pull (escrow accounting)
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), multi_pull_state.payment_mint.as_ref()],
+        bump = payment_config.bump
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+    /// Statistics of the user (pity counter)
+    #[account(
+        mut,
+        seeds = [USER_STATS, gacha_state.key().as_ref(), user.key().as_ref()],
+        bump = user_stats.bump,
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    /// User who performed the multi-pull
+    /// CHECK: Address is verified against multi_pull_state.user via has_one
+    pub user: AccountInfo<'info>,
+    /// Anyone settling the multi-pull (the user or a crank)
+    #[account(mut)]
+    pub caller: Signer<'info>,
+
+    /// Switchboard randomness account (must match the one used in pull_many)
+    /// CHECK: Address must match multi_pull_state.randomness_account; validated according to the randomness source
+    #[account(
+        address = multi_pull_state.randomness_account @ GachaError::InvalidRandomnessPlayerAccount,
+    )]
+    pub randomness_account_data: AccountInfo<'info>,
+
+    /// SlotHashes sysvar, sealing a commit-reveal pull not sealed yet (optional)
+    /// CHECK: Address is verified to be the SlotHashes sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::slot_hashes::ID @ GachaError::SlotHashesMissing)]
+    pub slot_hashes: Option<UncheckedAccount<'info>>,
+
+    /// Payment mint account (required to pay a crank tip)
+    /// CHECK: Payment mint is validated by comparing with payment_config.mint
+    pub payment_mint: Option<AccountInfo<'info>>,
+
+    /// Vault holding the payment (required to pay a crank tip)
+    /// CHECK: Validated in tip processing for owner and address
+    #[account(mut)]
+    pub payment_vault: Option<AccountInfo<'info>>,
+
+    /// Account receiving the crank tip (the caller for SOL, a caller token account for SPL)
+    /// CHECK: Validated in tip processing for owner, mint and authority
+    #[account(mut)]
+    pub caller_payment_account: Option<AccountInfo<'info>>,
+
+    /// Token program (Token or Token-2022) for SPL crank tips (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for refunding an unsettled multi-pull
+#[derive(Accounts)]
+pub struct RefundMany<'info> {
+    /// Multi-pull state of the multi-pull to refund
+    #[account(
+        mut,
+        seeds = [MULTI_PULL_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &multi_pull_state.nonce.to_le_bytes()],
+        bump = multi_pull_state.bump,
+        has_one = user,
+        has_one = gacha_state
+    )]
+    pub multi_pull_state: Account<'info, MultiPullState>,
+    /// The gacha machine state
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// Payment configuration used by the multi-pull (also the SOL payment vault)
+    #[account(
+        mut,
+        seeds = [PAYMENT_CONFIG, gacha_state.key().as_ref(), multi_pull_state.payment_mint.as_ref()],
+        bump = payment_config.bump
+    )]
+    pub payment_config: Account<'info, PaymentConfig>,
+    /// The multi-pull's user or the gacha admin
+    pub authority: Signer<'info>,
+    /// User who performed the multi-pull
+    /// CHECK: Address is verified against multi_pull_state.user via has_one
+    #[account(mut)]
+    pub user: AccountInfo<'info>,
+
+    /// Payment mint account
+    /// CHECK: Payment mint is validated by comparing with payment_config.mint
+    pub payment_mint: AccountInfo<'info>,
+
+    /// Account receiving the refund (the user for SOL, a user token account for SPL)
+    /// CHECK: Validated in refund processing for owner, mint and authority
+    #[account(mut)]
+    pub user_payment_account: AccountInfo<'info>,
+
+    /// Vault holding the payment (payment_config for SOL, its ATA for SPL)
+    /// CHECK: Validated in refund processing for owner and address
+    #[account(mut)]
+    pub payment_vault: AccountInfo<'info>,
+
+    /// Randomness account of the pull (decides whether the user may refund)
+    /// CHECK: Address must match multi_pull_state.randomness_account; validated according to the randomness source
+    #[account(
+        address = multi_pull_state.randomness_account @ GachaError::InvalidRandomnessPlayerAccount,
+    )]
+    pub randomness_account_data: AccountInfo<'info>,
+
+    /// Token program (Token or Token-2022) for SPL token transfers (optional)
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+/// Accounts required for claiming the winning keys of a settled multi-pull
+///
+/// The key pages holding the results are passed as remaining accounts.
+#[derive(Accounts)]
+pub struct ClaimManyKeys<'info> {
+    /// Multi-pull state of the settled multi-pull
+    #[account(
+        mut,
+        seeds = [MULTI_PULL_STATE, gacha_state.key().as_ref(), multi_pull_state.user.as_ref(), &multi_pull_state.nonce.to_le_bytes()],
+        bump = multi_pull_state.bump,
+        has_one = gacha_state
+    )]
+    pub multi_pull_state: Account<'info, MultiPullState>,
+    /// The gacha machine state (claim counter)
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+}
+
+/// Accounts required for claiming a winning key of a settled multi-pull with a Merkle proof
+#[derive(Accounts)]
+pub struct ClaimManyKeyWithProof<'info> {
+    /// Multi-pull state of the settled multi-pull
+    #[account(
+        mut,
+        seeds = [MULTI_PULL_STATE, gacha_state.key().as_ref(), multi_pull_state.user.as_ref(), &multi_pull_state.nonce.to_le_bytes()],
+        bump = multi_pull_state.bump,
+        has_one = gacha_state
+    )]
+    pub multi_pull_state: Account<'info, MultiPullState>,
+    /// The gacha machine state holding the key root (claim counter)
+    #[account(
+        mut,
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+}
+
+/// Accounts required for sealing a commit-reveal multi-pull with its slot hash
+#[derive(Accounts)]
+pub struct SealMultiPull<'info> {
+    /// Multi-pull state to seal
+    #[account(
+        mut,
+        seeds = [MULTI_PULL_STATE, gacha_state.key().as_ref(), multi_pull_state.user.as_ref(), &multi_pull_state.nonce.to_le_bytes()],
+        bump = multi_pull_state.bump,
+        has_one = gacha_state
+    )]
+    pub multi_pull_state: Account<'info, MultiPullState>,
+    /// The gacha machine state
+    #[account(
+        seeds = [GACHA_STATE, gacha_state.creator.as_ref(), &gacha_state.machine_id.to_le_bytes()],
+        bump = gacha_state.bump,
+    )]
+    pub gacha_state: Account<'info, GachaState>,
+    /// SlotHashes sysvar
+    /// CHECK: Address is verified to be the SlotHashes sysvar
+    #[account(address = anchor_lang::solana_program::sysvar::slot_hashes::ID @ GachaError::SlotHashesMissing)]
+    pub slot_hashes: UncheckedAccount<'info>,
+}
+
+/// Accounts required for closing a finished multi-pull state
+#[derive(Accounts)]
+pub struct CloseMultiPullState<'info> {
+    /// Multi-pull state to close (rent goes back to the user)
+    #[account(
+        mut,
+        close = user,
+        seeds = [MULTI_PULL_STATE, gacha_state.key().as_ref(), user.key().as_ref(), &multi_pull_state.nonce.to_le_bytes()],
+        bump = multi_pull_state.bump,
+        has_one = user,
+        has_one = gacha_state
+    )]
+    pub multi_pull_state: Account<'info, MultiPullState>,
+    /// The gacha machine the pull belongs to (may already be closed)
+    /// CHECK: Only the address is used, verified against multi_pull_state.gacha_state via has_one
+    pub gacha_state: UncheckedAccount<'info>,
+    /// User who performed the multi-pull
+    #[account(mut)]
+    pub user: Signer<'info>,
 }
@@ -60,4 +60,122 @@ pub enum GachaError {
     MintMismatch,
     #[msg("Token Program Missing")]
     TokenProgramMissing,
+    #[msg("The encrypted key exceeds the maximum key length.")]
+    KeyTooLong,
+    #[msg("The key page does not match the expected page for this operation.")]
+    InvalidKeyPage,
+    #[msg("The key page is full.")]
+    KeyPageFull,
+    #[msg("The gacha machine has reached its maximum number of key pages.")]
+    TooManyKeyPages,
+    #[msg("This pull has not been settled yet.")]
+    NotSettled,
+    #[msg("The winning key of this pull has already been claimed.")]
+    AlreadyClaimed,
+    #[msg("This operation is not supported by the machine's key storage mode.")]
+    KeyStorageMismatch,
+    #[msg("The Merkle proof does not match the committed key root.")]
+    InvalidKeyProof,
+    #[msg("This pull has already been refunded.")]
+    AlreadyRefunded,
+    #[msg("The refund timeout for this pull has not elapsed yet.")]
+    RefundNotAvailable,
+    #[msg("Only the pull's user or the admin can refund it.")]
+    UnauthorizedRefund,
+    #[msg("The payment vault account is invalid.")]
+    InvalidPaymentVault,
+    #[msg("The payment vault still holds escrowed or unwithdrawn funds.")]
+    VaultNotEmpty,
+    #[msg("The withdrawal exceeds the funds released by settled pulls.")]
+    InsufficientWithdrawableFunds,
+    #[msg("The number of pulls is outside the allowed multi-pull range.")]
+    InvalidPullCount,
+    #[msg("The bulk discount must be below 100%.")]
+    InvalidDiscount,
+    #[msg("The rarity tier does not exist.")]
+    InvalidTier,
+    #[msg(
+        "Tier weights must be non-zero, at most MAX_TIERS, and keep every tier that holds keys."
+    )]
+    InvalidTierWeights,
+    #[msg("Only settled and claimed, or refunded, pulls can be closed.")]
+    PullNotFinished,
+    #[msg("The gacha machine still has key pages; close them before closing the machine.")]
+    KeyPagesNotClosed,
+    #[msg("Every payment config of the machine (with the vault, mint and token program of SPL configs) must be passed, in order, to close it.")]
+    PaymentConfigsMismatch,
+    #[msg("There is no pending admin transfer.")]
+    NoPendingAdmin,
+    #[msg("The signer is not the pending admin.")]
+    NotPendingAdmin,
+    #[msg("The signer does not hold the role required for this operation.")]
+    Unauthorized,
+    #[msg("The account already holds this role.")]
+    RoleAlreadyGranted,
+    #[msg("The account does not hold this role.")]
+    RoleNotGranted,
+    #[msg("The gacha machine has reached its maximum number of role grants.")]
+    TooManyRoleGrants,
+    #[msg(
+        "USD pricing needs a non-zero price and staleness limit and a deviation of at most 100%."
+    )]
+    InvalidUsdPricing,
+    #[msg("The payment config is USD-priced; its price feed account is required.")]
+    PriceFeedMissing,
+    #[msg("The price feed account is invalid or does not match the payment config.")]
+    InvalidPriceFeed,
+    #[msg("The price feed result is older than the allowed staleness.")]
+    StalePriceFeed,
+    #[msg("The price feed samples deviate more than allowed.")]
+    PriceDeviationTooLarge,
+    #[msg("The price of the pull exceeds the maximum price accepted by the user.")]
+    PriceExceedsMax,
+    #[msg("The payment mint is not a Token or Token-2022 mint (or the System Program for SOL).")]
+    InvalidPaymentMint,
+    #[msg("A payment config already exists for this mint.")]
+    PaymentConfigAlreadyExists,
+    #[msg("The randomness has already been revealed.")]
+    RandomnessAlreadyRevealed,
+    #[msg("The randomness commitment has already been used by another pull.")]
+    RandomnessAlreadyUsed,
+    #[msg("This randomness source is not available in this build of the program.")]
+    RandomnessSourceUnavailable,
+    #[msg("Commit-reveal machines must commit a server seed hash at finalization; other machines must not.")]
+    ServerSeedCommitmentMismatch,
+    #[msg("The server seed does not match the current commitment.")]
+    InvalidServerSeed,
+    #[msg("The server seed of this pull has not been revealed yet.")]
+    ServerSeedNotRevealed,
+    #[msg("The SlotHashes sysvar is required to seal commit-reveal pulls.")]
+    SlotHashesMissing,
+    #[msg("The crank tip must be below 10000 basis points.")]
+    InvalidCrankTip,
+    #[msg("The payment mint, vault and caller payment account are required to pay the crank tip.")]
+    CrankTipAccountsMissing,
+    #[msg("Instant pulls are not enabled on this gacha machine.")]
+    InstantModeDisabled,
+    #[msg("The max prize value of instant mode exceeds the allowed maximum.")]
+    InvalidInstantPrizeValue,
+    #[msg("Instant pulls must be called directly and as the last instruction of the transaction.")]
+    InstantPullNotTopLevel,
+    #[msg("The pull's randomness can still be resolved; settle it instead of refunding.")]
+    RandomnessResolvable,
+    #[msg("Switchboard pulls must pass the randomness usage account of their randomness account.")]
+    RandomnessUsageMissing,
+    #[msg("This operation is not supported by the machine's randomness source.")]
+    RandomnessSourceMismatch,
+    #[msg("This pull has already been sealed with its slot hash.")]
+    PullAlreadySealed,
+    #[msg(
+        "The slot hash sealing this pull is not in the SlotHashes sysvar (too early, or aged out)."
+    )]
+    PullNotSealable,
+    #[msg("Too many server seeds were revealed since this pull to derive its seed; refund it instead.")]
+    ServerSeedTooOld,
+    #[msg("Instant pulls can only share their transaction with compute budget, system, token and associated token instructions.")]
+    InstantPullUnsafeTransaction,
+    #[msg("This pull has not been sealed with its slot hash yet.")]
+    PullNotSealed,
+    #[msg("The vault, mint and token program of an SPL payment config are required to close it.")]
+    PaymentVaultAccountsMissing,
 }
@@ -3,34 +3,105 @@
 /// for off-chain tracking and monitoring.
 use anchor_lang::prelude::*;
 
+use crate::states::{RandomnessSource, Role, UsdPricing};
+
 /// Emitted when a new gacha machine is initialized
 #[event]
 pub struct GachaInitialized {
     pub admin: Pubkey,
+    pub machine_id: u64,
     pub gacha_state: Pubkey,
 }
 
 /// Emitted when a key is added to the gacha machine
 #[event]
 pub struct KeyAdded {
-    pub admin: Pubkey,
+    pub authority: Pubkey,
     pub key: String,
+    pub tier: u8,
     pub total_keys: u16,
+    pub page_index: u16,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a batch of keys is added to the gacha machine
+#[event]
+pub struct KeysAdded {
+    pub authority: Pubkey,
+    pub start_index: u16,
+    pub count: u16,
+    pub tier: u8,
+    pub total_keys: u16,
+    pub page_index: u16,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a key page is created for the gacha machine
+#[event]
+pub struct KeyPageAdded {
+    pub authority: Pubkey,
+    pub page_index: u16,
+    pub key_page: Pubkey,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the rarity tier weights are configured
+#[event]
+pub struct TierWeightsUpdated {
+    pub authority: Pubkey,
+    pub weights: Vec<u32>,
     pub gacha_state: Pubkey,
 }
 
 /// Emitted when the gacha machine is finalized
 #[event]
 pub struct GachaFinalized {
-    pub admin: Pubkey,
+    pub authority: Pubkey,
     pub total_keys: u16,
+    pub total_pages: u16,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a Merkle root of the key list is committed at finalization
+#[event]
+pub struct KeyRootCommitted {
+    pub authority: Pubkey,
+    pub key_root: [u8; 32],
+    pub total_keys: u16,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a commit-reveal machine commits its server seed hash at finalization
+#[event]
+pub struct ServerSeedCommitted {
+    pub authority: Pubkey,
+    pub seed_commitment: [u8; 32],
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a server seed of a commit-reveal machine is revealed
+#[event]
+pub struct ServerSeedRevealed {
+    pub authority: Pubkey,
+    pub server_seed: [u8; 32],
+    /// Number of server seeds revealed so far (including this one)
+    pub seed_epoch: u32,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a commit-reveal pull (or multi-pull) is sealed with its slot hash
+#[event]
+pub struct PullSealed {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub slot_hash: [u8; 32],
     pub gacha_state: Pubkey,
 }
 
 /// Emitted when the gacha machine is paused or unpaused
 #[event]
 pub struct GachaPaused {
-    pub admin: Pubkey,
+    pub authority: Pubkey,
     pub paused: bool,
     pub gacha_state: Pubkey,
 }
@@ -38,23 +109,108 @@ pub struct GachaPaused {
 /// Emitted when the gacha machine is halted or unhalted
 #[event]
 pub struct GachaHalted {
-    pub admin: Pubkey,
+    pub authority: Pubkey,
     pub halted: bool,
     pub gacha_state: Pubkey,
 }
 
-/// Emitted when admin privileges are transferred
+/// Emitted when the refund timeout is updated
+#[event]
+pub struct RefundTimeoutUpdated {
+    pub admin: Pubkey,
+    pub refund_timeout_slots: u64,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the randomness source of a gacha machine is changed
+#[event]
+pub struct RandomnessSourceUpdated {
+    pub admin: Pubkey,
+    pub randomness_source: RandomnessSource,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the pity threshold is updated
+#[event]
+pub struct PityThresholdUpdated {
+    pub admin: Pubkey,
+    pub pity_threshold: u32,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the crank tip is updated
+#[event]
+pub struct CrankTipUpdated {
+    pub admin: Pubkey,
+    pub crank_tip_bps: u16,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when instant mode is enabled, updated or disabled
 #[event]
-pub struct AdminTransferred {
+pub struct InstantModeUpdated {
+    pub admin: Pubkey,
+    pub max_prize_value: u64,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a third party settling a pull receives the crank tip
+#[event]
+pub struct CrankTipPaid {
+    pub caller: Pubkey,
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub payment_mint: Pubkey,
+    pub amount: u64,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the admin grants a role
+#[event]
+pub struct RoleGranted {
+    pub admin: Pubkey,
+    pub account: Pubkey,
+    pub role: Role,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the admin revokes a role
+#[event]
+pub struct RoleRevoked {
+    pub admin: Pubkey,
+    pub account: Pubkey,
+    pub role: Role,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the admin proposes a new admin
+#[event]
+pub struct AdminProposed {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the pending admin accepts admin privileges
+#[event]
+pub struct AdminAccepted {
     pub previous_admin: Pubkey,
     pub new_admin: Pubkey,
     pub gacha_state: Pubkey,
 }
 
+/// Emitted when the admin cancels a pending admin transfer
+#[event]
+pub struct AdminTransferCancelled {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub gacha_state: Pubkey,
+}
+
 /// Emitted when a payment configuration is added
 #[event]
 pub struct PaymentConfigAdded {
-    pub admin: Pubkey,
+    pub authority: Pubkey,
     pub payment_mint: Pubkey,
     pub payment_price: u64,
     pub payment_recipient_account: Pubkey,
@@ -64,8 +220,49 @@ pub struct PaymentConfigAdded {
 /// Emitted when a payment configuration is removed
 #[event]
 pub struct PaymentConfigRemoved {
-    pub admin: Pubkey,
+    pub authority: Pubkey,
+    pub payment_mint: Pubkey,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the price and/or recipient of a payment configuration is updated
+#[event]
+pub struct PaymentConfigUpdated {
+    pub authority: Pubkey,
+    pub payment_mint: Pubkey,
+    pub old_price: u64,
+    pub new_price: u64,
+    pub old_recipient_account: Pubkey,
+    pub new_recipient_account: Pubkey,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the USD pricing of a payment configuration is set or cleared
+#[event]
+pub struct UsdPricingUpdated {
+    pub authority: Pubkey,
     pub payment_mint: Pubkey,
+    pub usd_pricing: Option<UsdPricing>,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the bulk discount of a payment configuration is updated
+#[event]
+pub struct BulkDiscountUpdated {
+    pub authority: Pubkey,
+    pub payment_mint: Pubkey,
+    pub bulk_discount_bps: u16,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when settled payments are withdrawn from a payment vault
+#[event]
+pub struct Withdrawn {
+    pub authority: Pubkey,
+    pub payment_mint: Pubkey,
+    pub amount: u64,
+    pub recipient: Pubkey,
+    pub total_withdrawn: u64,
     pub gacha_state: Pubkey,
 }
 
@@ -75,7 +272,33 @@ pub struct GachaPulled {
     pub user: Pubkey,
     pub nonce: u64,
     pub payment_mint: Pubkey,
+    /// Amount actually charged (converted from `usd_price` on USD-priced configs)
+    pub price: u64,
+    /// USD price of the pull in micro-USD (0 on fixed-price configs)
+    pub usd_price: u64,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a user performs a multi-pull
+#[event]
+pub struct GachaMultiPulled {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub count: u8,
+    pub payment_mint: Pubkey,
     pub price: u64,
+    pub usd_price: u64,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when an unsettled pull is refunded
+#[event]
+pub struct GachaRefunded {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub payment_mint: Pubkey,
+    pub amount: u64,
+    pub refunded_by: Pubkey,
     pub gacha_state: Pubkey,
 }
 
@@ -84,10 +307,70 @@ pub struct GachaPulled {
 pub struct GachaResult {
     pub user: Pubkey,
     pub key_index: u16,
+    pub tier: u8,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a settlement is forced into the top tier by the pity mechanic
+#[event]
+pub struct PityTriggered {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub key_index: u16,
+    pub tier: u8,
+    /// Unlucky pulls in a row before this forced one
+    pub pulls_without_top_tier: u32,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a multi-pull is settled
+#[event]
+pub struct GachaMultiResult {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub key_indices: Vec<u16>,
+    pub tiers: Vec<u8>,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when the winning key of a settled pull is claimed from its key page
+#[event]
+pub struct KeyClaimed {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub key_index: u16,
     pub encrypted_key: String,
     pub gacha_state: Pubkey,
 }
 
+/// Emitted when a player state is closed, as a receipt of the pull
+#[event]
+pub struct PlayerStateClosed {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub payment_mint: Pubkey,
+    pub price_paid: u64,
+    pub is_refunded: bool,
+    pub result_index: u16,
+    pub result_tier: u8,
+    pub winning_encrypted_key: String,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a multi-pull state is closed, as a receipt of the multi-pull
+#[event]
+pub struct MultiPullStateClosed {
+    pub user: Pubkey,
+    pub nonce: u64,
+    pub payment_mint: Pubkey,
+    pub price_paid: u64,
+    pub is_refunded: bool,
+    pub key_indices: Vec<u16>,
+    pub tiers: Vec<u8>,
+    pub winning_encrypted_keys: Vec<String>,
+    pub gacha_state: Pubkey,
+}
+
 /// Emitted when decryption key is released
 #[event]
 pub struct DecryptionKeyReleased {
@@ -95,3 +378,25 @@ pub struct DecryptionKeyReleased {
     pub decryption_key: String,
     pub gacha_state: Pubkey,
 }
+
+/// Emitted when a key page of a finished gacha machine is closed
+#[event]
+pub struct KeyPageClosed {
+    pub admin: Pubkey,
+    pub page_index: u16,
+    pub gacha_state: Pubkey,
+}
+
+/// Emitted when a gacha machine is closed, summarizing its lifetime
+#[event]
+pub struct GachaClosed {
+    pub admin: Pubkey,
+    pub machine_id: u64,
+    pub total_keys: u16,
+    pub pull_count: u64,
+    pub settle_count: u64,
+    pub refund_count: u64,
+    pub payment_configs_closed: u8,
+    pub decryption_key: String,
+    pub gacha_state: Pubkey,
+}
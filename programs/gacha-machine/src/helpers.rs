@@ -1,31 +1,121 @@
-use crate::{contexts::*, errors::GachaError, states::PaymentConfig};
+use crate::{
+    constants::*,
+    contexts::*,
+    errors::GachaError,
+    states::{
+        GachaState, PaymentConfig, PlayerState, PullCommitment, RandomnessSource, RandomnessUsage,
+        RarityTier, UserStats,
+    },
+};
 
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT};
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token_2022::spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    state::{Account as TokenAccountState, Mint as MintState},
+};
+use anchor_spl::token_interface::{
+    self, Burn, CloseAccount, HarvestWithheldTokensToMint, Mint, TokenAccount, TokenInterface,
+    TransferChecked,
+};
+use switchboard_on_demand::{
+    accounts::{PullFeedAccountData, RandomnessAccountData},
+    get_switchboard_on_demand_program_id, PRECISION,
+};
 
-/// ========================================
-/// Payment Helper Functions
-/// ========================================
+// ========================================
+// Payment Helper Functions
+// ========================================
+
+/// Accounts involved in paying for a pull
+///
+/// Borrowed from the `Pull`, `PullMany` or `PullInstant` context so all pull
+/// instructions share the same payment processing.
+pub struct PaymentAccounts<'a, 'info> {
+    /// User paying for the pull(s)
+    pub user: &'a Signer<'info>,
+    /// Payment config of the chosen payment method (also the SOL payment vault)
+    pub payment_config: &'a Account<'info, PaymentConfig>,
+    /// Payment mint account
+    pub payment_mint: &'a AccountInfo<'info>,
+    /// User's payment account (SOL account or token account)
+    pub user_payment_account: &'a AccountInfo<'info>,
+    /// Vault receiving the payment
+    pub payment_vault: &'a AccountInfo<'info>,
+    /// System program for SOL transfers
+    pub system_program: &'a Program<'info, System>,
+    /// Token program for SPL token transfers (optional)
+    pub token_program: Option<&'a Interface<'info, TokenInterface>>,
+}
+
+impl<'info> Pull<'info> {
+    /// Payment accounts of a single pull
+    pub fn payment_accounts(&self) -> PaymentAccounts<'_, 'info> {
+        PaymentAccounts {
+            user: &self.user,
+            payment_config: &self.payment_config,
+            payment_mint: &self.payment_mint,
+            user_payment_account: &self.user_payment_account,
+            payment_vault: &self.payment_vault,
+            system_program: &self.system_program,
+            token_program: self.token_program.as_ref(),
+        }
+    }
+}
+
+impl<'info> PullMany<'info> {
+    /// Payment accounts of a multi-pull
+    pub fn payment_accounts(&self) -> PaymentAccounts<'_, 'info> {
+        PaymentAccounts {
+            user: &self.user,
+            payment_config: &self.payment_config,
+            payment_mint: &self.payment_mint,
+            user_payment_account: &self.user_payment_account,
+            payment_vault: &self.payment_vault,
+            system_program: &self.system_program,
+            token_program: self.token_program.as_ref(),
+        }
+    }
+}
+
+impl<'info> PullInstant<'info> {
+    /// Payment accounts of an instant pull
+    pub fn payment_accounts(&self) -> PaymentAccounts<'_, 'info> {
+        PaymentAccounts {
+            user: &self.user,
+            payment_config: &self.payment_config,
+            payment_mint: &self.payment_mint,
+            user_payment_account: &self.user_payment_account,
+            payment_vault: &self.payment_vault,
+            system_program: &self.system_program,
+            token_program: self.token_program.as_ref(),
+        }
+    }
+}
 
 /// Processes SOL payment for gacha pulls
 ///
-/// Handles native SOL transfers from user to admin recipient.
+/// Handles native SOL transfers from user to the payment vault (the payment config PDA).
 /// Validates account ownership, balances, and executes the transfer.
 ///
 /// Args:
-/// - ctx: Pull context containing all payment-related accounts
-/// - payment_config: Config specifying price and recipient
+/// - accounts: Payment-related accounts of the pull
+/// - amount: Lamports to charge
 ///
 /// Returns: Result indicating success or failure of the payment
-pub fn process_sol_payment(ctx: &Context<Pull>, payment_config: &PaymentConfig) -> Result<()> {
+pub fn process_sol_payment(accounts: &PaymentAccounts, amount: u64) -> Result<()> {
     // ============ OWNERSHIP VERIFICATION ============
-    // Verify both accounts are owned by the System Program (native SOL accounts)
+    // Verify the user's account is owned by the System Program (native SOL account)
     require_keys_eq!(
-        *ctx.accounts.user_payment_account.owner,
-        anchor_lang::system_program::ID,
-        GachaError::IncorrectOwner
-    );
-    require_keys_eq!(
-        *ctx.accounts.admin_recipient_account.owner,
+        *accounts.user_payment_account.owner,
         anchor_lang::system_program::ID,
         GachaError::IncorrectOwner
     );
@@ -33,71 +123,73 @@ pub fn process_sol_payment(ctx: &Context<Pull>, payment_config: &PaymentConfig)
     // ============ ACCOUNT MATCHING ============
     // Ensure the user's payment account is actually their own account
     require_keys_eq!(
-        ctx.accounts.user_payment_account.key(),
-        ctx.accounts.user.key(),
+        accounts.user_payment_account.key(),
+        accounts.user.key(),
         GachaError::AccountMismatch
     );
-    // Ensure the admin recipient matches the config
+    // SOL payments are held on the payment config PDA itself
     require_keys_eq!(
-        ctx.accounts.admin_recipient_account.key(),
-        payment_config.admin_recipient_account,
-        GachaError::AccountMismatch
+        accounts.payment_vault.key(),
+        accounts.payment_config.key(),
+        GachaError::InvalidPaymentVault
     );
 
     // ============ BALANCE VERIFICATION ============
     // Ensure user has enough SOL (lamports) for the payment
     require!(
-        ctx.accounts.user_payment_account.lamports() >= payment_config.price,
+        accounts.user_payment_account.lamports() >= amount,
         GachaError::InsufficientFunds
     );
 
     // ============ TRANSFER EXECUTION ============
     // Execute the SOL transfer using system program CPI
     let cpi_context = CpiContext::new(
-        ctx.accounts.system_program.to_account_info(),
+        accounts.system_program.to_account_info(),
         anchor_lang::system_program::Transfer {
-            from: ctx.accounts.user_payment_account.to_account_info(),
-            to: ctx.accounts.admin_recipient_account.to_account_info(),
+            from: accounts.user_payment_account.to_account_info(),
+            to: accounts.payment_vault.to_account_info(),
         },
     );
-    anchor_lang::system_program::transfer(cpi_context, payment_config.price)?;
+    anchor_lang::system_program::transfer(cpi_context, amount)?;
 
     Ok(())
 }
 
 /// Processes SPL token payment for gacha pulls
 ///
-/// Handles SPL token transfers from user's token account to admin's token account.
-/// Validates token account ownership, mint matching, balance sufficiency, and executes transfer.
+/// Handles token transfers from user's token account to the payment vault
+/// (the associated token account of the payment config PDA). Works with both the
+/// Token and Token-2022 programs; for transfer-fee mints the user is charged the
+/// fee on top of the amount so the vault receives at least the amount.
 ///
 /// Args:
-/// - ctx: Pull context containing all payment-related accounts
-/// - payment_config: Config specifying mint, price, and recipient
+/// - accounts: Payment-related accounts of the pull
+/// - amount: Tokens the vault must receive (smallest units)
 ///
 /// Returns: Result indicating success or failure of the payment
-pub fn process_spl_payment(ctx: &Context<Pull>, payment_config: &PaymentConfig) -> Result<()> {
+pub fn process_spl_payment(accounts: &PaymentAccounts, amount: u64) -> Result<()> {
+    let payment_config = accounts.payment_config;
+
     // ============ PROGRAM VERIFICATION ============
     // Ensure token program is provided for SPL token operations
-    let token_program = ctx
-        .accounts
+    let token_program = accounts
         .token_program
-        .as_ref()
         .ok_or(GachaError::TokenProgramMissing)?;
 
     // ============ OWNERSHIP VERIFICATION ============
     // Verify all accounts are owned by the Token Program
     require_keys_eq!(
-        *ctx.accounts.user_payment_account.owner,
+        *accounts.user_payment_account.owner,
         token_program.key(),
         GachaError::IncorrectOwner
     );
     require_keys_eq!(
-        *ctx.accounts.admin_recipient_account.owner,
+        *accounts.payment_vault.owner,
         token_program.key(),
         GachaError::IncorrectOwner
     );
     require_keys_eq!(
-        *ctx.accounts.payment_mint.owner,
+        *accounts.payment_mint.owner,
         token_program.key(),
         GachaError::IncorrectOwner
     );
@@ -105,38 +197,2048 @@ pub fn process_spl_payment(ctx: &Context<Pull>, payment_config: &PaymentConfig)
     // ============ ACCOUNT MATCHING ============
     // Ensure the mint account matches the payment config
     require_keys_eq!(
-        ctx.accounts.payment_mint.key(),
+        accounts.payment_mint.key(),
         payment_config.mint,
         GachaError::MintMismatch
     );
-    // Ensure the admin recipient matches the config
+    // Ensure the vault is the payment config's associated token account
     require_keys_eq!(
-        ctx.accounts.admin_recipient_account.key(),
-        payment_config.admin_recipient_account,
-        GachaError::AccountMismatch
+        accounts.payment_vault.key(),
+        payment_vault_address(&payment_config.key(), payment_config, &token_program.key()),
+        GachaError::InvalidPaymentVault
     );
 
+    // ============ FEE CALCULATION ============
+    // Gross up the transfer so the vault receives the full amount after transfer fees
+    let gross_amount = gross_payment_amount(accounts.payment_mint, amount)?;
+
     // ============ BALANCE VERIFICATION ============
     // Parse the user's token account and check balance
-    let user_token_account = anchor_spl::token::TokenAccount::try_deserialize(
-        &mut ctx.accounts.user_payment_account.data.borrow().as_ref(),
-    )?;
+    let user_token_account =
+        TokenAccount::try_deserialize(&mut accounts.user_payment_account.data.borrow().as_ref())?;
 
     require!(
-        user_token_account.amount >= payment_config.price,
+        user_token_account.amount >= gross_amount,
         GachaError::InsufficientFunds
     );
 
     // ============ TRANSFER EXECUTION ============
-    // Execute the SPL token transfer using token program CPI
-    let cpi_accounts = anchor_spl::token::Transfer {
-        from: ctx.accounts.user_payment_account.to_account_info(),
-        to: ctx.accounts.admin_recipient_account.to_account_info(),
-        authority: ctx.accounts.user.to_account_info(),
+    // Execute the checked token transfer using token program CPI
+    let decimals = mint_decimals(accounts.payment_mint)?;
+    let cpi_accounts = TransferChecked {
+        from: accounts.user_payment_account.to_account_info(),
+        mint: accounts.payment_mint.to_account_info(),
+        to: accounts.payment_vault.to_account_info(),
+        authority: accounts.user.to_account_info(),
     };
     let cpi_program = token_program.to_account_info();
     let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-    anchor_spl::token::transfer(cpi_ctx, payment_config.price)?;
+    token_interface::transfer_checked(cpi_ctx, gross_amount, decimals)?;
+
+    Ok(())
+}
+
+/// Computes the amount the user is charged for a payment
+///
+/// Payments in a Token-2022 transfer-fee mint are grossed up by the fee, so the
+/// vault receives `amount`; other payments (SOL included) charge `amount` as is.
+/// Pulls compare this amount, not the net price, with the user's `max_price`.
+///
+/// Args:
+/// - payment_mint: The payment mint account
+/// - amount: Amount the vault must receive
+///
+/// Returns: The amount debited from the user
+pub fn gross_payment_amount(payment_mint: &AccountInfo, amount: u64) -> Result<u64> {
+    let transfer_fee = transfer_fee_for_net_amount(payment_mint, amount)?;
+    amount
+        .checked_add(transfer_fee)
+        .ok_or_else(|| error!(GachaError::InsufficientFunds))
+}
+
+/// Computes the total price of a multi-pull
+///
+/// Charges `count * price`, minus the payment config's bulk discount when more
+/// than one pull is bought at once.
+///
+/// Args:
+/// - payment_config: Config specifying the bulk discount
+/// - unit_price: Price of a single pull (see `pull_unit_price`)
+/// - count: Number of pulls
+///
+/// Returns: The total price in lamports (SOL) or smallest token units (SPL)
+pub fn multi_pull_price(payment_config: &PaymentConfig, unit_price: u64, count: u8) -> Result<u64> {
+    let full_price = (unit_price as u128) * (count as u128);
+    let discount_bps = if count > 1 {
+        payment_config.bulk_discount_bps as u128
+    } else {
+        0
+    };
+    let total = full_price * (BPS_DENOMINATOR as u128 - discount_bps) / BPS_DENOMINATOR as u128;
+    u64::try_from(total).map_err(|_| error!(GachaError::InsufficientFunds))
+}
+
+/// Verifies that an account is a usable payment mint
+///
+/// SOL is represented by the System Program id; any other mint must be an
+/// initialized Token or Token-2022 mint.
+///
+/// Args:
+/// - payment_mint: The mint account to check
+///
+/// Returns: Result indicating whether the mint is valid
+pub fn validate_payment_mint(payment_mint: &AccountInfo) -> Result<()> {
+    if payment_mint.key() == anchor_lang::system_program::ID {
+        return Ok(());
+    }
+
+    require!(
+        *payment_mint.owner == anchor_spl::token::ID
+            || *payment_mint.owner == anchor_spl::token_2022::ID,
+        GachaError::InvalidPaymentMint
+    );
+    Mint::try_deserialize(&mut payment_mint.data.borrow().as_ref())
+        .map_err(|_| GachaError::InvalidPaymentMint)?;
+    Ok(())
+}
+
+/// Verifies that an account can receive the payments of a mint
+///
+/// SOL recipients must be System Program accounts; SPL recipients must be token
+/// accounts (Token or Token-2022) holding the payment mint.
+///
+/// Args:
+/// - payment_mint: Mint of the payment config (SystemProgram::id() for SOL)
+/// - recipient: The recipient account to check
+///
+/// Returns: Result indicating whether the recipient is valid
+pub fn validate_payment_recipient(payment_mint: &Pubkey, recipient: &AccountInfo) -> Result<()> {
+    if *payment_mint == anchor_lang::system_program::ID {
+        require_keys_eq!(
+            *recipient.owner,
+            anchor_lang::system_program::ID,
+            GachaError::IncorrectOwner
+        );
+        return Ok(());
+    }
+
+    require!(
+        *recipient.owner == anchor_spl::token::ID || *recipient.owner == anchor_spl::token_2022::ID,
+        GachaError::IncorrectOwner
+    );
+    let token_account = TokenAccount::try_deserialize(&mut recipient.data.borrow().as_ref())?;
+    require_keys_eq!(token_account.mint, *payment_mint, GachaError::MintMismatch);
+    Ok(())
+}
+
+/// Returns the price of a single pull in lamports (SOL) or smallest token units (SPL)
+///
+/// Fixed-price configs charge `payment_config.price`. USD-priced configs convert
+/// `usd_price` with the configured Switchboard pull feed, rounding up, after
+/// checking the feed result is fresh and its samples agree within the allowed spread.
+///
+/// Args:
+/// - payment_config: Config specifying the price or USD pricing
+/// - payment_mint: Payment mint account (decimals of SPL payments)
+/// - price_feed: The config's price feed (required for USD-priced configs)
+/// - current_slot: Current slot for the staleness check
+///
+/// Returns: The price of one pull
+pub fn pull_unit_price(
+    payment_config: &PaymentConfig,
+    payment_mint: &AccountInfo,
+    price_feed: Option<&AccountInfo>,
+    current_slot: u64,
+) -> Result<u64> {
+    let Some(usd_pricing) = payment_config.usd_pricing else {
+        return Ok(payment_config.price);
+    };
+
+    // ============ FEED VALIDATION ============
+    let price_feed = price_feed.ok_or(GachaError::PriceFeedMissing)?;
+    require_keys_eq!(
+        price_feed.key(),
+        usd_pricing.price_feed,
+        GachaError::InvalidPriceFeed
+    );
+    require_keys_eq!(
+        *price_feed.owner,
+        get_switchboard_on_demand_program_id(),
+        GachaError::InvalidPriceFeed
+    );
+    let feed = PullFeedAccountData::parse(price_feed.data.borrow())
+        .map_err(|_| GachaError::InvalidPriceFeed)?;
+
+    // ============ FRESHNESS AND DEVIATION GUARDS ============
+    require!(
+        feed.result.slot != 0
+            && current_slot.saturating_sub(feed.result.slot) <= usd_pricing.max_staleness_slots,
+        GachaError::StalePriceFeed
+    );
+    let feed_value = feed.result.value;
+    require!(feed_value > 0, GachaError::InvalidPriceFeed);
+    require!(
+        feed.result.range.unsigned_abs() * BPS_DENOMINATOR as u128
+            <= feed_value as u128 * usd_pricing.max_deviation_bps as u128,
+        GachaError::PriceDeviationTooLarge
+    );
+
+    // ============ CONVERSION ============
+    let decimals = if payment_config.mint == anchor_lang::system_program::ID {
+        SOL_DECIMALS
+    } else {
+        require_keys_eq!(
+            payment_mint.key(),
+            payment_config.mint,
+            GachaError::MintMismatch
+        );
+        mint_decimals(payment_mint)?
+    };
+    // amount = usd_price / 10^6 * 10^decimals / (feed_value / 10^18)
+    let numerator = (usd_pricing.usd_price as u128)
+        .checked_mul(10u128.pow(decimals as u32))
+        .and_then(|n| n.checked_mul(10u128.pow(PRECISION - USD_PRICE_DECIMALS)))
+        .ok_or(GachaError::InvalidPriceFeed)?;
+    let amount = numerator.div_ceil(feed_value as u128);
+    u64::try_from(amount).map_err(|_| error!(GachaError::InvalidPriceFeed))
+}
+
+/// Derives the vault token account of an SPL payment config
+///
+/// The vault is the associated token account of the payment config PDA for the
+/// config's mint under the given token program (Token or Token-2022).
+pub fn payment_vault_address(
+    payment_config_key: &Pubkey,
+    payment_config: &PaymentConfig,
+    token_program: &Pubkey,
+) -> Pubkey {
+    get_associated_token_address_with_program_id(
+        payment_config_key,
+        &payment_config.mint,
+        token_program,
+    )
+}
+
+/// Reads the decimals of a Token or Token-2022 mint
+pub fn mint_decimals(mint: &AccountInfo) -> Result<u8> {
+    let mint = Mint::try_deserialize(&mut mint.data.borrow().as_ref())?;
+    Ok(mint.decimals)
+}
+
+/// Computes the transfer fee to add so that `net_amount` arrives after fees
+///
+/// Returns 0 for mints without the Token-2022 transfer fee extension.
+pub fn transfer_fee_for_net_amount(mint: &AccountInfo, net_amount: u64) -> Result<u64> {
+    if *mint.owner != anchor_spl::token_2022::ID {
+        return Ok(0);
+    }
+
+    let mint_data = mint.data.borrow();
+    let mint_state = StateWithExtensions::<MintState>::unpack(&mint_data)?;
+    match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => fee_config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, net_amount)
+            .ok_or_else(|| error!(GachaError::InsufficientFunds)),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Releases SOL held in a payment vault
+///
+/// SOL vaults are the program-owned payment config PDAs, so lamports are moved
+/// directly without a system program CPI. Only payments tracked on top of the
+/// account's rent-exempt balance are ever released.
+///
+/// Args:
+/// - payment_config: The payment config acting as the vault
+/// - destination: Account receiving the lamports
+/// - amount: Lamports to release
+///
+/// Returns: Result indicating success or failure of the release
+pub fn release_sol_from_vault<'info>(
+    payment_config: &Account<'info, PaymentConfig>,
+    destination: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    payment_config.sub_lamports(amount)?;
+    destination.add_lamports(amount)?;
+    Ok(())
+}
+
+/// Releases SPL tokens held in a payment vault
+///
+/// The vault is the payment config's associated token account, so the transfer
+/// is signed with the payment config PDA seeds. For transfer-fee mints the
+/// destination receives `amount` minus the mint's transfer fee.
+///
+/// Args:
+/// - payment_config: The payment config owning the vault
+/// - payment_vault: The vault token account
+/// - payment_mint: The mint of the vault
+/// - destination: Token account receiving the tokens
+/// - token_program: The token program owning the accounts (Token or Token-2022)
+/// - amount: Tokens to release (smallest units)
+///
+/// Returns: Result indicating success or failure of the release
+pub fn release_spl_from_vault<'info>(
+    payment_config: &Account<'info, PaymentConfig>,
+    payment_vault: &AccountInfo<'info>,
+    payment_mint: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    require_keys_eq!(
+        payment_mint.key(),
+        payment_config.mint,
+        GachaError::MintMismatch
+    );
+    require_keys_eq!(
+        payment_vault.key(),
+        payment_vault_address(&payment_config.key(), payment_config, &token_program.key()),
+        GachaError::InvalidPaymentVault
+    );
+
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        PAYMENT_CONFIG,
+        payment_config.gacha_state.as_ref(),
+        payment_config.mint.as_ref(),
+        &[payment_config.bump],
+    ]];
+    let decimals = mint_decimals(payment_mint)?;
+    let cpi_accounts = TransferChecked {
+        from: payment_vault.clone(),
+        mint: payment_mint.clone(),
+        to: destination.clone(),
+        authority: payment_config.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer_seeds);
+    token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
+
+    Ok(())
+}
+
+/// Closes the vault token account of an SPL payment config
+///
+/// Token accounts can only be closed once empty: withheld Token-2022 transfer
+/// fees are harvested to the mint, and stray tokens sent to the vault outside of
+/// pulls (escrow and revenue are already drained) are burned. A vault that was
+/// never created is skipped.
+///
+/// Args:
+/// - payment_config: The payment config owning the vault
+/// - payment_vault: The vault token account
+/// - payment_mint: The mint of the vault
+/// - token_program: The token program owning the vault (Token or Token-2022)
+/// - destination: Account receiving the vault's rent
+///
+/// Returns: Result indicating success or failure of the close
+pub fn close_payment_vault<'info>(
+    payment_config: &Account<'info, PaymentConfig>,
+    payment_vault: &AccountInfo<'info>,
+    payment_mint: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    require!(
+        token_program.key() == anchor_spl::token::ID
+            || token_program.key() == anchor_spl::token_2022::ID,
+        GachaError::IncorrectOwner
+    );
+    require_keys_eq!(
+        payment_mint.key(),
+        payment_config.mint,
+        GachaError::MintMismatch
+    );
+    require_keys_eq!(
+        payment_vault.key(),
+        payment_vault_address(&payment_config.key(), payment_config, &token_program.key()),
+        GachaError::InvalidPaymentVault
+    );
+    if payment_vault.data_is_empty() {
+        return Ok(());
+    }
+    require_keys_eq!(
+        *payment_vault.owner,
+        token_program.key(),
+        GachaError::IncorrectOwner
+    );
+
+    let (amount, withheld_amount) = {
+        let vault_data = payment_vault.data.borrow();
+        let vault_state = StateWithExtensions::<TokenAccountState>::unpack(&vault_data)?;
+        let withheld_amount = vault_state
+            .get_extension::<TransferFeeAmount>()
+            .map_or(0, |fee_amount| u64::from(fee_amount.withheld_amount));
+        (vault_state.base.amount, withheld_amount)
+    };
+
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        PAYMENT_CONFIG,
+        payment_config.gacha_state.as_ref(),
+        payment_config.mint.as_ref(),
+        &[payment_config.bump],
+    ]];
+    if withheld_amount > 0 {
+        let cpi_accounts = HarvestWithheldTokensToMint {
+            token_program_id: token_program.clone(),
+            mint: payment_mint.clone(),
+        };
+        token_interface::harvest_withheld_tokens_to_mint(
+            CpiContext::new(token_program.clone(), cpi_accounts),
+            vec![payment_vault.clone()],
+        )?;
+    }
+    if amount > 0 {
+        let cpi_accounts = Burn {
+            mint: payment_mint.clone(),
+            from: payment_vault.clone(),
+            authority: payment_config.to_account_info(),
+        };
+        token_interface::burn(
+            CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer_seeds),
+            amount,
+        )?;
+    }
+    let cpi_accounts = CloseAccount {
+        account: payment_vault.clone(),
+        destination: destination.clone(),
+        authority: payment_config.to_account_info(),
+    };
+    token_interface::close_account(CpiContext::new_with_signer(
+        token_program.clone(),
+        cpi_accounts,
+        signer_seeds,
+    ))
+}
+
+/// Sends funds held in the payment vault to `recipient`
+///
+/// Used for refunds (to the pull's user) and crank tips (to the settling caller).
+/// SOL goes straight to the recipient; SPL goes to a token account of the
+/// recipient for the payment mint.
+///
+/// Args:
+/// - payment_config: The payment config acting as (or owning) the vault
+/// - payment_mint: The payment mint account
+/// - payment_vault: The vault holding the payment
+/// - destination: Account receiving the funds
+/// - token_program: Token program for SPL transfers (optional)
+/// - recipient: The account entitled to the funds
+/// - amount: Amount to send
+///
+/// Returns: Result indicating success or failure of the transfer
+pub fn release_payment<'info>(
+    payment_config: &Account<'info, PaymentConfig>,
+    payment_mint: &AccountInfo<'info>,
+    payment_vault: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    token_program: Option<&Interface<'info, TokenInterface>>,
+    recipient: Pubkey,
+    amount: u64,
+) -> Result<()> {
+    require_keys_eq!(
+        payment_mint.key(),
+        payment_config.mint,
+        GachaError::MintMismatch
+    );
+
+    if payment_config.mint == anchor_lang::system_program::ID {
+        // SOL goes straight to the recipient
+        require_keys_eq!(
+            payment_vault.key(),
+            payment_config.key(),
+            GachaError::InvalidPaymentVault
+        );
+        require_keys_eq!(destination.key(), recipient, GachaError::AccountMismatch);
+        release_sol_from_vault(payment_config, destination, amount)
+    } else {
+        let token_program = token_program.ok_or(GachaError::TokenProgramMissing)?;
+        require_keys_eq!(
+            *destination.owner,
+            token_program.key(),
+            GachaError::IncorrectOwner
+        );
+        // The destination must be a token account of the recipient for the payment mint
+        let destination_token_account =
+            TokenAccount::try_deserialize(&mut destination.data.borrow().as_ref())?;
+        require_keys_eq!(
+            destination_token_account.owner,
+            recipient,
+            GachaError::AccountMismatch
+        );
+        require_keys_eq!(
+            destination_token_account.mint,
+            payment_config.mint,
+            GachaError::MintMismatch
+        );
+        release_spl_from_vault(
+            payment_config,
+            payment_vault,
+            payment_mint,
+            destination,
+            &token_program.to_account_info(),
+            amount,
+        )
+    }
+}
+
+/// Pays the crank tip of a settlement to a third-party caller
+///
+/// No tip is due when the user settles their own pull or the machine's crank tip
+/// is 0; otherwise `crank_tip_bps` of the price paid is sent from the vault to the
+/// caller, so the tip accounts become required.
+///
+/// Skipping the tip for the user is a convenience (their own settlement needs no
+/// tip accounts), not a protection: a user settling through a second wallet
+/// collects the tip, so every pull is effectively discounted by the tip.
+///
+/// Args:
+/// - gacha_state: The gacha machine state
+/// - payment_config: The payment config of the pull
+/// - caller: Signer settling the pull
+/// - user: User who performed the pull
+/// - price_paid: Price paid for the pull
+/// - payment_mint: The payment mint account (optional)
+/// - payment_vault: The vault holding the payment (optional)
+/// - caller_payment_account: Account receiving the tip (optional)
+/// - token_program: Token program for SPL tips (optional)
+///
+/// Returns: The tip paid to the caller (0 if none)
+#[allow(clippy::too_many_arguments)]
+pub fn pay_crank_tip<'info>(
+    gacha_state: &GachaState,
+    payment_config: &Account<'info, PaymentConfig>,
+    caller: Pubkey,
+    user: Pubkey,
+    price_paid: u64,
+    payment_mint: Option<&AccountInfo<'info>>,
+    payment_vault: Option<&AccountInfo<'info>>,
+    caller_payment_account: Option<&AccountInfo<'info>>,
+    token_program: Option<&Interface<'info, TokenInterface>>,
+) -> Result<u64> {
+    if caller == user || gacha_state.crank_tip_bps == 0 {
+        return Ok(0);
+    }
+    let tip =
+        (price_paid as u128 * gacha_state.crank_tip_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+    if tip == 0 {
+        return Ok(0);
+    }
+
+    let (Some(payment_mint), Some(payment_vault), Some(caller_payment_account)) =
+        (payment_mint, payment_vault, caller_payment_account)
+    else {
+        return err!(GachaError::CrankTipAccountsMissing);
+    };
+    release_payment(
+        payment_config,
+        payment_mint,
+        payment_vault,
+        caller_payment_account,
+        token_program,
+        caller,
+        tip,
+    )?;
+
+    Ok(tip)
+}
+
+/// Checks that `authority` may refund an unsettled pull
+///
+/// Only pulls that cannot be settled can be refunded: either the machine is
+/// halted or the pull's randomness is stuck (see `pull_randomness_stalled`).
+/// Otherwise a user who dislikes the revealed outcome could refund it instead
+/// (or have the admin do it), turning refunds into free rerolls. The user must
+/// also wait until the machine's refund timeout is enabled and has elapsed
+/// since the pull, while the admin can refund right away.
+///
+/// Args:
+/// - gacha_state: The gacha machine state
+/// - authority: Signer requesting the refund
+/// - user: User who performed the pull
+/// - pull_slot: Slot of the pull
+/// - randomness_account: The randomness account recorded at pull time
+/// - commitment: Commitment stored on the pull
+/// - current_slot: The current slot
+///
+/// Returns: Result indicating whether the refund is allowed
+pub fn require_refund_allowed(
+    gacha_state: &GachaState,
+    authority: Pubkey,
+    user: Pubkey,
+    pull_slot: u64,
+    randomness_account: &AccountInfo,
+    commitment: &PullCommitment,
+    current_slot: u64,
+) -> Result<()> {
+    if authority != gacha_state.admin {
+        require_keys_eq!(authority, user, GachaError::UnauthorizedRefund);
+        require!(
+            gacha_state.refund_timeout_slots > 0
+                && current_slot >= pull_slot + gacha_state.refund_timeout_slots,
+            GachaError::RefundNotAvailable
+        );
+    }
+    require!(
+        gacha_state.is_halted
+            || pull_randomness_stalled(gacha_state, randomness_account, commitment),
+        GachaError::RandomnessResolvable
+    );
+    Ok(())
+}
+
+/// Whether the randomness of a pull is stuck through no action of the user
+///
+/// - Switchboard: the randomness account still holds the commitment bound at
+///   pull time and the oracle has not revealed it. A revealed value can be
+///   settled, and a re-committed (or closed) account is the user's own doing,
+///   since they control the randomness account; both can only be refunded
+///   while the machine is halted.
+/// - Commit-reveal: the operator has not revealed the server seed of the pull's
+///   epoch yet, or revealed so many seeds since that it can no longer be derived
+///   on-chain (`MAX_SERVER_SEED_DISTANCE`).
+/// - Deterministic (test builds): never stuck.
+///
+/// Args:
+/// - gacha_state: The gacha machine
+/// - randomness_account: The randomness account recorded at pull time
+/// - commitment: Commitment stored on the pull
+///
+/// Returns: true if the pull cannot be settled with its randomness
+pub fn pull_randomness_stalled(
+    gacha_state: &GachaState,
+    randomness_account: &AccountInfo,
+    commitment: &PullCommitment,
+) -> bool {
+    match gacha_state.randomness_source {
+        RandomnessSource::Switchboard => {
+            if *randomness_account.owner != get_switchboard_on_demand_program_id() {
+                return false;
+            }
+            let Ok(data) = randomness_account.try_borrow_data() else {
+                return false;
+            };
+            RandomnessAccountData::parse(data).is_ok_and(|randomness_data| {
+                randomness_data.seed_slot == commitment.seed_slot
+                    && randomness_data.reveal_slot < randomness_data.seed_slot
+            })
+        }
+        RandomnessSource::CommitReveal => {
+            commitment.seed_epoch >= gacha_state.seed_epoch
+                || server_seed_too_old(gacha_state.seed_epoch, commitment.seed_epoch)
+        }
+        RandomnessSource::Deterministic => false,
+    }
+}
+
+/// Initializes the user's stats account on their first pull
+///
+/// The account is created with `init_if_needed`, so this only fills it in when
+/// it was just created.
+pub fn init_user_stats(user_stats: &mut UserStats, user: Pubkey, gacha_state: Pubkey, bump: u8) {
+    if user_stats.user == Pubkey::default() {
+        user_stats.user = user;
+        user_stats.gacha_state = gacha_state;
+        user_stats.bump = bump;
+    }
+}
+
+// ========================================
+// Randomness Helper Functions
+// ========================================
+
+/// Binds a pull to the randomness it will be settled with
+///
+/// Dispatches on the machine's randomness source:
+/// - Switchboard pulls are bound to the current commitment of the randomness
+///   account (see `bind_switchboard_randomness`);
+/// - commit-reveal pulls record the client seed and the server seed epoch they
+///   will be settled with (the slot hash is sealed after the pull);
+/// - deterministic pulls need nothing beyond the pull itself.
+///
+/// Args:
+/// - gacha_state: The gacha machine
+/// - randomness_account: The randomness account passed to the pull
+/// - randomness_usage: Usage marker of the randomness account (required for Switchboard)
+/// - bump: PDA bump of the usage marker
+/// - client_seed: User-supplied client seed (commit-reveal)
+/// - current_slot: Current slot
+///
+/// Returns: The commitment to store on the pull
+pub fn bind_pull_randomness(
+    gacha_state: &GachaState,
+    randomness_account: &AccountInfo,
+    randomness_usage: Option<&mut RandomnessUsage>,
+    bump: Option<u8>,
+    client_seed: [u8; 32],
+    current_slot: u64,
+) -> Result<PullCommitment> {
+    match gacha_state.randomness_source {
+        RandomnessSource::Switchboard => {
+            require_keys_eq!(
+                *randomness_account.owner,
+                get_switchboard_on_demand_program_id(),
+                GachaError::InvalidRandomnessOwner
+            );
+            let (randomness_usage, bump) = randomness_usage
+                .zip(bump)
+                .ok_or(GachaError::RandomnessUsageMissing)?;
+            let seed_slot = bind_switchboard_randomness(
+                randomness_account,
+                randomness_usage,
+                bump,
+                current_slot,
+            )?;
+            Ok(PullCommitment {
+                seed_slot,
+                ..Default::default()
+            })
+        }
+        RandomnessSource::CommitReveal => Ok(PullCommitment {
+            seed_epoch: gacha_state.seed_epoch,
+            client_seed,
+            ..Default::default()
+        }),
+        RandomnessSource::Deterministic => {
+            require!(
+                TEST_RANDOMNESS_ENABLED,
+                GachaError::RandomnessSourceUnavailable
+            );
+            Ok(PullCommitment::default())
+        }
+    }
+}
+
+/// Resolves the random value a pull is settled with
+///
+/// Args:
+/// - gacha_state: The gacha machine
+/// - gacha_state_key: Address of the gacha machine
+/// - randomness_account: The randomness account recorded at pull time
+/// - commitment: Commitment stored on the pull (an unsealed commit-reveal pull
+///   records the slot hash it is sealed with here)
+/// - user: The user who pulled
+/// - nonce: Nonce of the pull
+/// - pull_slot: Slot of the pull
+/// - slot_hashes: The SlotHashes sysvar (seals unsealed commit-reveal pulls)
+/// - current_slot: Current slot
+///
+/// Returns: The 32 random bytes of the pull
+#[allow(clippy::too_many_arguments)]
+pub fn resolve_pull_randomness(
+    gacha_state: &GachaState,
+    gacha_state_key: &Pubkey,
+    randomness_account: &AccountInfo,
+    commitment: &mut PullCommitment,
+    user: &Pubkey,
+    nonce: u64,
+    pull_slot: u64,
+    slot_hashes: Option<&AccountInfo>,
+    current_slot: u64,
+) -> Result<[u8; 32]> {
+    match gacha_state.randomness_source {
+        RandomnessSource::Switchboard => {
+            require_keys_eq!(
+                *randomness_account.owner,
+                get_switchboard_on_demand_program_id(),
+                GachaError::InvalidRandomnessOwner
+            );
+            let randomness_data = RandomnessAccountData::parse(randomness_account.data.borrow())
+                .map_err(|_| GachaError::InvalidRandomnessAccount)?;
+            // The randomness account must still hold the commitment bound at pull time
+            require_eq!(
+                randomness_data.seed_slot,
+                commitment.seed_slot,
+                GachaError::RandomnessExpired
+            );
+            Ok(randomness_data
+                .get_value(current_slot)
+                .map_err(|_| GachaError::RandomnessNotResolved)?)
+        }
+        RandomnessSource::CommitReveal => {
+            require!(
+                !server_seed_too_old(gacha_state.seed_epoch, commitment.seed_epoch),
+                GachaError::ServerSeedTooOld
+            );
+            let server_seed = server_seed_for_epoch(
+                &gacha_state.seed_commitment,
+                gacha_state.seed_epoch,
+                commitment.seed_epoch,
+            )
+            .ok_or(GachaError::ServerSeedNotRevealed)?;
+            if !commitment.is_sealed() {
+                let slot_hashes = slot_hashes.ok_or(GachaError::SlotHashesMissing)?;
+                commitment.slot_hash = sealing_slot_hash(slot_hashes, pull_slot)?;
+            }
+            Ok(commit_reveal_value(
+                &server_seed,
+                &commitment.client_seed,
+                &commitment.slot_hash,
+                gacha_state_key,
+                user,
+                nonce,
+            ))
+        }
+        RandomnessSource::Deterministic => {
+            require!(
+                TEST_RANDOMNESS_ENABLED,
+                GachaError::RandomnessSourceUnavailable
+            );
+            Ok(hashv(&[
+                TEST_RANDOMNESS_DOMAIN,
+                gacha_state_key.as_ref(),
+                user.as_ref(),
+                &nonce.to_le_bytes(),
+            ])
+            .to_bytes())
+        }
+    }
+}
 
+/// Reads the most recent entry of the SlotHashes sysvar
+///
+/// The sysvar is too large to deserialize on-chain, so the first entry is read
+/// from the raw data: an 8-byte entry count followed by (slot, hash) pairs.
+pub fn most_recent_slot_hash(slot_hashes: &AccountInfo) -> Result<[u8; 32]> {
+    require_keys_eq!(
+        slot_hashes.key(),
+        anchor_lang::solana_program::sysvar::slot_hashes::ID,
+        GachaError::SlotHashesMissing
+    );
+    let data = slot_hashes.data.borrow();
+    require!(data.len() >= 48, GachaError::SlotHashesMissing);
+    let mut slot_hash = [0u8; 32];
+    slot_hash.copy_from_slice(&data[16..48]);
+    Ok(slot_hash)
+}
+
+/// Reads the hash sealing a commit-reveal pull from the SlotHashes sysvar
+///
+/// The sealing hash is the one of the first slot after `pull_slot`, so it is
+/// unknown when the pull is submitted and the client seed cannot be ground
+/// against it. Entries are stored newest first as (slot, hash) pairs after an
+/// 8-byte count; the entry is only trusted if the next older entry is at or
+/// before `pull_slot`, which proves no earlier slot after the pull aged out.
+///
+/// Args:
+/// - slot_hashes: The SlotHashes sysvar
+/// - pull_slot: Slot of the pull
+///
+/// Returns: The sealing slot hash (PullNotSealable if it is not available)
+pub fn sealing_slot_hash(slot_hashes: &AccountInfo, pull_slot: u64) -> Result<[u8; 32]> {
+    require_keys_eq!(
+        slot_hashes.key(),
+        anchor_lang::solana_program::sysvar::slot_hashes::ID,
+        GachaError::SlotHashesMissing
+    );
+    let data = slot_hashes.data.borrow();
+    let entry = |index: usize| -> Option<(u64, &[u8])> {
+        let entry = data.get(8 + index * 40..8 + (index + 1) * 40)?;
+        let slot = u64::from_le_bytes(entry[..8].try_into().ok()?);
+        Some((slot, &entry[8..]))
+    };
+
+    let mut index = 0;
+    while let Some((slot, hash)) = entry(index) {
+        match entry(index + 1) {
+            Some((older_slot, _)) if older_slot > pull_slot => index += 1,
+            Some(_) if slot > pull_slot => {
+                let mut slot_hash = [0u8; 32];
+                slot_hash.copy_from_slice(hash);
+                return Ok(slot_hash);
+            }
+            _ => break,
+        }
+    }
+    err!(GachaError::PullNotSealable)
+}
+
+/// Random value of an instant pull
+///
+/// Mixes the most recent slot hash with the machine, user and nonce of the pull,
+/// so every pull in a slot gets a distinct value.
+///
+/// Args:
+/// - slot_hash: Most recent entry of the SlotHashes sysvar
+/// - gacha_state: Public key of the gacha machine
+/// - user: User performing the pull
+/// - nonce: Nonce of the pull
+///
+/// Returns: The random bytes of the pull
+pub fn instant_randomness(
+    slot_hash: &[u8; 32],
+    gacha_state: &Pubkey,
+    user: &Pubkey,
+    nonce: u64,
+) -> [u8; 32] {
+    hashv(&[
+        INSTANT_RANDOMNESS_DOMAIN,
+        slot_hash,
+        gacha_state.as_ref(),
+        user.as_ref(),
+        &nonce.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Checks that an instant pull cannot be reverted based on its result
+///
+/// The pull must be a top-level instruction (not a CPI from a program that could
+/// inspect the drawn key and abort) and the last instruction of its transaction
+/// (so no later instruction can check the result and fail the transaction).
+/// Every other instruction of the transaction must belong to the Compute Budget,
+/// System, Token, Token-2022 or Associated Token programs: no other instruction
+/// of this program, and no unknown program that could assert the slot or the
+/// machine's `pull_count` to only let a known-good draw land.
+///
+/// This only covers the pull's own transaction. Bundles of several transactions
+/// and offline prediction of the draw are not prevented (see `pull_instant`).
+///
+/// Args:
+/// - instructions_sysvar: The Instructions sysvar
+///
+/// Returns: Result indicating whether the pull may proceed
+pub fn require_unrevertable_instant_pull(instructions_sysvar: &AccountInfo) -> Result<()> {
+    let current_index = load_current_index_checked(instructions_sysvar)? as usize;
+    let mut program_ids = Vec::new();
+    while let Ok(instruction) = load_instruction_at_checked(program_ids.len(), instructions_sysvar)
+    {
+        program_ids.push(instruction.program_id);
+    }
+    check_instant_pull_transaction(get_stack_height(), current_index, &program_ids)
+}
+
+/// Checks the call depth and the transaction of an instant pull
+///
+/// See `require_unrevertable_instant_pull`, which reads these from the runtime.
+///
+/// Args:
+/// - stack_height: Invocation stack height of the pull
+/// - current_index: Index of the top-level instruction being executed
+/// - program_ids: Program of every top-level instruction of the transaction
+///
+/// Returns: Result indicating whether the pull may proceed
+pub fn check_instant_pull_transaction(
+    stack_height: usize,
+    current_index: usize,
+    program_ids: &[Pubkey],
+) -> Result<()> {
+    require!(
+        stack_height == TRANSACTION_LEVEL_STACK_HEIGHT,
+        GachaError::InstantPullNotTopLevel
+    );
+    require!(
+        current_index + 1 == program_ids.len(),
+        GachaError::InstantPullNotTopLevel
+    );
+
+    for (index, program_id) in program_ids.iter().enumerate() {
+        let allowed = if *program_id == crate::ID {
+            index == current_index
+        } else {
+            [
+                COMPUTE_BUDGET_PROGRAM_ID,
+                anchor_lang::system_program::ID,
+                anchor_spl::token::ID,
+                anchor_spl::token_2022::ID,
+                anchor_spl::associated_token::ID,
+            ]
+            .contains(program_id)
+        };
+        require!(allowed, GachaError::InstantPullUnsafeTransaction);
+    }
     Ok(())
 }
+
+// ========================================
+// Commit-Reveal Verification
+// ========================================
+//
+// Commit-reveal machines use a hash chain of server seeds: the operator picks a
+// random `s_0` and computes `s_i = sha256(s_{i-1})` up to `s_n`, committing `s_n`
+// at finalization. Each `reveal_server_seed` publishes the preimage of the current
+// commitment (`s_{n-1}`, then `s_{n-2}`, ...), which becomes the new commitment.
+// These functions are public so anyone can recompute the random value of a pull.
+
+/// Hash commitment of a server seed (`sha256(server_seed)`)
+pub fn server_seed_commitment(server_seed: &[u8; 32]) -> [u8; 32] {
+    hashv(&[server_seed]).to_bytes()
+}
+
+/// Derives the server seed of a pull epoch from the latest revealed seed
+///
+/// Pulls made in epoch `e` are settled with the `e + 1`-th revealed seed, which
+/// is `revealed_count - 1 - e` hashes away from the latest one.
+///
+/// Args:
+/// - latest_seed: The latest revealed server seed
+/// - revealed_count: Number of server seeds revealed so far
+/// - epoch: Epoch recorded on the pull
+///
+/// Returns: The server seed of the epoch, or None if it is not revealed yet
+pub fn server_seed_for_epoch(
+    latest_seed: &[u8; 32],
+    revealed_count: u32,
+    epoch: u32,
+) -> Option<[u8; 32]> {
+    if epoch >= revealed_count {
+        return None;
+    }
+    let mut seed = *latest_seed;
+    for _ in 0..(revealed_count - 1 - epoch) {
+        seed = server_seed_commitment(&seed);
+    }
+    Some(seed)
+}
+
+/// Whether the server seed of an epoch is too many reveals old to derive on-chain
+///
+/// Deriving it takes one hash per seed revealed after it (see
+/// `server_seed_for_epoch`), so settlement is capped at `MAX_SERVER_SEED_DISTANCE`
+/// hashes to stay within compute limits.
+///
+/// Args:
+/// - revealed_count: Number of server seeds revealed so far
+/// - epoch: Epoch recorded on the pull
+pub fn server_seed_too_old(revealed_count: u32, epoch: u32) -> bool {
+    revealed_count.saturating_sub(epoch).saturating_sub(1) > MAX_SERVER_SEED_DISTANCE
+}
+
+/// Random value of a commit-reveal pull
+///
+/// `sha256(domain || server_seed || client_seed || slot_hash || gacha_state || user || nonce)`
+pub fn commit_reveal_value(
+    server_seed: &[u8; 32],
+    client_seed: &[u8; 32],
+    slot_hash: &[u8; 32],
+    gacha_state: &Pubkey,
+    user: &Pubkey,
+    nonce: u64,
+) -> [u8; 32] {
+    hashv(&[
+        COMMIT_REVEAL_DOMAIN,
+        server_seed,
+        client_seed,
+        slot_hash,
+        gacha_state.as_ref(),
+        user.as_ref(),
+        &nonce.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Recomputes the key drawn by a settled commit-reveal pull
+///
+/// Rebuilds the pull's random value from the revealed server seed and the fields
+/// recorded on the pull, then draws like `settle` does. The tiers and user stats
+/// must be those right before the settlement (e.g. read at the slot before it),
+/// since every settlement changes them.
+///
+/// Args:
+/// - server_seed: Server seed of the pull's epoch (see `server_seed_for_epoch`)
+/// - player_state: The pull, sealed by `seal_pull` or at settlement
+/// - gacha_state_key: Address of the gacha machine
+/// - tiers: The machine's rarity tiers before the settlement
+/// - pity_threshold: The machine's pity threshold
+/// - user_stats: Statistics of the user before the settlement
+///
+/// Returns: The drawn tier, the key index, and whether pity forced the draw
+pub fn recompute_commit_reveal_draw(
+    server_seed: &[u8; 32],
+    player_state: &PlayerState,
+    gacha_state_key: &Pubkey,
+    tiers: &[RarityTier],
+    pity_threshold: u32,
+    user_stats: &UserStats,
+) -> Result<(u8, u16, bool)> {
+    let commitment = &player_state.commitment;
+    require!(commitment.is_sealed(), GachaError::PullNotSealed);
+    let random_value = commit_reveal_value(
+        server_seed,
+        &commitment.client_seed,
+        &commitment.slot_hash,
+        gacha_state_key,
+        &player_state.user,
+        player_state.nonce,
+    );
+    draw_key_with_pity(
+        &mut tiers.to_vec(),
+        pity_threshold,
+        &mut user_stats.clone(),
+        &random_value,
+    )
+}
+
+/// Binds a pull to the current commitment of a Switchboard randomness account
+///
+/// The commitment must be recent (seeded within `MAX_SLOT_DIFFERENCE` slots), not
+/// revealed yet, and not consumed by any earlier pull. Its seed slot is recorded
+/// in the randomness usage marker and returned so settlement can check the
+/// account was not re-committed in between.
+///
+/// Args:
+/// - randomness_account: The Switchboard randomness account
+/// - randomness_usage: Usage marker of the randomness account (init_if_needed)
+/// - bump: PDA bump of the usage marker
+/// - current_slot: Current slot
+///
+/// Returns: The seed slot of the bound commitment
+pub fn bind_switchboard_randomness(
+    randomness_account: &AccountInfo,
+    randomness_usage: &mut RandomnessUsage,
+    bump: u8,
+    current_slot: u64,
+) -> Result<u64> {
+    let randomness_data = RandomnessAccountData::parse(randomness_account.data.borrow())
+        .map_err(|_| GachaError::InvalidRandomnessAccount)?;
+    let seed_slot = randomness_data.seed_slot;
+
+    require!(
+        current_slot >= seed_slot && current_slot - seed_slot <= MAX_SLOT_DIFFERENCE,
+        GachaError::RandomnessNotReady
+    );
+    // A reveal of the current commitment lands after its seed slot
+    require!(
+        randomness_data.reveal_slot < seed_slot,
+        GachaError::RandomnessAlreadyRevealed
+    );
+    require!(
+        randomness_usage.seed_slot != seed_slot,
+        GachaError::RandomnessAlreadyUsed
+    );
+
+    randomness_usage.randomness_account = randomness_account.key();
+    randomness_usage.seed_slot = seed_slot;
+    randomness_usage.bump = bump;
+    Ok(seed_slot)
+}
+
+/// Draws a key index from the remaining pool
+///
+/// Picks a random position among the remaining indices (Fisher-Yates style) and
+/// removes it, so every key is drawn at most once. The removal reorders the pool,
+/// so the key a random value maps to depends on the settlements before it.
+///
+/// Args:
+/// - remaining_indices: Pool of key indices not drawn yet
+/// - random_bytes: Random bytes (at least 8) used to pick the position
+///
+/// Returns: The drawn key index
+pub fn draw_key_index(remaining_indices: &mut Vec<u16>, random_bytes: &[u8]) -> Result<u16> {
+    require!(!remaining_indices.is_empty(), GachaError::GachaIsEmpty);
+
+    // Convert randomness bytes to u64 for indexing
+    let random_u64 = u64::from_le_bytes(
+        random_bytes
+            .get(0..8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(GachaError::InvalidRandomnessValue)?,
+    );
+
+    let selected_index_in_remaining = random_u64 as usize % remaining_indices.len();
+    Ok(remaining_indices.swap_remove(selected_index_in_remaining))
+}
+
+/// Draws a key from the rarity tiers by weight
+///
+/// The first 8 random bytes pick a tier with probability proportional to its
+/// weight among the tiers that still have keys; the next 8 bytes pick a key
+/// uniformly within that tier.
+///
+/// Args:
+/// - tiers: The machine's rarity tiers
+/// - random_bytes: Random bytes used for both choices
+///
+/// Returns: The drawn tier and key index
+pub fn draw_tiered_key(tiers: &mut [RarityTier], random_bytes: &[u8; 32]) -> Result<(u8, u16)> {
+    // Only tiers with keys left take part in the draw
+    let total_weight: u64 = tiers
+        .iter()
+        .filter(|tier| !tier.remaining_indices.is_empty())
+        .map(|tier| tier.weight as u64)
+        .sum();
+    require!(total_weight > 0, GachaError::GachaIsEmpty);
+
+    let random_u64 = u64::from_le_bytes(
+        random_bytes[0..8]
+            .try_into()
+            .map_err(|_| GachaError::InvalidRandomnessValue)?,
+    );
+    let mut roll = random_u64 % total_weight;
+
+    for (tier_index, tier) in tiers.iter_mut().enumerate() {
+        if tier.remaining_indices.is_empty() {
+            continue;
+        }
+        if roll < tier.weight as u64 {
+            let key_index = draw_key_index(&mut tier.remaining_indices, &random_bytes[8..16])?;
+            return Ok((tier_index as u8, key_index));
+        }
+        roll -= tier.weight as u64;
+    }
+
+    err!(GachaError::GachaIsEmpty)
+}
+
+/// Draws a key for a user, applying the pity mechanic
+///
+/// If pity is enabled and the user's last `pity_threshold` settled pulls all
+/// missed the top tier, the key is drawn from the top (last) tier directly,
+/// provided it still has keys. Otherwise the draw is a
+/// regular weighted tier draw. The user's pity counter is updated either way.
+///
+/// Args:
+/// - tiers: The machine's rarity tiers
+/// - pity_threshold: The machine's pity threshold (0 disables pity)
+/// - user_stats: Statistics of the user the draw is for
+/// - random_bytes: Random bytes for this draw
+///
+/// Returns: The drawn tier, the key index, and whether pity forced the draw
+pub fn draw_key_with_pity(
+    tiers: &mut [RarityTier],
+    pity_threshold: u32,
+    user_stats: &mut UserStats,
+    random_bytes: &[u8; 32],
+) -> Result<(u8, u16, bool)> {
+    let top_tier = tiers.len().saturating_sub(1);
+    let pity_due = pity_threshold > 0
+        && user_stats.pulls_since_top_tier >= pity_threshold
+        && tiers
+            .get(top_tier)
+            .is_some_and(|tier| !tier.remaining_indices.is_empty());
+
+    let (tier, key_index) = if pity_due {
+        let key_index =
+            draw_key_index(&mut tiers[top_tier].remaining_indices, &random_bytes[8..16])?;
+        (top_tier as u8, key_index)
+    } else {
+        draw_tiered_key(tiers, random_bytes)?
+    };
+
+    // Top-tier results reset the pity counter
+    if tier as usize == top_tier {
+        user_stats.pulls_since_top_tier = 0;
+    } else {
+        user_stats.pulls_since_top_tier += 1;
+    }
+    user_stats.total_settled += 1;
+
+    Ok((tier, key_index, pity_due))
+}
+
+/// Expands a single randomness value into independent values for each draw
+///
+/// Draw `i` uses `sha256(random_value || i)`, so a multi-pull settled with one
+/// oracle value still gets a distinct, deterministic value per pull.
+///
+/// Args:
+/// - random_value: The resolved oracle randomness
+/// - draw: Position of the draw within the multi-pull
+///
+/// Returns: The random bytes for this draw
+pub fn expand_randomness(random_value: &[u8; 32], draw: u8) -> [u8; 32] {
+    hashv(&[random_value, &[draw]]).to_bytes()
+}
+
+// ========================================
+// Merkle Helper Functions
+// ========================================
+
+/// Domain separator for Merkle leaves
+const MERKLE_LEAF_PREFIX: &[u8] = &[0];
+/// Domain separator for Merkle inner nodes
+const MERKLE_NODE_PREFIX: &[u8] = &[1];
+
+/// Computes the Merkle leaf of an encrypted key
+///
+/// Leaves commit to both the key index and the encrypted key, so a key cannot be
+/// claimed for a different index than the one selected at settlement.
+///
+/// Args:
+/// - index: Index of the key in the committed key list
+/// - encrypted_key: The encrypted reward key
+///
+/// Returns: The sha256 leaf hash
+pub fn key_leaf_hash(index: u16, encrypted_key: &str) -> [u8; 32] {
+    hashv(&[
+        MERKLE_LEAF_PREFIX,
+        &index.to_le_bytes(),
+        encrypted_key.as_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Verifies that an encrypted key is part of a committed key list
+///
+/// Inner nodes hash their two children in sorted order, so proofs only need
+/// the sibling hashes from the leaf up to the root.
+///
+/// Args:
+/// - root: The committed Merkle root
+/// - index: Index of the key in the committed key list
+/// - encrypted_key: The encrypted reward key
+/// - proof: Sibling hashes from the leaf level up to the root
+///
+/// Returns: Whether the proof is valid for the given root
+pub fn verify_key_proof(
+    root: &[u8; 32],
+    index: u16,
+    encrypted_key: &str,
+    proof: &[[u8; 32]],
+) -> bool {
+    let computed = proof
+        .iter()
+        .fold(key_leaf_hash(index, encrypted_key), |node, sibling| {
+            if node <= *sibling {
+                hashv(&[MERKLE_NODE_PREFIX, &node, sibling]).to_bytes()
+            } else {
+                hashv(&[MERKLE_NODE_PREFIX, sibling, &node]).to_bytes()
+            }
+        });
+    computed == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::states::{KeyStorage, UsdPricing};
+    use anchor_lang::solana_program::sysvar::slot_hashes;
+    use std::mem::{offset_of, size_of};
+    use switchboard_on_demand::accounts::CurrentResult;
+    use switchboard_on_demand::Discriminator as _;
+
+    /// SlotHashes sysvar data holding `slots` (newest first), each hashed to `[slot; 32]`
+    fn slot_hashes_data(slots: &[u64]) -> Vec<u8> {
+        let mut data = (slots.len() as u64).to_le_bytes().to_vec();
+        for slot in slots {
+            data.extend_from_slice(&slot.to_le_bytes());
+            data.extend_from_slice(&[*slot as u8; 32]);
+        }
+        data
+    }
+
+    fn sealing_hash(slots: &[u64], pull_slot: u64) -> Result<[u8; 32]> {
+        let key = slot_hashes::ID;
+        let owner = anchor_lang::solana_program::sysvar::ID;
+        let mut lamports = 0;
+        let mut data = slot_hashes_data(slots);
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        sealing_slot_hash(&account, pull_slot)
+    }
+
+    #[test]
+    fn sealing_slot_hash_picks_the_first_slot_after_the_pull() {
+        assert_eq!(sealing_hash(&[14, 13, 11, 10, 9], 11).unwrap(), [13; 32]);
+        // Skipped slots are absent from the sysvar
+        assert_eq!(sealing_hash(&[14, 13, 11, 10, 9], 12).unwrap(), [13; 32]);
+        assert_eq!(sealing_hash(&[14, 13, 11, 10, 9], 10).unwrap(), [11; 32]);
+    }
+
+    #[test]
+    fn sealing_slot_hash_rejects_missing_entries() {
+        // Too early: no slot after the pull yet
+        assert!(sealing_hash(&[14, 13, 11], 14).is_err());
+        // Aged out: the first slot after the pull may have left the sysvar
+        assert!(sealing_hash(&[14, 13, 11], 10).is_err());
+        assert!(sealing_hash(&[], 10).is_err());
+    }
+
+    #[test]
+    fn server_seed_distance_is_capped() {
+        assert!(!server_seed_too_old(1, 0));
+        assert!(!server_seed_too_old(MAX_SERVER_SEED_DISTANCE + 1, 0));
+        assert!(server_seed_too_old(MAX_SERVER_SEED_DISTANCE + 2, 0));
+        // Unrevealed epochs are not too old
+        assert!(!server_seed_too_old(3, 5));
+    }
+
+    #[test]
+    fn server_seed_for_epoch_follows_the_hash_chain() {
+        let s0 = [7u8; 32];
+        let s1 = server_seed_commitment(&s0);
+        let s2 = server_seed_commitment(&s1);
+        // Two reveals: s1 for epoch 0, then s0 for epoch 1
+        assert_eq!(server_seed_for_epoch(&s0, 2, 0), Some(s1));
+        assert_eq!(server_seed_for_epoch(&s0, 2, 1), Some(s0));
+        assert_eq!(server_seed_for_epoch(&s0, 2, 2), None);
+        assert_eq!(server_seed_commitment(&s1), s2);
+    }
+
+    fn gacha_state(randomness_source: RandomnessSource, tiers: Vec<RarityTier>) -> GachaState {
+        GachaState {
+            admin: Pubkey::default(),
+            pending_admin: None,
+            creator: Pubkey::default(),
+            machine_id: 0,
+            bump: 0,
+            is_finalized: true,
+            is_paused: false,
+            is_halted: false,
+            pull_count: 0,
+            settle_count: 0,
+            refund_count: 0,
+            claim_count: 0,
+            refund_timeout_slots: 0,
+            pity_threshold: 0,
+            randomness_source,
+            seed_commitment: [0; 32],
+            seed_epoch: 0,
+            crank_tip_bps: 0,
+            instant_max_prize_value: 0,
+            key_storage: KeyStorage::Paged,
+            key_root: [0; 32],
+            total_keys: tiers.iter().map(|tier| tier.key_count).sum(),
+            key_page_count: 0,
+            decryption_key: String::new(),
+            tiers,
+            payment_configs: Vec::new(),
+            roles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn gacha_state_is_closable_once_settled_or_fully_refunded() {
+        let mut gacha = gacha_state(
+            RandomnessSource::Deterministic,
+            vec![rarity_tier(1, vec![0, 1, 2])],
+        );
+        assert!(gacha.is_closable());
+
+        // Pulls pending, then all of them refunded (a multi-pull of 2 and a pull)
+        gacha.pull_count = 3;
+        assert!(!gacha.is_closable());
+        gacha.refund_count = 3;
+        assert!(gacha.is_closable());
+
+        // Every key settled, then claimed
+        gacha.pull_count = 5;
+        gacha.settle_count = 2;
+        assert!(!gacha.is_closable());
+        gacha.settle_count = 3;
+        assert!(!gacha.is_closable());
+        gacha.claim_count = 3;
+        assert!(gacha.is_closable());
+    }
+
+    fn refund(
+        gacha: &GachaState,
+        authority: Pubkey,
+        seed_epoch: u32,
+        current_slot: u64,
+    ) -> Result<()> {
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let mut lamports = 0;
+        let mut data = Vec::new();
+        let randomness_account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let commitment = PullCommitment {
+            seed_epoch,
+            ..Default::default()
+        };
+        require_refund_allowed(
+            gacha,
+            authority,
+            Pubkey::new_from_array([1; 32]),
+            100,
+            &randomness_account,
+            &commitment,
+            current_slot,
+        )
+    }
+
+    #[test]
+    fn require_refund_allowed_rejects_resolvable_pulls_for_everyone() {
+        let user = Pubkey::new_from_array([1; 32]);
+        let mut gacha = gacha_state(
+            RandomnessSource::CommitReveal,
+            vec![rarity_tier(1, vec![0, 1, 2])],
+        );
+        gacha.admin = Pubkey::new_unique();
+        gacha.refund_timeout_slots = 10;
+        gacha.seed_epoch = 1;
+
+        // The seed of epoch 0 is revealed: the pull can be settled
+        assert_eq!(
+            refund(&gacha, gacha.admin, 0, 100).unwrap_err(),
+            GachaError::RandomnessResolvable.into()
+        );
+        assert_eq!(
+            refund(&gacha, user, 0, 110).unwrap_err(),
+            GachaError::RandomnessResolvable.into()
+        );
+
+        // The seed of epoch 1 is not: the admin can refund right away, the user
+        // only once the timeout has elapsed
+        refund(&gacha, gacha.admin, 1, 100).unwrap();
+        assert_eq!(
+            refund(&gacha, user, 1, 109).unwrap_err(),
+            GachaError::RefundNotAvailable.into()
+        );
+        refund(&gacha, user, 1, 110).unwrap();
+        assert_eq!(
+            refund(&gacha, Pubkey::new_unique(), 1, 110).unwrap_err(),
+            GachaError::UnauthorizedRefund.into()
+        );
+
+        // Halting makes every pull refundable
+        gacha.is_halted = true;
+        refund(&gacha, gacha.admin, 0, 100).unwrap();
+        refund(&gacha, user, 0, 110).unwrap();
+    }
+
+    fn player_state(nonce: u64, pull_slot: u64, commitment: PullCommitment) -> PlayerState {
+        PlayerState {
+            user: Pubkey::new_from_array([1; 32]),
+            gacha_state: Pubkey::default(),
+            randomness_account: Pubkey::default(),
+            payment_mint: Pubkey::default(),
+            is_settled: false,
+            is_refunded: false,
+            price_paid: 0,
+            is_claimed: false,
+            result_index: 0,
+            result_tier: 0,
+            winning_encrypted_key: String::new(),
+            bump: 0,
+            pull_slot,
+            nonce,
+            commitment,
+        }
+    }
+
+    #[test]
+    fn recompute_commit_reveal_draw_matches_the_settlement() {
+        let gacha_state_key = Pubkey::new_unique();
+        let s0 = [7u8; 32];
+        let s1 = server_seed_commitment(&s0);
+        let mut gacha = gacha_state(
+            RandomnessSource::CommitReveal,
+            vec![
+                rarity_tier(3, (0..6).collect()),
+                rarity_tier(1, (6..8).collect()),
+            ],
+        );
+        gacha.pity_threshold = 2;
+        // Pulled in epoch 0 and left unsealed; s1 is revealed afterwards
+        let mut pull = player_state(
+            4,
+            10,
+            PullCommitment {
+                client_seed: [9; 32],
+                ..Default::default()
+            },
+        );
+        gacha.seed_commitment = s1;
+        gacha.seed_epoch = 1;
+        let tiers_before = gacha.tiers.clone();
+        let stats_before = user_stats(1);
+
+        // Settle like `settle` does, sealing the pull with the SlotHashes sysvar
+        let randomness_key = Pubkey::new_unique();
+        let mut randomness_lamports = 0;
+        let mut randomness_data = Vec::new();
+        let randomness_account = AccountInfo::new(
+            &randomness_key,
+            false,
+            false,
+            &mut randomness_lamports,
+            &mut randomness_data,
+            &randomness_key,
+            false,
+            0,
+        );
+        let slot_hashes_key = slot_hashes::ID;
+        let sysvar_owner = anchor_lang::solana_program::sysvar::ID;
+        let mut slot_hashes_lamports = 0;
+        let mut slot_hashes_data = slot_hashes_data(&[12, 11, 10]);
+        let slot_hashes_account = AccountInfo::new(
+            &slot_hashes_key,
+            false,
+            false,
+            &mut slot_hashes_lamports,
+            &mut slot_hashes_data,
+            &sysvar_owner,
+            false,
+            0,
+        );
+        let random_value = resolve_pull_randomness(
+            &gacha,
+            &gacha_state_key,
+            &randomness_account,
+            &mut pull.commitment,
+            &pull.user,
+            pull.nonce,
+            pull.pull_slot,
+            Some(&slot_hashes_account),
+            12,
+        )
+        .unwrap();
+        let mut stats = stats_before.clone();
+        let settled = draw_key_with_pity(
+            &mut gacha.tiers,
+            gacha.pity_threshold,
+            &mut stats,
+            &random_value,
+        )
+        .unwrap();
+
+        // Settlement recorded the sealing slot hash, so the draw can be recomputed
+        assert_eq!(pull.commitment.slot_hash, [11; 32]);
+        assert_eq!(
+            recompute_commit_reveal_draw(
+                &s1,
+                &pull,
+                &gacha_state_key,
+                &tiers_before,
+                gacha.pity_threshold,
+                &stats_before,
+            )
+            .unwrap(),
+            settled
+        );
+
+        // An unsealed pull cannot be recomputed
+        let unsealed = player_state(4, 10, PullCommitment::default());
+        assert_eq!(
+            recompute_commit_reveal_draw(
+                &s1,
+                &unsealed,
+                &gacha_state_key,
+                &tiers_before,
+                2,
+                &stats_before
+            )
+            .unwrap_err(),
+            GachaError::PullNotSealed.into()
+        );
+    }
+
+    fn payment_config(bulk_discount_bps: u16) -> PaymentConfig {
+        PaymentConfig {
+            gacha_state: Pubkey::default(),
+            mint: anchor_lang::system_program::ID,
+            price: 0,
+            admin_recipient_account: Pubkey::default(),
+            bump: 0,
+            escrowed_amount: 0,
+            settled_amount: 0,
+            withdrawn_amount: 0,
+            bulk_discount_bps,
+            usd_pricing: None,
+        }
+    }
+
+    #[test]
+    fn multi_pull_price_applies_the_bulk_discount() {
+        assert_eq!(multi_pull_price(&payment_config(0), 100, 10).unwrap(), 1000);
+        assert_eq!(
+            multi_pull_price(&payment_config(1000), 100, 10).unwrap(),
+            900
+        );
+        // A single pull is never discounted
+        assert_eq!(
+            multi_pull_price(&payment_config(1000), 100, 1).unwrap(),
+            100
+        );
+        // Rounds down in the user's favour
+        assert_eq!(multi_pull_price(&payment_config(3333), 3, 3).unwrap(), 6);
+    }
+
+    #[test]
+    fn multi_pull_price_is_computed_without_intermediate_overflow() {
+        assert_eq!(
+            multi_pull_price(&payment_config(9000), u64::MAX, 10).unwrap(),
+            u64::MAX
+        );
+        assert!(multi_pull_price(&payment_config(0), u64::MAX, 2).is_err());
+    }
+
+    fn merkle_node(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        hashv(&[MERKLE_NODE_PREFIX, &low, &high]).to_bytes()
+    }
+
+    #[test]
+    fn verify_key_proof_accepts_sorted_pair_proofs() {
+        let keys = ["key_a", "key_b", "key_c"];
+        let leaves: Vec<[u8; 32]> = keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| key_leaf_hash(index as u16, key))
+            .collect();
+        // The odd leaf is promoted to the next level unchanged
+        let left = merkle_node(leaves[0], leaves[1]);
+        let root = merkle_node(left, leaves[2]);
+
+        assert!(verify_key_proof(&root, 0, "key_a", &[leaves[1], leaves[2]]));
+        assert!(verify_key_proof(&root, 1, "key_b", &[leaves[0], leaves[2]]));
+        assert!(verify_key_proof(&root, 2, "key_c", &[left]));
+
+        // Wrong key, wrong index, or a truncated proof
+        assert!(!verify_key_proof(
+            &root,
+            0,
+            "key_b",
+            &[leaves[1], leaves[2]]
+        ));
+        assert!(!verify_key_proof(
+            &root,
+            1,
+            "key_a",
+            &[leaves[1], leaves[2]]
+        ));
+        assert!(!verify_key_proof(&root, 0, "key_a", &[leaves[1]]));
+    }
+
+    /// A config charging `usd_price` micro-USD through the feed `price_feed`
+    fn usd_payment_config(mint: Pubkey, price_feed: Pubkey, usd_price: u64) -> PaymentConfig {
+        PaymentConfig {
+            mint,
+            usd_pricing: Some(UsdPricing {
+                usd_price,
+                price_feed,
+                max_staleness_slots: 50,
+                max_deviation_bps: 100,
+            }),
+            ..payment_config(0)
+        }
+    }
+
+    /// Prices a pull with a Switchboard pull feed whose result is `value` (18
+    /// decimals) with a sample `range`, signed at `result_slot`
+    fn usd_unit_price(
+        payment_config: &PaymentConfig,
+        decimals: u8,
+        value: i128,
+        range: i128,
+        result_slot: u64,
+        current_slot: u64,
+    ) -> Result<u64> {
+        let feed_key = payment_config.usd_pricing.unwrap().price_feed;
+        let feed_owner = get_switchboard_on_demand_program_id();
+        let feed_size = 8 + size_of::<PullFeedAccountData>();
+        // The feed is cast in place, so its data after the discriminator must be 16-byte aligned
+        let mut buffer = vec![0u8; feed_size + 16];
+        let start = (24 - buffer.as_ptr() as usize % 16) % 16;
+        let data = &mut buffer[start..start + feed_size];
+        data[..8].copy_from_slice(PullFeedAccountData::DISCRIMINATOR);
+        let result = 8 + offset_of!(PullFeedAccountData, result);
+        let mut write = |offset: usize, bytes: &[u8]| {
+            data[result + offset..result + offset + bytes.len()].copy_from_slice(bytes)
+        };
+        write(offset_of!(CurrentResult, value), &value.to_le_bytes());
+        write(offset_of!(CurrentResult, range), &range.to_le_bytes());
+        write(offset_of!(CurrentResult, slot), &result_slot.to_le_bytes());
+        let mut feed_lamports = 0;
+        let price_feed = AccountInfo::new(
+            &feed_key,
+            false,
+            false,
+            &mut feed_lamports,
+            data,
+            &feed_owner,
+            false,
+            0,
+        );
+
+        // An initialized SPL mint without authorities
+        let mut mint_data = vec![0u8; 82];
+        mint_data[44] = decimals;
+        mint_data[45] = 1;
+        let mut mint_lamports = 0;
+        let payment_mint = AccountInfo::new(
+            &payment_config.mint,
+            false,
+            false,
+            &mut mint_lamports,
+            &mut mint_data,
+            &anchor_spl::token::ID,
+            false,
+            0,
+        );
+
+        pull_unit_price(
+            payment_config,
+            &payment_mint,
+            Some(&price_feed),
+            current_slot,
+        )
+    }
+
+    #[test]
+    fn pull_unit_price_converts_usd_prices() {
+        let price_feed = Pubkey::new_unique();
+        let usd = |dollars: i128| dollars * 10i128.pow(PRECISION);
+
+        // $2.50 in SOL at $125: 0.02 SOL
+        let sol_config = usd_payment_config(anchor_lang::system_program::ID, price_feed, 2_500_000);
+        assert_eq!(
+            usd_unit_price(&sol_config, 0, usd(125), 0, 100, 100).unwrap(),
+            20_000_000
+        );
+        // $1 in SOL at $3 is rounded up to the next lamport
+        let sol_config = usd_payment_config(anchor_lang::system_program::ID, price_feed, 1_000_000);
+        assert_eq!(
+            usd_unit_price(&sol_config, 0, usd(3), 0, 100, 100).unwrap(),
+            333_333_334
+        );
+
+        // $2.50 in a 6-decimal token at $0.50: 5 tokens
+        let token_config = usd_payment_config(Pubkey::new_unique(), price_feed, 2_500_000);
+        assert_eq!(
+            usd_unit_price(&token_config, 6, usd(1) / 2, 0, 100, 100).unwrap(),
+            5_000_000
+        );
+    }
+
+    #[test]
+    fn pull_unit_price_rejects_stale_feeds() {
+        let sol_config = usd_payment_config(
+            anchor_lang::system_program::ID,
+            Pubkey::new_unique(),
+            2_500_000,
+        );
+        let value = 125 * 10i128.pow(PRECISION);
+
+        // Signed exactly `max_staleness_slots` ago is still fresh
+        assert!(usd_unit_price(&sol_config, 0, value, 0, 100, 150).is_ok());
+        assert_eq!(
+            usd_unit_price(&sol_config, 0, value, 0, 100, 151).unwrap_err(),
+            GachaError::StalePriceFeed.into()
+        );
+        // A feed that never reported
+        assert_eq!(
+            usd_unit_price(&sol_config, 0, value, 0, 0, 10).unwrap_err(),
+            GachaError::StalePriceFeed.into()
+        );
+    }
+
+    #[test]
+    fn pull_unit_price_rejects_deviating_feeds() {
+        let sol_config = usd_payment_config(
+            anchor_lang::system_program::ID,
+            Pubkey::new_unique(),
+            2_500_000,
+        );
+        let value = 125 * 10i128.pow(PRECISION);
+
+        // Samples spread over 1% of the value are allowed with a 100 bps limit
+        assert!(usd_unit_price(&sol_config, 0, value, value / 100, 100, 100).is_ok());
+        assert_eq!(
+            usd_unit_price(&sol_config, 0, value, value / 100 + 1, 100, 100).unwrap_err(),
+            GachaError::PriceDeviationTooLarge.into()
+        );
+    }
+
+    #[test]
+    fn check_instant_pull_transaction_accepts_a_last_top_level_pull() {
+        assert!(check_instant_pull_transaction(1, 0, &[crate::ID]).is_ok());
+        // Compute budget and payment setup may come first
+        assert!(check_instant_pull_transaction(
+            1,
+            2,
+            &[
+                COMPUTE_BUDGET_PROGRAM_ID,
+                anchor_spl::associated_token::ID,
+                crate::ID
+            ]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn check_instant_pull_transaction_rejects_revertable_pulls() {
+        let other_program = Pubkey::new_unique();
+
+        // Invoked through CPI by a program that could inspect the draw
+        assert_eq!(
+            check_instant_pull_transaction(2, 0, &[other_program]).unwrap_err(),
+            GachaError::InstantPullNotTopLevel.into()
+        );
+        // Followed by another program's instruction
+        assert_eq!(
+            check_instant_pull_transaction(1, 0, &[crate::ID, other_program]).unwrap_err(),
+            GachaError::InstantPullNotTopLevel.into()
+        );
+        // Preceded by an unknown program or another gacha instruction
+        assert_eq!(
+            check_instant_pull_transaction(1, 1, &[other_program, crate::ID]).unwrap_err(),
+            GachaError::InstantPullUnsafeTransaction.into()
+        );
+        assert_eq!(
+            check_instant_pull_transaction(1, 1, &[crate::ID, crate::ID]).unwrap_err(),
+            GachaError::InstantPullUnsafeTransaction.into()
+        );
+    }
+
+    fn rarity_tier(weight: u32, remaining_indices: Vec<u16>) -> RarityTier {
+        RarityTier {
+            weight,
+            key_count: remaining_indices.len() as u16,
+            remaining_indices,
+        }
+    }
+
+    fn user_stats(pulls_since_top_tier: u32) -> UserStats {
+        UserStats {
+            user: Pubkey::default(),
+            gacha_state: Pubkey::default(),
+            pulls_since_top_tier,
+            total_settled: 0,
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn draw_key_with_pity_forces_the_top_tier_and_resets_the_counter() {
+        // Zero bytes always roll the common tier and the first remaining key
+        let random_bytes = [0u8; 32];
+        let mut tiers = vec![rarity_tier(1000, vec![0, 1, 2, 3]), rarity_tier(1, vec![4])];
+        let mut user_stats = user_stats(0);
+
+        assert_eq!(
+            draw_key_with_pity(&mut tiers, 3, &mut user_stats, &random_bytes).unwrap(),
+            (0, 0, false)
+        );
+        assert_eq!(
+            draw_key_with_pity(&mut tiers, 3, &mut user_stats, &random_bytes).unwrap(),
+            (0, 3, false)
+        );
+        assert_eq!(
+            draw_key_with_pity(&mut tiers, 3, &mut user_stats, &random_bytes).unwrap(),
+            (0, 2, false)
+        );
+        assert_eq!(user_stats.pulls_since_top_tier, 3);
+
+        // The pull after three unlucky pulls in a row is drawn from the top tier
+        assert_eq!(
+            draw_key_with_pity(&mut tiers, 3, &mut user_stats, &random_bytes).unwrap(),
+            (1, 4, true)
+        );
+        assert_eq!(user_stats.pulls_since_top_tier, 0);
+        assert_eq!(user_stats.total_settled, 4);
+    }
+
+    #[test]
+    fn draw_key_with_pity_falls_back_to_a_regular_draw() {
+        let random_bytes = [0u8; 32];
+        let mut user_stats = user_stats(5);
+
+        // Disabled pity
+        let mut tiers = vec![rarity_tier(1000, vec![0, 1]), rarity_tier(1, vec![2])];
+        assert_eq!(
+            draw_key_with_pity(&mut tiers, 0, &mut user_stats, &random_bytes).unwrap(),
+            (0, 0, false)
+        );
+        // Exhausted top tier
+        let mut tiers = vec![rarity_tier(1000, vec![0, 1]), rarity_tier(1, vec![])];
+        assert_eq!(
+            draw_key_with_pity(&mut tiers, 3, &mut user_stats, &random_bytes).unwrap(),
+            (0, 0, false)
+        );
+        assert_eq!(user_stats.pulls_since_top_tier, 7);
+    }
+
+    /// Switchboard randomness account data seeded at `seed_slot` and last revealed at `reveal_slot`
+    fn randomness_data(seed_slot: u64, reveal_slot: u64) -> Vec<u8> {
+        let mut data = vec![0u8; RandomnessAccountData::size()];
+        data[..8].copy_from_slice(RandomnessAccountData::DISCRIMINATOR);
+        // Offsets of `seed_slot` and `reveal_slot` after the discriminator
+        data[104..112].copy_from_slice(&seed_slot.to_le_bytes());
+        data[144..152].copy_from_slice(&reveal_slot.to_le_bytes());
+        data
+    }
+
+    fn bind(
+        randomness_usage: &mut RandomnessUsage,
+        seed_slot: u64,
+        reveal_slot: u64,
+        current_slot: u64,
+    ) -> Result<u64> {
+        let key = Pubkey::new_unique();
+        let owner = get_switchboard_on_demand_program_id();
+        let mut lamports = 0;
+        let mut data = randomness_data(seed_slot, reveal_slot);
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        bind_switchboard_randomness(&account, randomness_usage, 255, current_slot)
+    }
+
+    fn unused_randomness() -> RandomnessUsage {
+        RandomnessUsage {
+            randomness_account: Pubkey::default(),
+            seed_slot: 0,
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn bind_switchboard_randomness_records_the_commitment() {
+        let mut randomness_usage = unused_randomness();
+        assert_eq!(bind(&mut randomness_usage, 100, 90, 101).unwrap(), 100);
+        assert_eq!(randomness_usage.seed_slot, 100);
+        assert_eq!(randomness_usage.bump, 255);
+    }
+
+    #[test]
+    fn bind_switchboard_randomness_rejects_revealed_and_reused_commitments() {
+        // Already revealed: the reveal landed after the commitment's seed slot
+        assert_eq!(
+            bind(&mut unused_randomness(), 100, 101, 102).unwrap_err(),
+            GachaError::RandomnessAlreadyRevealed.into()
+        );
+
+        // Reused: the same commitment was consumed by an earlier pull
+        let mut randomness_usage = unused_randomness();
+        bind(&mut randomness_usage, 100, 90, 101).unwrap();
+        assert_eq!(
+            bind(&mut randomness_usage, 100, 90, 102).unwrap_err(),
+            GachaError::RandomnessAlreadyUsed.into()
+        );
+        // A new commitment of the same account can be bound again
+        assert_eq!(bind(&mut randomness_usage, 105, 101, 106).unwrap(), 105);
+
+        // Stale or future commitments
+        assert_eq!(
+            bind(
+                &mut unused_randomness(),
+                100,
+                90,
+                100 + MAX_SLOT_DIFFERENCE + 1
+            )
+            .unwrap_err(),
+            GachaError::RandomnessNotReady.into()
+        );
+        assert_eq!(
+            bind(&mut unused_randomness(), 100, 90, 99).unwrap_err(),
+            GachaError::RandomnessNotReady.into()
+        );
+    }
+}
@@ -1,33 +1,60 @@
 use anchor_lang::prelude::*;
+use anchor_lang::system_program;
 
-use crate::{constants::*, contexts::*, errors::GachaError, events::*};
+use crate::{
+    constants::*,
+    contexts::*,
+    errors::GachaError,
+    events::*,
+    helpers::*,
+    states::{
+        GachaState, KeyStorage, PaymentConfig, RandomnessSource, RarityTier, Role, RoleGrant,
+        UsdPricing,
+    },
+};
+use switchboard_on_demand::{accounts::PullFeedAccountData, get_switchboard_on_demand_program_id};
 
-/// ========================================
-/// Admin Instructions
-/// ========================================
+// ========================================
+// Admin Instructions
+// ========================================
 
 /// Initialize a new gacha machine
 ///
 /// This instruction creates a new gacha machine with the caller as admin.
 /// The machine starts in an unfinalized state where keys can be added.
+/// Each machine is addressed by its creator and a creator-chosen id, so a
+/// single program deployment can host any number of independent machines.
 ///
 /// Args:
 /// - ctx: Context containing gacha_state PDA and admin accounts
+/// - machine_id: Identifier of the machine among those created by the admin
 ///
 /// Returns: Result indicating success or failure
-pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+pub fn initialize(ctx: Context<Initialize>, machine_id: u64) -> Result<()> {
     let gacha_state = &mut ctx.accounts.gacha_state;
 
     // Set the admin as the signer of this transaction
     gacha_state.admin = ctx.accounts.admin.key();
+    gacha_state.pending_admin = None;
+    gacha_state.creator = ctx.accounts.admin.key();
+    gacha_state.machine_id = machine_id;
     gacha_state.bump = ctx.bumps.gacha_state;
     gacha_state.is_finalized = false;
+    gacha_state.key_storage = KeyStorage::Paged;
+    gacha_state.randomness_source = RandomnessSource::Switchboard;
+    // Machines start with a single tier, i.e. uniform selection
+    gacha_state.tiers = vec![RarityTier {
+        weight: 1,
+        key_count: 0,
+        remaining_indices: Vec::new(),
+    }];
     gacha_state.pull_count = 0;
     gacha_state.settle_count = 0;
     gacha_state.is_paused = false;
 
     emit!(GachaInitialized {
         admin: ctx.accounts.admin.key(),
+        machine_id,
         gacha_state: gacha_state.key(),
     });
     Ok(())
@@ -37,9 +64,12 @@ pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
 ///
 /// Payment configs define what tokens can be used to pay for pulls,
 /// their prices, and where payments are sent. Supports both SOL and SPL tokens.
+/// The mint must be a real token mint (or the System Program for SOL) and the
+/// recipient must be able to receive it, so misconfigurations fail here instead
+/// of in every pull.
 ///
 /// Args:
-/// - ctx: Context containing payment_config PDA, gacha_state, and admin
+/// - ctx: Context containing payment_config PDA, gacha_state, and authority (admin or pricing manager)
 /// - payment_mint: The mint address (SystemProgram::id() for SOL, mint pubkey for SPL)
 /// - payment_price: Price in lamports (for SOL) or smallest token units (for SPL)
 /// - payment_recipient_account: Where payments are sent (admin pubkey for SOL, ATA for SPL)
@@ -54,6 +84,16 @@ pub fn add_payment_config(
     let payment_config = &mut ctx.accounts.payment_config;
     let gacha_state = &mut ctx.accounts.gacha_state;
 
+    // ============ VALIDATIONS ============
+    // An existing config is left untouched; use update_payment_config to change it
+    require!(
+        payment_config.gacha_state == Pubkey::default()
+            && !gacha_state.payment_configs.contains(&payment_config.key()),
+        GachaError::PaymentConfigAlreadyExists
+    );
+    validate_payment_mint(&ctx.accounts.payment_mint_account)?;
+    validate_payment_recipient(&payment_mint, &ctx.accounts.recipient_account)?;
+
     // Initialize the payment configuration
     payment_config.gacha_state = gacha_state.key();
     payment_config.mint = payment_mint;
@@ -65,7 +105,7 @@ pub fn add_payment_config(
     gacha_state.payment_configs.push(payment_config.key());
 
     emit!(PaymentConfigAdded {
-        admin: ctx.accounts.admin.key(),
+        authority: ctx.accounts.authority.key(),
         payment_mint,
         payment_price,
         payment_recipient_account,
@@ -77,10 +117,12 @@ pub fn add_payment_config(
 /// Remove a payment configuration from the gacha machine
 ///
 /// Completely removes a payment method from the gacha machine and closes the account
-/// to reclaim rent. Updates the gacha state to remove the reference.
+/// to reclaim rent. Updates the gacha state to remove the reference. The vault
+/// token account of an SPL config is closed along with it.
 ///
 /// Args:
-/// - ctx: Context containing payment_config to remove and gacha_state to update
+/// - ctx: Context containing payment_config to remove and gacha_state to update;
+///   SPL configs also pass their vault, mint and token program
 /// - payment_mint: The mint address of the config to remove
 ///
 /// Returns: Result indicating success or failure
@@ -91,6 +133,24 @@ pub fn remove_payment_config(
     let gacha_state = &mut ctx.accounts.gacha_state;
     let payment_config = &ctx.accounts.payment_config;
 
+    // SPL configs hold their payments in an associated token account
+    if payment_config.mint != system_program::ID {
+        let (Some(payment_vault), Some(payment_mint_account), Some(token_program)) = (
+            ctx.accounts.payment_vault.as_ref(),
+            ctx.accounts.payment_mint_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+        ) else {
+            return err!(GachaError::PaymentVaultAccountsMissing);
+        };
+        close_payment_vault(
+            payment_config,
+            payment_vault,
+            payment_mint_account,
+            &token_program.to_account_info(),
+            &ctx.accounts.authority.to_account_info(),
+        )?;
+    }
+
     // Find and remove the payment config from the gacha_state's payment_configs vector
     if let Some(index) = gacha_state
         .payment_configs
@@ -103,8 +163,258 @@ pub fn remove_payment_config(
     }
 
     emit!(PaymentConfigRemoved {
-        admin: ctx.accounts.admin.key(),
+        authority: ctx.accounts.authority.key(),
+        payment_mint,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Update the price and/or recipient of a payment configuration
+///
+/// Changes the config in place so the currency stays accepted throughout, instead
+/// of removing and re-adding it. The new recipient, when given, is validated
+/// against the config's mint before it is stored.
+///
+/// Args:
+/// - ctx: Context containing payment_config and the optional new recipient account
+/// - payment_mint: The mint address of the config to update
+/// - payment_price: New price (None keeps the current price)
+///
+/// Returns: Result indicating success or failure
+///
+/// Constraints:
+/// - Changing the price requires the pricing manager role
+/// - Changing the recipient requires the treasurer role, since it redirects revenue
+pub fn update_payment_config(
+    ctx: Context<UpdatePaymentConfig>,
+    payment_mint: Pubkey,
+    payment_price: Option<u64>,
+) -> Result<()> {
+    let gacha_state = &ctx.accounts.gacha_state;
+    let authority = ctx.accounts.authority.key();
+    let payment_config = &mut ctx.accounts.payment_config;
+    require!(
+        payment_price.is_some() || ctx.accounts.new_recipient_account.is_some(),
+        GachaError::InvalidPaymentConfig
+    );
+    if payment_price.is_some() {
+        require!(
+            gacha_state.has_role(&authority, Role::PricingManager),
+            GachaError::Unauthorized
+        );
+    }
+    if ctx.accounts.new_recipient_account.is_some() {
+        require!(
+            gacha_state.has_role(&authority, Role::Treasurer),
+            GachaError::Unauthorized
+        );
+    }
+
+    let old_price = payment_config.price;
+    let old_recipient_account = payment_config.admin_recipient_account;
+
+    if let Some(payment_price) = payment_price {
+        payment_config.price = payment_price;
+    }
+    if let Some(new_recipient_account) = &ctx.accounts.new_recipient_account {
+        validate_payment_recipient(&payment_config.mint, new_recipient_account)?;
+        payment_config.admin_recipient_account = new_recipient_account.key();
+    }
+
+    emit!(PaymentConfigUpdated {
+        authority,
+        payment_mint,
+        old_price,
+        new_price: payment_config.price,
+        old_recipient_account,
+        new_recipient_account: payment_config.admin_recipient_account,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Set the bulk discount of a payment configuration
+///
+/// The discount applies to multi-pulls of more than one pull and is expressed in
+/// basis points of the full `count * price`.
+///
+/// Args:
+/// - ctx: Context containing payment_config, gacha_state, and authority (admin or pricing manager)
+/// - payment_mint: The mint address of the payment config to update
+/// - bulk_discount_bps: Discount in basis points (must be below 10_000)
+///
+/// Returns: Result indicating success or failure
+pub fn set_bulk_discount(
+    ctx: Context<SetBulkDiscount>,
+    payment_mint: Pubkey,
+    bulk_discount_bps: u16,
+) -> Result<()> {
+    require!(
+        (bulk_discount_bps as u64) < BPS_DENOMINATOR,
+        GachaError::InvalidDiscount
+    );
+
+    ctx.accounts.payment_config.bulk_discount_bps = bulk_discount_bps;
+
+    emit!(BulkDiscountUpdated {
+        authority: ctx.accounts.authority.key(),
+        payment_mint,
+        bulk_discount_bps,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Set or clear the USD pricing of a payment configuration
+///
+/// With USD pricing the charge of every pull is converted from `usd_price` using
+/// the Switchboard pull feed at pull time, so the token price tracks the USD
+/// target automatically. Clearing it falls back to the fixed `price`.
+///
+/// Args:
+/// - ctx: Context containing payment_config and the price feed of the new pricing
+/// - payment_mint: The mint address of the payment config to update
+/// - usd_pricing: New USD pricing (None switches back to the fixed price)
+///
+/// Returns: Result indicating success or failure
+pub fn set_usd_pricing(
+    ctx: Context<SetUsdPricing>,
+    payment_mint: Pubkey,
+    usd_pricing: Option<UsdPricing>,
+) -> Result<()> {
+    if let Some(usd_pricing) = usd_pricing {
+        require!(
+            usd_pricing.usd_price > 0
+                && usd_pricing.max_staleness_slots > 0
+                && (usd_pricing.max_deviation_bps as u64) <= BPS_DENOMINATOR,
+            GachaError::InvalidUsdPricing
+        );
+
+        // The feed must be a Switchboard pull feed so pulls don't fail on a typo
+        let price_feed = ctx
+            .accounts
+            .price_feed
+            .as_ref()
+            .ok_or(GachaError::PriceFeedMissing)?;
+        require_keys_eq!(
+            price_feed.key(),
+            usd_pricing.price_feed,
+            GachaError::InvalidPriceFeed
+        );
+        require_keys_eq!(
+            *price_feed.owner,
+            get_switchboard_on_demand_program_id(),
+            GachaError::InvalidPriceFeed
+        );
+        PullFeedAccountData::parse(price_feed.data.borrow())
+            .map_err(|_| GachaError::InvalidPriceFeed)?;
+    }
+
+    ctx.accounts.payment_config.usd_pricing = usd_pricing;
+
+    emit!(UsdPricingUpdated {
+        authority: ctx.accounts.authority.key(),
         payment_mint,
+        usd_pricing,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Withdraw settled payments from a payment vault
+///
+/// Sends funds from the vault of a payment config to its configured recipient.
+/// Only payments released by settled pulls can be withdrawn; payments of unsettled
+/// pulls stay in escrow so they can still be refunded.
+///
+/// Args:
+/// - ctx: Context containing payment_config, its vault, and the recipient
+/// - payment_mint: The mint address of the payment config to withdraw from
+/// - amount: Amount to withdraw in lamports (SOL) or smallest token units (SPL)
+///
+/// Returns: Result indicating success or failure
+pub fn withdraw(ctx: Context<Withdraw>, payment_mint: Pubkey, amount: u64) -> Result<()> {
+    require!(
+        amount <= ctx.accounts.payment_config.withdrawable_amount(),
+        GachaError::InsufficientWithdrawableFunds
+    );
+
+    if payment_mint == system_program::ID {
+        require_keys_eq!(
+            ctx.accounts.payment_vault.key(),
+            ctx.accounts.payment_config.key(),
+            GachaError::InvalidPaymentVault
+        );
+        release_sol_from_vault(
+            &ctx.accounts.payment_config,
+            &ctx.accounts.admin_recipient_account,
+            amount,
+        )?;
+    } else {
+        let token_program = ctx
+            .accounts
+            .token_program
+            .as_ref()
+            .ok_or(GachaError::TokenProgramMissing)?;
+        release_spl_from_vault(
+            &ctx.accounts.payment_config,
+            &ctx.accounts.payment_vault,
+            &ctx.accounts.payment_mint_account,
+            &ctx.accounts.admin_recipient_account,
+            &token_program.to_account_info(),
+            amount,
+        )?;
+    }
+
+    let payment_config = &mut ctx.accounts.payment_config;
+    payment_config.withdrawn_amount += amount;
+
+    emit!(Withdrawn {
+        authority: ctx.accounts.authority.key(),
+        payment_mint,
+        amount,
+        recipient: payment_config.admin_recipient_account,
+        total_withdrawn: payment_config.withdrawn_amount,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Create the next key page of the gacha machine
+///
+/// Key pages hold up to `KEYS_PER_PAGE` encrypted keys each. Pages are created
+/// sequentially, so the new page's index is the machine's current page count.
+///
+/// Args:
+/// - ctx: Context containing the key_page PDA to create and gacha_state
+///
+/// Returns: Result indicating success or failure
+///
+/// Constraints:
+/// - Machine must not be finalized
+/// - Must not exceed MAX_KEY_PAGES limit
+pub fn add_key_page(ctx: Context<AddKeyPage>) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let key_page = &mut ctx.accounts.key_page;
+
+    require!(!gacha_state.is_finalized, GachaError::GachaAlreadyFinalized);
+
+    key_page.gacha_state = gacha_state.key();
+    key_page.page_index = gacha_state.key_page_count;
+    key_page.bump = ctx.bumps.key_page;
+
+    gacha_state.key_page_count += 1;
+
+    emit!(KeyPageAdded {
+        authority: ctx.accounts.authority.key(),
+        page_index: key_page.page_index,
+        key_page: key_page.key(),
         gacha_state: gacha_state.key()
     });
 
@@ -115,35 +425,181 @@ pub fn remove_payment_config(
 ///
 /// Keys represent rewards that users can win. They are stored encrypted
 /// and only revealed when a user wins them through the settle process.
+/// The key is appended to the key page that holds the next key index.
 ///
 /// Args:
-/// - ctx: Context containing gacha_state to add the key to
+/// - ctx: Context containing gacha_state and the key_page to add the key to
 /// - encrypted_key: The encrypted reward key as a string
+/// - tier: Rarity tier of the key (index into the configured tiers)
 ///
 /// Returns: Result indicating success or failure
 ///
 /// Constraints:
 /// - Machine must not be finalized
-/// - Key cannot be empty
+/// - Key cannot be empty or longer than MAX_KEY_LENGTH
+/// - Tier must be configured
 /// - Must not exceed MAX_KEYS limit
-pub fn add_key(ctx: Context<AddKey>, encrypted_key: String) -> Result<()> {
+pub fn add_key(ctx: Context<AddKey>, encrypted_key: String, tier: u8) -> Result<()> {
     let gacha_state = &mut ctx.accounts.gacha_state;
+    let key_page = &mut ctx.accounts.key_page;
 
     // Validation: ensure machine is in the correct state for adding keys
     require!(!gacha_state.is_finalized, GachaError::GachaAlreadyFinalized);
     require!(!encrypted_key.is_empty(), GachaError::EmptyKeyProvided);
     require!(
-        gacha_state.encrypted_keys.len() < MAX_KEYS,
+        encrypted_key.len() <= MAX_KEY_LENGTH,
+        GachaError::KeyTooLong
+    );
+    require!(
+        (gacha_state.total_keys as usize) < MAX_KEYS,
         GachaError::KeyPoolFull
     );
+    require!(key_page.keys.len() < KEYS_PER_PAGE, GachaError::KeyPageFull);
+    require!(
+        (tier as usize) < gacha_state.tiers.len(),
+        GachaError::InvalidTier
+    );
 
-    // Add the key to the pool
-    gacha_state.encrypted_keys.push(encrypted_key.clone());
+    // Add the key to the page and its index to the tier's pool
+    let key_index = gacha_state.total_keys;
+    key_page.keys.push(encrypted_key.clone());
+    let rarity_tier = &mut gacha_state.tiers[tier as usize];
+    rarity_tier.remaining_indices.push(key_index);
+    rarity_tier.key_count += 1;
+    gacha_state.total_keys += 1;
 
     emit!(KeyAdded {
-        admin: ctx.accounts.admin.key(),
+        authority: ctx.accounts.authority.key(),
         key: encrypted_key,
-        total_keys: gacha_state.encrypted_keys.len() as u16,
+        tier,
+        total_keys: gacha_state.total_keys,
+        page_index: key_page.page_index,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Add a batch of encrypted keys to the gacha machine's reward pool
+///
+/// Behaves like `add_key` for every entry, but reallocates the accounts once and
+/// emits a single summarized event. The whole batch is validated before any key
+/// is stored, so either every key is added or none is.
+///
+/// Args:
+/// - ctx: Context containing gacha_state and the key_page to add the keys to
+/// - encrypted_keys: The encrypted reward keys, in insertion order
+/// - tier: Rarity tier of every key in the batch
+///
+/// Returns: Result indicating success or failure
+///
+/// Constraints:
+/// - Machine must not be finalized
+/// - No key can be empty or longer than MAX_KEY_LENGTH
+/// - The batch must fit in the current key page and within MAX_KEYS
+pub fn add_keys(ctx: Context<AddKeys>, encrypted_keys: Vec<String>, tier: u8) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let key_page = &mut ctx.accounts.key_page;
+
+    // Validation: ensure machine is in the correct state and the whole batch is valid
+    require!(!gacha_state.is_finalized, GachaError::GachaAlreadyFinalized);
+    require!(!encrypted_keys.is_empty(), GachaError::EmptyKeyProvided);
+    for encrypted_key in &encrypted_keys {
+        require!(!encrypted_key.is_empty(), GachaError::EmptyKeyProvided);
+        require!(
+            encrypted_key.len() <= MAX_KEY_LENGTH,
+            GachaError::KeyTooLong
+        );
+    }
+    require!(
+        gacha_state.total_keys as usize + encrypted_keys.len() <= MAX_KEYS,
+        GachaError::KeyPoolFull
+    );
+    require!(
+        key_page.keys.len() + encrypted_keys.len() <= KEYS_PER_PAGE,
+        GachaError::KeyPageFull
+    );
+    require!(
+        (tier as usize) < gacha_state.tiers.len(),
+        GachaError::InvalidTier
+    );
+
+    // Add the keys to the page and their indices to the tier's pool
+    let start_index = gacha_state.total_keys;
+    let count = encrypted_keys.len() as u16;
+    key_page.keys.extend(encrypted_keys);
+    let rarity_tier = &mut gacha_state.tiers[tier as usize];
+    rarity_tier
+        .remaining_indices
+        .extend(start_index..start_index + count);
+    rarity_tier.key_count += count;
+    gacha_state.total_keys += count;
+
+    emit!(KeysAdded {
+        authority: ctx.accounts.authority.key(),
+        start_index,
+        count,
+        tier,
+        total_keys: gacha_state.total_keys,
+        page_index: key_page.page_index,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Configure the rarity tiers and their drop weights
+///
+/// Sets one weight per tier, which also defines the number of tiers. Weights are
+/// relative: a tier is drawn with probability `weight / sum of weights` among the
+/// tiers that still have keys. Tiers can be added at any time before finalization,
+/// but a tier can only be dropped while it holds no keys.
+///
+/// Args:
+/// - ctx: Context containing gacha_state to configure
+/// - weights: Drop weight of each tier (tier 0 first, top tier last)
+///
+/// Returns: Result indicating success or failure
+///
+/// Constraints:
+/// - Machine must not be finalized
+/// - Between 1 and MAX_TIERS weights, all non-zero
+pub fn set_tier_weights(ctx: Context<SetTierWeights>, weights: Vec<u32>) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+
+    require!(!gacha_state.is_finalized, GachaError::GachaAlreadyFinalized);
+    require!(
+        !weights.is_empty() && weights.len() <= MAX_TIERS,
+        GachaError::InvalidTierWeights
+    );
+    require!(
+        weights.iter().all(|&weight| weight > 0),
+        GachaError::InvalidTierWeights
+    );
+    require!(
+        gacha_state
+            .tiers
+            .iter()
+            .skip(weights.len())
+            .all(|tier| tier.key_count == 0),
+        GachaError::InvalidTierWeights
+    );
+
+    gacha_state.tiers.truncate(weights.len());
+    for (tier_index, &weight) in weights.iter().enumerate() {
+        match gacha_state.tiers.get_mut(tier_index) {
+            Some(rarity_tier) => rarity_tier.weight = weight,
+            None => gacha_state.tiers.push(RarityTier {
+                weight,
+                key_count: 0,
+                remaining_indices: Vec::new(),
+            }),
+        }
+    }
+
+    emit!(TierWeightsUpdated {
+        authority: ctx.accounts.authority.key(),
+        weights,
         gacha_state: gacha_state.key()
     });
 
@@ -153,40 +609,186 @@ pub fn add_key(ctx: Context<AddKey>, encrypted_key: String) -> Result<()> {
 /// Finalize the gacha machine to enable pulling
 ///
 /// Once finalized, no more keys can be added and users can start pulling.
-/// This creates the remaining_indices vector used for fair randomization.
+/// The tier pools used for randomization were filled while adding keys.
 ///
 /// Args:
 /// - ctx: Context containing gacha_state to finalize
+/// - server_seed_hash: Commitment of the server seed chain (commit-reveal machines only)
 ///
 /// Returns: Result indicating success or failure
 ///
 /// Constraints:
 /// - Machine must not already be finalized
 /// - At least one key must be in the pool
-pub fn finalize(ctx: Context<Finalize>) -> Result<()> {
+/// - server_seed_hash must be given exactly when the machine uses commit-reveal
+pub fn finalize(ctx: Context<Finalize>, server_seed_hash: Option<[u8; 32]>) -> Result<()> {
     let gacha_state = &mut ctx.accounts.gacha_state;
 
     // Validation: ensure machine is ready for finalization
     require!(!gacha_state.is_finalized, GachaError::GachaAlreadyFinalized);
+    require!(gacha_state.total_keys > 0, GachaError::NoKeysInPool);
+    commit_server_seed(gacha_state, &ctx.accounts.authority.key(), server_seed_hash)?;
+
+    let total_keys = gacha_state.total_keys;
+    gacha_state.is_finalized = true;
+
+    emit!(GachaFinalized {
+        authority: ctx.accounts.authority.key(),
+        total_keys,
+        total_pages: gacha_state.key_page_count,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Finalize the gacha machine with a Merkle-committed key list
+///
+/// Alternative to adding keys and calling `finalize`: the key manager commits only the
+/// Merkle root of the encrypted key list and its length. Keys stay off-chain until
+/// a winner claims theirs with `claim_key_with_proof`. The committed list is ordered
+/// by tier: the first `tier_key_counts[0]` keys belong to tier 0, and so on.
+///
+/// Args:
+/// - ctx: Context containing gacha_state to finalize
+/// - key_root: Merkle root over `key_leaf_hash(index, encrypted_key)` leaves
+/// - total_keys: Number of keys in the committed list
+/// - tier_key_counts: Number of keys in each tier (must sum to total_keys)
+/// - server_seed_hash: Commitment of the server seed chain (commit-reveal machines only)
+///
+/// Returns: Result indicating success or failure
+///
+/// Constraints:
+/// - Machine must not already be finalized
+/// - No keys or key pages may have been added on-chain
+/// - total_keys must be between 1 and MAX_COMMITTED_KEYS
+/// - tier_key_counts must have one entry per configured tier
+/// - server_seed_hash must be given exactly when the machine uses commit-reveal
+pub fn finalize_with_root(
+    ctx: Context<FinalizeWithRoot>,
+    key_root: [u8; 32],
+    total_keys: u16,
+    tier_key_counts: Vec<u16>,
+    server_seed_hash: Option<[u8; 32]>,
+) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+
+    // Validation: ensure machine is ready for finalization
+    require!(!gacha_state.is_finalized, GachaError::GachaAlreadyFinalized);
+    require!(
+        gacha_state.total_keys == 0 && gacha_state.key_page_count == 0,
+        GachaError::KeyStorageMismatch
+    );
+    require!(total_keys > 0, GachaError::NoKeysInPool);
+    require!(
+        total_keys as usize <= MAX_COMMITTED_KEYS,
+        GachaError::KeyPoolFull
+    );
+    require!(
+        tier_key_counts.len() == gacha_state.tiers.len(),
+        GachaError::InvalidTier
+    );
     require!(
-        !gacha_state.encrypted_keys.is_empty(),
-        GachaError::NoKeysInPool
+        tier_key_counts
+            .iter()
+            .map(|&count| count as usize)
+            .sum::<usize>()
+            == total_keys as usize,
+        GachaError::InvalidTier
     );
+    commit_server_seed(gacha_state, &ctx.accounts.authority.key(), server_seed_hash)?;
+
+    // Assign consecutive index ranges to the tiers
+    let mut next_index = 0u16;
+    for (rarity_tier, &count) in gacha_state.tiers.iter_mut().zip(tier_key_counts.iter()) {
+        rarity_tier.key_count = count;
+        rarity_tier.remaining_indices = (next_index..next_index + count).collect();
+        next_index += count;
+    }
 
-    // Create indices array for randomized selection (Fisher-Yates shuffle implementation)
-    let total_keys = gacha_state.encrypted_keys.len() as u16;
-    gacha_state.remaining_indices = (0..total_keys).collect();
+    gacha_state.key_storage = KeyStorage::Merkle;
+    gacha_state.key_root = key_root;
+    gacha_state.total_keys = total_keys;
     gacha_state.is_finalized = true;
 
+    emit!(KeyRootCommitted {
+        authority: ctx.accounts.authority.key(),
+        key_root,
+        total_keys,
+        gacha_state: gacha_state.key()
+    });
     emit!(GachaFinalized {
-        admin: ctx.accounts.admin.key(),
+        authority: ctx.accounts.authority.key(),
         total_keys,
-        gacha_state: ctx.accounts.gacha_state.key()
+        total_pages: 0,
+        gacha_state: gacha_state.key()
     });
 
     Ok(())
 }
 
+/// Reveal the next server seed of a commit-reveal machine
+///
+/// The seed must hash to the current commitment and becomes the new commitment,
+/// so seeds are revealed one batch at a time down the hash chain committed at
+/// finalization. Every pull made before this reveal can then be settled. A single
+/// reveal at the end of the sale works the same way.
+///
+/// Args:
+/// - ctx: Context containing gacha_state and authority (admin or key manager)
+/// - server_seed: Preimage of the current seed commitment
+///
+/// Returns: Result indicating success or failure
+pub fn reveal_server_seed(ctx: Context<RevealServerSeed>, server_seed: [u8; 32]) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    require!(gacha_state.is_finalized, GachaError::GachaNotFinalized);
+    require!(
+        gacha_state.randomness_source == RandomnessSource::CommitReveal,
+        GachaError::ServerSeedCommitmentMismatch
+    );
+    require!(
+        server_seed_commitment(&server_seed) == gacha_state.seed_commitment,
+        GachaError::InvalidServerSeed
+    );
+
+    gacha_state.seed_commitment = server_seed;
+    gacha_state.seed_epoch += 1;
+
+    emit!(ServerSeedRevealed {
+        authority: ctx.accounts.authority.key(),
+        server_seed,
+        seed_epoch: gacha_state.seed_epoch,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Stores the server seed commitment of a commit-reveal machine at finalization
+fn commit_server_seed(
+    gacha_state: &mut Account<GachaState>,
+    authority: &Pubkey,
+    server_seed_hash: Option<[u8; 32]>,
+) -> Result<()> {
+    let is_commit_reveal = gacha_state.randomness_source == RandomnessSource::CommitReveal;
+    require!(
+        is_commit_reveal == server_seed_hash.is_some(),
+        GachaError::ServerSeedCommitmentMismatch
+    );
+
+    if let Some(seed_commitment) = server_seed_hash {
+        gacha_state.seed_commitment = seed_commitment;
+        gacha_state.seed_epoch = 0;
+
+        emit!(ServerSeedCommitted {
+            authority: *authority,
+            seed_commitment,
+            gacha_state: gacha_state.key()
+        });
+    }
+    Ok(())
+}
+
 /// Set the paused state of the gacha machine
 ///
 /// When paused, users cannot perform pull operations.
@@ -197,11 +799,11 @@ pub fn finalize(ctx: Context<Finalize>) -> Result<()> {
 /// - paused: Whether to pause (true) or unpause (false) the machine
 ///
 /// Returns: Result indicating success or failure
-pub fn set_paused(ctx: Context<AdminAction>, paused: bool) -> Result<()> {
+pub fn set_paused(ctx: Context<PauserAction>, paused: bool) -> Result<()> {
     ctx.accounts.gacha_state.is_paused = paused;
 
     emit!(GachaPaused {
-        admin: ctx.accounts.admin.key(),
+        authority: ctx.accounts.authority.key(),
         paused,
         gacha_state: ctx.accounts.gacha_state.key()
     });
@@ -219,11 +821,11 @@ pub fn set_paused(ctx: Context<AdminAction>, paused: bool) -> Result<()> {
 /// - halted: Whether to halt (true) or unhalt (false) the machine
 ///
 /// Returns: Result indicating success or failure
-pub fn set_halted(ctx: Context<AdminAction>, halted: bool) -> Result<()> {
+pub fn set_halted(ctx: Context<PauserAction>, halted: bool) -> Result<()> {
     ctx.accounts.gacha_state.is_halted = halted;
 
     emit!(GachaHalted {
-        admin: ctx.accounts.admin.key(),
+        authority: ctx.accounts.authority.key(),
         halted,
         gacha_state: ctx.accounts.gacha_state.key()
     });
@@ -231,25 +833,278 @@ pub fn set_halted(ctx: Context<AdminAction>, halted: bool) -> Result<()> {
     Ok(())
 }
 
-/// Transfer admin privileges to a new account
+/// Grant a role to an account
+///
+/// Role holders can perform the instructions restricted to their role without
+/// being admin. The admin implicitly holds every role.
+///
+/// Args:
+/// - ctx: Context containing gacha_state and admin
+/// - account: Account receiving the role
+/// - role: The role to grant
+///
+/// Returns: Result indicating success or failure
+pub fn grant_role(ctx: Context<GrantRole>, account: Pubkey, role: Role) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+
+    let grant = RoleGrant { account, role };
+    require!(
+        !gacha_state.roles.contains(&grant),
+        GachaError::RoleAlreadyGranted
+    );
+    require!(
+        gacha_state.roles.len() < MAX_ROLE_GRANTS,
+        GachaError::TooManyRoleGrants
+    );
+    gacha_state.roles.push(grant);
+
+    emit!(RoleGranted {
+        admin: ctx.accounts.admin.key(),
+        account,
+        role,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Revoke a role from an account
+///
+/// The space of the grant is given back, refunding its rent to the admin.
+///
+/// Args:
+/// - ctx: Context containing gacha_state and admin
+/// - account: Account losing the role
+/// - role: The role to revoke
+///
+/// Returns: Result indicating success or failure
+pub fn revoke_role(ctx: Context<RevokeRole>, account: Pubkey, role: Role) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+
+    let index = gacha_state
+        .roles
+        .iter()
+        .position(|grant| grant.account == account && grant.role == role)
+        .ok_or(GachaError::RoleNotGranted)?;
+    gacha_state.roles.remove(index);
+
+    emit!(RoleRevoked {
+        admin: ctx.accounts.admin.key(),
+        account,
+        role,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Set the refund timeout of the gacha machine
+///
+/// Users can refund an unsettled pull once this many slots have passed since it.
+/// A timeout of 0 disables user-initiated refunds (the admin can always refund).
+///
+/// Args:
+/// - ctx: Context containing gacha_state to modify
+/// - refund_timeout_slots: Slots to wait after a pull before it can be refunded
+///
+/// Returns: Result indicating success or failure
+pub fn set_refund_timeout(ctx: Context<AdminAction>, refund_timeout_slots: u64) -> Result<()> {
+    ctx.accounts.gacha_state.refund_timeout_slots = refund_timeout_slots;
+
+    emit!(RefundTimeoutUpdated {
+        admin: ctx.accounts.admin.key(),
+        refund_timeout_slots,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Set the randomness source of the gacha machine
 ///
-/// Changes the admin of the gacha machine to a new public key.
-/// Only the current admin can perform this operation.
+/// Selects the provider pulls are settled with. Can only be changed before
+/// finalization, so every pull of a machine uses the same source. The
+/// deterministic source is rejected unless the program was built with the
+/// `test-randomness` feature.
 ///
 /// Args:
 /// - ctx: Context containing gacha_state to modify
-/// - new_admin: Public key of the new admin
+/// - randomness_source: The new randomness source
 ///
 /// Returns: Result indicating success or failure
-pub fn transfer_admin(ctx: Context<AdminAction>, new_admin: Pubkey) -> Result<()> {
-    let previous_admin = ctx.accounts.gacha_state.admin;
-    ctx.accounts.gacha_state.admin = new_admin;
+pub fn set_randomness_source(
+    ctx: Context<AdminAction>,
+    randomness_source: RandomnessSource,
+) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    require!(!gacha_state.is_finalized, GachaError::GachaAlreadyFinalized);
+    require!(
+        randomness_source != RandomnessSource::Deterministic || TEST_RANDOMNESS_ENABLED,
+        GachaError::RandomnessSourceUnavailable
+    );
 
-    emit!(AdminTransferred {
-        previous_admin,
-        new_admin,
+    gacha_state.randomness_source = randomness_source;
+
+    emit!(RandomnessSourceUpdated {
+        admin: ctx.accounts.admin.key(),
+        randomness_source,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Set the pity threshold of the gacha machine
+///
+/// After this many consecutive settled pulls without a top-tier result, a user's
+/// next settlement is forced into the top tier (as long as it still has keys).
+/// A threshold of 0 disables the pity mechanic.
+///
+/// Args:
+/// - ctx: Context containing gacha_state to modify
+/// - pity_threshold: Pulls without a top-tier result that trigger pity
+///
+/// Returns: Result indicating success or failure
+pub fn set_pity_threshold(ctx: Context<AdminAction>, pity_threshold: u32) -> Result<()> {
+    ctx.accounts.gacha_state.pity_threshold = pity_threshold;
+
+    emit!(PityThresholdUpdated {
+        admin: ctx.accounts.admin.key(),
+        pity_threshold,
         gacha_state: ctx.accounts.gacha_state.key()
     });
+
+    Ok(())
+}
+
+/// Set the crank tip of the gacha machine
+///
+/// Settlement is permissionless; a caller settling someone else's pull receives
+/// this share of the pull price from the vault. A tip of 0 disables crank tips.
+///
+/// Args:
+/// - ctx: Context containing gacha_state to modify
+/// - crank_tip_bps: Share of the pull price paid to the caller, in basis points
+///
+/// Returns: Result indicating success or failure
+pub fn set_crank_tip(ctx: Context<AdminAction>, crank_tip_bps: u16) -> Result<()> {
+    require!(
+        (crank_tip_bps as u64) < BPS_DENOMINATOR,
+        GachaError::InvalidCrankTip
+    );
+    ctx.accounts.gacha_state.crank_tip_bps = crank_tip_bps;
+
+    emit!(CrankTipUpdated {
+        admin: ctx.accounts.admin.key(),
+        crank_tip_bps,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Enable, update or disable instant pulls on the gacha machine
+///
+/// Instant pulls draw their key from slot hashes, which users can predict and
+/// block producers can bias, so they are only meant for machines whose prizes are
+/// all worth little. The admin declares the value of the machine's most valuable
+/// prize, which must not exceed MAX_INSTANT_PRIZE_VALUE. The value is advisory
+/// only: the program cannot price keys, so no pull or prize is ever compared with
+/// it. It is a published declaration the admin is accountable for. A value of 0
+/// disables instant pulls.
+///
+/// Args:
+/// - ctx: Context containing gacha_state to modify
+/// - max_prize_value: Value of the most valuable prize, in micro-USD
+///
+/// Returns: Result indicating success or failure
+pub fn set_instant_mode(ctx: Context<AdminAction>, max_prize_value: u64) -> Result<()> {
+    require!(
+        max_prize_value <= MAX_INSTANT_PRIZE_VALUE,
+        GachaError::InvalidInstantPrizeValue
+    );
+    ctx.accounts.gacha_state.instant_max_prize_value = max_prize_value;
+
+    emit!(InstantModeUpdated {
+        admin: ctx.accounts.admin.key(),
+        max_prize_value,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Propose a new admin for the gacha machine
+///
+/// First step of a two-step admin transfer: the proposed account only becomes
+/// admin once it accepts with `accept_admin`, so a mistyped key cannot take over
+/// the machine. Proposing again replaces any previous proposal.
+///
+/// Args:
+/// - ctx: Context containing gacha_state to modify
+/// - new_admin: Public key of the proposed admin
+///
+/// Returns: Result indicating success or failure
+pub fn propose_admin(ctx: Context<AdminAction>, new_admin: Pubkey) -> Result<()> {
+    ctx.accounts.gacha_state.pending_admin = Some(new_admin);
+
+    emit!(AdminProposed {
+        admin: ctx.accounts.admin.key(),
+        pending_admin: new_admin,
+        gacha_state: ctx.accounts.gacha_state.key()
+    });
+    Ok(())
+}
+
+/// Accept admin privileges proposed by the current admin
+///
+/// Second step of a two-step admin transfer. Must be signed by the pending admin.
+///
+/// Args:
+/// - ctx: Context containing gacha_state and the pending admin signer
+///
+/// Returns: Result indicating success or failure
+pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let pending_admin = gacha_state
+        .pending_admin
+        .ok_or(GachaError::NoPendingAdmin)?;
+    require_keys_eq!(
+        ctx.accounts.new_admin.key(),
+        pending_admin,
+        GachaError::NotPendingAdmin
+    );
+
+    let previous_admin = gacha_state.admin;
+    gacha_state.admin = pending_admin;
+    gacha_state.pending_admin = None;
+
+    emit!(AdminAccepted {
+        previous_admin,
+        new_admin: pending_admin,
+        gacha_state: gacha_state.key()
+    });
+    Ok(())
+}
+
+/// Cancel a pending admin transfer
+///
+/// Args:
+/// - ctx: Context containing gacha_state to modify
+///
+/// Returns: Result indicating success or failure
+pub fn cancel_admin_transfer(ctx: Context<AdminAction>) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let pending_admin = gacha_state
+        .pending_admin
+        .take()
+        .ok_or(GachaError::NoPendingAdmin)?;
+
+    emit!(AdminTransferCancelled {
+        admin: ctx.accounts.admin.key(),
+        pending_admin,
+        gacha_state: gacha_state.key()
+    });
     Ok(())
 }
 
@@ -269,7 +1124,7 @@ pub fn release_decryption_key(ctx: Context<AdminAction>, decryption_key: String)
     // Validation: ensure gacha machine is complete
     require_eq!(
         gacha_state.settle_count,
-        gacha_state.encrypted_keys.len() as u64,
+        gacha_state.total_keys as u64,
         GachaError::GachaNotComplete
     );
 
@@ -278,7 +1133,124 @@ pub fn release_decryption_key(ctx: Context<AdminAction>, decryption_key: String)
 
     emit!(DecryptionKeyReleased {
         admin: ctx.accounts.admin.key(),
-        decryption_key: decryption_key,
+        decryption_key,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Close the last key page of a finished gacha machine
+///
+/// Returns the page's rent to the admin. Pages are closed from the last one
+/// down, and all of them must be closed before `close_machine`. Claims are
+/// permissionless, so the admin can claim any outstanding results first.
+///
+/// Args:
+/// - ctx: Context containing the key_page to close and gacha_state
+///
+/// Returns: Result indicating success or failure
+///
+/// Constraints:
+/// - Every key must be settled, or every pull must have been refunded
+/// - Every settled pull must have claimed its key
+pub fn close_key_page(ctx: Context<CloseKeyPage>) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+
+    require!(gacha_state.is_closable(), GachaError::GachaNotComplete);
+
+    gacha_state.key_page_count -= 1;
+
+    emit!(KeyPageClosed {
+        admin: ctx.accounts.admin.key(),
+        page_index: ctx.accounts.key_page.page_index,
+        gacha_state: gacha_state.key()
+    });
+
+    Ok(())
+}
+
+/// Close a finished gacha machine
+///
+/// Closes the gacha state, every payment config of the machine and the vault
+/// token accounts of SPL configs, returning their rent (and any leftover
+/// lamports) to the admin. Payment vaults must be drained first: no escrowed
+/// payments and no unwithdrawn revenue may remain. A `GachaClosed` event
+/// summarizes the machine, including its released decryption key, before the
+/// accounts disappear. Finished player and multi-pull states can still be closed
+/// by their users afterwards.
+///
+/// Args:
+/// - ctx: Context containing gacha_state and admin; payment configs (each SPL
+///   config followed by its vault, mint and token program) as remaining accounts
+///
+/// Returns: Result indicating success or failure
+///
+/// Constraints:
+/// - Every key must be settled, or every pull must have been refunded
+/// - Every settled pull must have claimed its key
+/// - All key pages must have been closed with `close_key_page`
+/// - Every payment config must be passed, in the order of `gacha_state.payment_configs`
+pub fn close_machine<'info>(ctx: Context<'_, '_, 'info, 'info, CloseMachine<'info>>) -> Result<()> {
+    let gacha_state = &ctx.accounts.gacha_state;
+    let admin = ctx.accounts.admin.to_account_info();
+
+    require!(gacha_state.is_closable(), GachaError::GachaNotComplete);
+    require!(
+        gacha_state.key_page_count == 0,
+        GachaError::KeyPagesNotClosed
+    );
+
+    // ============ PAYMENT CONFIG CLOSING ============
+    let mut remaining_accounts = ctx.remaining_accounts.iter();
+    for expected_key in gacha_state.payment_configs.iter() {
+        let account = remaining_accounts
+            .next()
+            .ok_or(GachaError::PaymentConfigsMismatch)?;
+        require_keys_eq!(
+            account.key(),
+            *expected_key,
+            GachaError::PaymentConfigsMismatch
+        );
+        let payment_config = Account::<PaymentConfig>::try_from(account)?;
+        require!(
+            payment_config.escrowed_amount == 0 && payment_config.withdrawable_amount() == 0,
+            GachaError::VaultNotEmpty
+        );
+
+        // SPL configs hold their payments in an associated token account
+        if payment_config.mint != system_program::ID {
+            let (Some(payment_vault), Some(payment_mint), Some(token_program)) = (
+                remaining_accounts.next(),
+                remaining_accounts.next(),
+                remaining_accounts.next(),
+            ) else {
+                return err!(GachaError::PaymentConfigsMismatch);
+            };
+            close_payment_vault(
+                &payment_config,
+                payment_vault,
+                payment_mint,
+                token_program,
+                &admin,
+            )?;
+        }
+        payment_config.close(admin.clone())?;
+    }
+    require!(
+        remaining_accounts.next().is_none(),
+        GachaError::PaymentConfigsMismatch
+    );
+
+    emit!(GachaClosed {
+        admin: ctx.accounts.admin.key(),
+        machine_id: gacha_state.machine_id,
+        total_keys: gacha_state.total_keys,
+        pull_count: gacha_state.pull_count,
+        settle_count: gacha_state.settle_count,
+        refund_count: gacha_state.refund_count,
+        payment_configs_closed: gacha_state.payment_configs.len() as u8,
+        decryption_key: gacha_state.decryption_key.clone(),
         gacha_state: gacha_state.key()
     });
 
@@ -1,12 +1,18 @@
 use anchor_lang::prelude::*;
 use anchor_lang::system_program;
-use switchboard_on_demand::accounts::RandomnessAccountData;
 
-use crate::{constants::*, contexts::*, errors::GachaError, events::*, helpers::*};
+use crate::{
+    constants::*,
+    contexts::*,
+    errors::GachaError,
+    events::*,
+    helpers::*,
+    states::{KeyPage, KeyStorage, PullCommitment, RandomnessSource},
+};
 
-/// ========================================
-/// User Instructions
-/// ========================================
+// ========================================
+// User Instructions
+// ========================================
 
 /// Perform a gacha pull
 ///
@@ -16,15 +22,18 @@ use crate::{constants::*, contexts::*, errors::GachaError, events::*, helpers::*
 /// Process:
 /// 1. Validate gacha machine state (not paused, finalized, has keys)
 /// 2. Validate payment configuration and randomness account
-/// 3. Process payment (SOL or SPL tokens)
+/// 3. Process payment (SOL or SPL tokens) into the payment vault
 /// 4. Create player state for later settlement
 /// 5. Increment pull counter
 ///
 /// Args:
 /// - ctx: Context containing all required accounts for the pull operation
+/// - max_price: Highest amount the user accepts to be debited, transfer fees included
+///   (fails with PriceExceedsMax above it)
+/// - client_seed: Seed mixed into the randomness of commit-reveal machines
 ///
 /// Returns: Result indicating success or failure
-pub fn pull(ctx: Context<Pull>) -> Result<()> {
+pub fn pull(ctx: Context<Pull>, max_price: u64, client_seed: [u8; 32]) -> Result<()> {
     let clock = Clock::get()?;
 
     // ============ GACHA MACHINE VALIDATIONS ============
@@ -34,8 +43,10 @@ pub fn pull(ctx: Context<Pull>) -> Result<()> {
         ctx.accounts.gacha_state.is_finalized,
         GachaError::GachaNotFinalized
     );
+    // Refunded pulls give their key back to the pool
     require!(
-        ctx.accounts.gacha_state.pull_count < ctx.accounts.gacha_state.encrypted_keys.len() as u64,
+        ctx.accounts.gacha_state.pull_count - ctx.accounts.gacha_state.refund_count
+            < ctx.accounts.gacha_state.total_keys as u64,
         GachaError::NotEnoughKeys
     );
 
@@ -49,25 +60,44 @@ pub fn pull(ctx: Context<Pull>) -> Result<()> {
         GachaError::InvalidPaymentConfig
     );
 
-    // ============ RANDOMNESS VALIDATION ============
-    // Ensure the randomness account is current and valid
-    let randomness_account = &ctx.accounts.randomness_account_data;
-    let randomness_data = RandomnessAccountData::parse(randomness_account.data.borrow())
-        .map_err(|_| GachaError::InvalidRandomnessAccount)?;
+    // ============ PRICE VALIDATION ============
+    let price = pull_unit_price(
+        &ctx.accounts.payment_config,
+        &ctx.accounts.payment_mint,
+        ctx.accounts.price_feed.as_deref(),
+        clock.slot,
+    )?;
+    // Protect the user against a price change landing before their transaction,
+    // comparing the amount actually debited (including transfer fees)
     require!(
-        clock.slot >= randomness_data.seed_slot
-            && clock.slot - randomness_data.seed_slot <= MAX_SLOT_DIFFERENCE,
-        GachaError::RandomnessNotReady
+        gross_payment_amount(&ctx.accounts.payment_mint, price)? <= max_price,
+        GachaError::PriceExceedsMax
     );
 
+    // ============ RANDOMNESS VALIDATION ============
+    // Bind the pull to the randomness it will be settled with (for Switchboard, a
+    // fresh, unrevealed and unused commitment)
+    let randomness_account = &ctx.accounts.randomness_account_data;
+    let commitment = bind_pull_randomness(
+        &ctx.accounts.gacha_state,
+        randomness_account,
+        ctx.accounts.randomness_usage.as_deref_mut(),
+        ctx.bumps.randomness_usage,
+        client_seed,
+        clock.slot,
+    )?;
+
     // ============ PAYMENT PROCESSING ============
     // Process payment based on payment method (SOL vs SPL token)
     if ctx.accounts.payment_config.mint == system_program::ID {
-        process_sol_payment(&ctx, &ctx.accounts.payment_config)?;
+        process_sol_payment(&ctx.accounts.payment_accounts(), price)?;
     } else {
-        process_spl_payment(&ctx, &ctx.accounts.payment_config)?;
+        process_spl_payment(&ctx.accounts.payment_accounts(), price)?;
     }
 
+    // The payment stays in escrow until the pull is settled or refunded
+    ctx.accounts.payment_config.escrowed_amount += price;
+
     // ============ PLAYER STATE SETUP ============
     // Initialize the player state for later settlement
     let player_state = &mut ctx.accounts.player_state;
@@ -76,10 +106,21 @@ pub fn pull(ctx: Context<Pull>) -> Result<()> {
     player_state.randomness_account = randomness_account.key();
     player_state.payment_mint = ctx.accounts.payment_config.mint.key();
     player_state.is_settled = false;
+    player_state.is_refunded = false;
+    player_state.price_paid = price;
+    player_state.is_claimed = false;
     player_state.pull_slot = clock.slot;
     player_state.nonce = ctx.accounts.gacha_state.pull_count;
+    player_state.commitment = commitment;
     player_state.bump = ctx.bumps.player_state;
 
+    init_user_stats(
+        &mut ctx.accounts.user_stats,
+        ctx.accounts.user.key(),
+        ctx.accounts.gacha_state.key(),
+        ctx.bumps.user_stats,
+    );
+
     // Increment the pull counter
     ctx.accounts.gacha_state.pull_count += 1;
 
@@ -87,7 +128,52 @@ pub fn pull(ctx: Context<Pull>) -> Result<()> {
         user: ctx.accounts.user.key(),
         nonce: player_state.nonce,
         payment_mint: ctx.accounts.payment_config.mint,
-        price: ctx.accounts.payment_config.price,
+        price,
+        usd_price: ctx
+            .accounts
+            .payment_config
+            .usd_pricing
+            .map_or(0, |usd_pricing| usd_pricing.usd_price),
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Seal a commit-reveal pull with its slot hash
+///
+/// Records the hash of the first slot after the pull, read from the SlotHashes
+/// sysvar, as the pull's slot hash. The hash is unknown when the pull is
+/// submitted, so neither the user nor the operator (who knows the server seeds)
+/// can grind the client seed against it. The sysvar only keeps the last 512
+/// slots: settlement seals the pull itself when it lands within that window,
+/// otherwise anyone (the user, a keeper) must seal it in time.
+///
+/// Args:
+/// - ctx: Context containing player_state, gacha_state, and the SlotHashes sysvar
+///
+/// Returns: Result indicating success or failure
+pub fn seal_pull(ctx: Context<SealPull>) -> Result<()> {
+    let player_state = &mut ctx.accounts.player_state;
+
+    require!(
+        ctx.accounts.gacha_state.randomness_source == RandomnessSource::CommitReveal,
+        GachaError::RandomnessSourceMismatch
+    );
+    require!(!player_state.is_settled, GachaError::AlreadySettled);
+    require!(!player_state.is_refunded, GachaError::AlreadyRefunded);
+    require!(
+        !player_state.commitment.is_sealed(),
+        GachaError::PullAlreadySealed
+    );
+
+    let slot_hash = sealing_slot_hash(&ctx.accounts.slot_hashes, player_state.pull_slot)?;
+    player_state.commitment.slot_hash = slot_hash;
+
+    emit!(PullSealed {
+        user: player_state.user,
+        nonce: player_state.nonce,
+        slot_hash,
         gacha_state: ctx.accounts.gacha_state.key(),
     });
 
@@ -102,9 +188,21 @@ pub fn pull(ctx: Context<Pull>) -> Result<()> {
 /// Process:
 /// 1. Validate settlement conditions (not settled, not halted, slot passed)
 /// 2. Extract randomness from Switchboard oracle
-/// 3. Use randomness to select from remaining indices
-/// 4. Remove selected index and assign reward to player
-/// 5. Mark as settled and increment settle counter
+/// 3. Use randomness to pick a rarity tier by weight (or the top tier when the
+///    user's pity is due), then an index within it
+/// 4. Remove selected index from its tier and assign it to the player
+/// 5. Mark as settled, release the payment from escrow and increment settle counter
+///
+/// Anyone can settle a pull once its randomness resolves, so keepers can crank
+/// pending pulls. A caller other than the user receives the machine's crank tip
+/// out of the pull price.
+///
+/// The random value of a pull is fixed, but the key it draws depends on the pool
+/// left by earlier settlements. A caller settling several resolvable pulls can
+/// therefore pick their order to steer keys between them (see the README).
+///
+/// The encrypted key itself is copied from its key page by `claim_key`, since the
+/// selected index (and therefore its page) is only known once randomness resolves.
 ///
 /// Args:
 /// - ctx: Context containing player_state, gacha_state, and randomness account
@@ -118,6 +216,7 @@ pub fn settle(ctx: Context<Settle>) -> Result<()> {
     // ============ SETTLEMENT VALIDATIONS ============
     // Ensure this pull hasn't been settled
     require!(!player_state.is_settled, GachaError::AlreadySettled);
+    require!(!player_state.is_refunded, GachaError::AlreadyRefunded);
     require!(gacha_state.is_finalized, GachaError::GachaNotFinalized);
     require!(!gacha_state.is_halted, GachaError::GachaHalted);
     require!(
@@ -126,51 +225,76 @@ pub fn settle(ctx: Context<Settle>) -> Result<()> {
     );
 
     // Check if there are still rewards available
-    let remaining_count = gacha_state.remaining_indices.len();
-    require!(remaining_count > 0, GachaError::GachaIsEmpty);
+    require!(gacha_state.remaining_keys() > 0, GachaError::GachaIsEmpty);
 
     // ============ RANDOMNESS EXTRACTION ============
-    // Get the resolved randomness from the Switchboard oracle
-    let randomness_data =
-        RandomnessAccountData::parse(ctx.accounts.randomness_account_data.data.borrow())
-            .map_err(|_| GachaError::InvalidRandomnessAccount)?;
-
-    // require_eq!(
-    //     randomness_data.seed_slot,
-    //     player_state.pull_slot,
-    //     GachaError::RandomnessExpired
-    // );
-
-    let random_value_bytes = randomness_data
-        .get_value(clock.slot)
-        .map_err(|_| GachaError::RandomnessNotResolved)?;
-
-    // Convert randomness bytes to u64 for indexing
-    let random_u64 = u64::from_le_bytes(
-        random_value_bytes[0..8]
-            .try_into()
-            .map_err(|_| GachaError::InvalidRandomnessValue)?,
-    );
+    // Get the resolved randomness from the machine's randomness source
+    let pull = &mut **player_state;
+    let random_value_bytes = resolve_pull_randomness(
+        gacha_state,
+        &gacha_state.key(),
+        &ctx.accounts.randomness_account_data,
+        &mut pull.commitment,
+        &pull.user,
+        pull.nonce,
+        pull.pull_slot,
+        ctx.accounts.slot_hashes.as_deref(),
+        clock.slot,
+    )?;
 
     // ============ REWARD SELECTION ============
-    // Use Fisher-Yates shuffle approach: select random index from remaining
-    let selected_index_in_remaining = random_u64 as usize % remaining_count;
-    let final_key_index = gacha_state
-        .remaining_indices
-        .swap_remove(selected_index_in_remaining);
-
-    // Get the actual encrypted key from the pool
-    let encrypted_key_from_pool = gacha_state
-        .encrypted_keys
-        .get(final_key_index as usize)
-        .ok_or(GachaError::IndexOutOfBounds)?
-        .clone();
+    // Pick a tier by weight (or the top tier when pity is due), then a key within it
+    let user_stats = &mut ctx.accounts.user_stats;
+    let pity_threshold = gacha_state.pity_threshold;
+    let pulls_without_top_tier = user_stats.pulls_since_top_tier;
+    let (final_tier, final_key_index, pity_triggered) = draw_key_with_pity(
+        &mut gacha_state.tiers,
+        pity_threshold,
+        user_stats,
+        &random_value_bytes,
+    )?;
+    if pity_triggered {
+        emit!(PityTriggered {
+            user: player_state.user,
+            nonce: player_state.nonce,
+            key_index: final_key_index,
+            tier: final_tier,
+            pulls_without_top_tier,
+            gacha_state: gacha_state.key(),
+        });
+    }
 
     // ============ SETTLEMENT COMPLETION ============
     // Update player state with the result
     player_state.is_settled = true;
     player_state.result_index = final_key_index;
-    player_state.winning_encrypted_key = encrypted_key_from_pool;
+    player_state.result_tier = final_tier;
+
+    // A third-party caller earns the crank tip; the rest is earned by the machine
+    let crank_tip = pay_crank_tip(
+        gacha_state,
+        &ctx.accounts.payment_config,
+        ctx.accounts.caller.key(),
+        player_state.user,
+        player_state.price_paid,
+        ctx.accounts.payment_mint.as_ref(),
+        ctx.accounts.payment_vault.as_ref(),
+        ctx.accounts.caller_payment_account.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    let payment_config = &mut ctx.accounts.payment_config;
+    payment_config.escrowed_amount -= player_state.price_paid;
+    payment_config.settled_amount += player_state.price_paid - crank_tip;
+    if crank_tip > 0 {
+        emit!(CrankTipPaid {
+            caller: ctx.accounts.caller.key(),
+            user: player_state.user,
+            nonce: player_state.nonce,
+            payment_mint: payment_config.mint,
+            amount: crank_tip,
+            gacha_state: gacha_state.key(),
+        });
+    }
 
     // Increment the settlement counter
     gacha_state.settle_count += 1;
@@ -178,9 +302,866 @@ pub fn settle(ctx: Context<Settle>) -> Result<()> {
     emit!(GachaResult {
         user: player_state.user,
         key_index: final_key_index,
+        tier: final_tier,
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Perform an instant gacha pull
+///
+/// Charges the payment and selects the key in a single instruction on machines
+/// with instant mode enabled, using the most recent SlotHashes entry mixed with
+/// the machine, user and nonce instead of an oracle.
+///
+/// This randomness is not secure. The most recent slot hash is known before the
+/// transaction is sent, so anyone can compute the draw of the next pull offline,
+/// and a block producer can bias slot hashes. The pull must be the top-level, last
+/// instruction of its transaction, next to compute budget, system and token
+/// instructions only, which stops a single transaction from checking the result
+/// and reverting. It cannot stop a bundle whose other transactions assert the slot
+/// and `pull_count` so the pull only lands when its draw is known to be good.
+/// Instant mode is therefore only for machines whose prizes are all worth little;
+/// the max prize value is the admin's advisory declaration of that, and no pull
+/// is checked against it.
+///
+/// Process:
+/// 1. Validate gacha machine state and instant mode
+/// 2. Check that the pull cannot be reverted on its result
+/// 3. Process payment (SOL or SPL tokens) into the payment vault
+/// 4. Draw a tier (or the top tier when pity is due) and a key within it
+/// 5. Create the settled player state and increment the pull and settle counters
+///
+/// Args:
+/// - ctx: Context containing all required accounts for the instant pull
+/// - max_price: Highest amount the user accepts to be debited, transfer fees included
+///   (fails with PriceExceedsMax above it)
+///
+/// Returns: Result indicating success or failure
+pub fn pull_instant(ctx: Context<PullInstant>, max_price: u64) -> Result<()> {
+    let clock = Clock::get()?;
+
+    // ============ GACHA MACHINE VALIDATIONS ============
+    require!(!ctx.accounts.gacha_state.is_paused, GachaError::GachaPaused);
+    require!(!ctx.accounts.gacha_state.is_halted, GachaError::GachaHalted);
+    require!(
+        ctx.accounts.gacha_state.is_finalized,
+        GachaError::GachaNotFinalized
+    );
+    require!(
+        ctx.accounts.gacha_state.instant_max_prize_value > 0,
+        GachaError::InstantModeDisabled
+    );
+    // Keys reserved by pending pulls are not available
+    require!(
+        ctx.accounts.gacha_state.pull_count - ctx.accounts.gacha_state.refund_count
+            < ctx.accounts.gacha_state.total_keys as u64,
+        GachaError::NotEnoughKeys
+    );
+    require_unrevertable_instant_pull(&ctx.accounts.instructions_sysvar)?;
+
+    // ============ PAYMENT VALIDATION ============
+    require!(
+        ctx.accounts
+            .gacha_state
+            .payment_configs
+            .contains(&ctx.accounts.payment_config.key()),
+        GachaError::InvalidPaymentConfig
+    );
+
+    // ============ PAYMENT PROCESSING ============
+    let price = pull_unit_price(
+        &ctx.accounts.payment_config,
+        &ctx.accounts.payment_mint,
+        ctx.accounts.price_feed.as_deref(),
+        clock.slot,
+    )?;
+    require!(
+        gross_payment_amount(&ctx.accounts.payment_mint, price)? <= max_price,
+        GachaError::PriceExceedsMax
+    );
+    if ctx.accounts.payment_config.mint == system_program::ID {
+        process_sol_payment(&ctx.accounts.payment_accounts(), price)?;
+    } else {
+        process_spl_payment(&ctx.accounts.payment_accounts(), price)?;
+    }
+
+    // The pull is settled right away, so the payment is earned immediately
+    ctx.accounts.payment_config.settled_amount += price;
+
+    // ============ REWARD SELECTION ============
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let user = ctx.accounts.user.key();
+    let nonce = gacha_state.pull_count;
+    let slot_hash = most_recent_slot_hash(&ctx.accounts.slot_hashes)?;
+    let random_value_bytes = instant_randomness(&slot_hash, &gacha_state.key(), &user, nonce);
+
+    let user_stats = &mut ctx.accounts.user_stats;
+    init_user_stats(user_stats, user, gacha_state.key(), ctx.bumps.user_stats);
+    let pity_threshold = gacha_state.pity_threshold;
+    let pulls_without_top_tier = user_stats.pulls_since_top_tier;
+    let (final_tier, final_key_index, pity_triggered) = draw_key_with_pity(
+        &mut gacha_state.tiers,
+        pity_threshold,
+        user_stats,
+        &random_value_bytes,
+    )?;
+    if pity_triggered {
+        emit!(PityTriggered {
+            user,
+            nonce,
+            key_index: final_key_index,
+            tier: final_tier,
+            pulls_without_top_tier,
+            gacha_state: gacha_state.key(),
+        });
+    }
+
+    // ============ PLAYER STATE SETUP ============
+    // The player state is created settled; the key is copied by `claim_key`
+    let player_state = &mut ctx.accounts.player_state;
+    player_state.user = user;
+    player_state.gacha_state = gacha_state.key();
+    player_state.randomness_account = ctx.accounts.slot_hashes.key();
+    player_state.payment_mint = ctx.accounts.payment_config.mint.key();
+    player_state.is_settled = true;
+    player_state.is_refunded = false;
+    player_state.price_paid = price;
+    player_state.is_claimed = false;
+    player_state.result_index = final_key_index;
+    player_state.result_tier = final_tier;
+    player_state.pull_slot = clock.slot;
+    player_state.nonce = nonce;
+    player_state.commitment = PullCommitment {
+        seed_slot: clock.slot,
+        slot_hash,
+        ..PullCommitment::default()
+    };
+    player_state.bump = ctx.bumps.player_state;
+
+    gacha_state.pull_count += 1;
+    gacha_state.settle_count += 1;
+
+    emit!(GachaPulled {
+        user,
+        nonce,
+        payment_mint: ctx.accounts.payment_config.mint,
+        price,
+        usd_price: ctx
+            .accounts
+            .payment_config
+            .usd_pricing
+            .map_or(0, |usd_pricing| usd_pricing.usd_price),
+        gacha_state: gacha_state.key(),
+    });
+    emit!(GachaResult {
+        user,
+        key_index: final_key_index,
+        tier: final_tier,
+        gacha_state: gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Refund an unsettled gacha pull
+///
+/// Returns the escrowed payment of a pull that was never settled, e.g. because the
+/// machine stayed halted or the randomness never resolved. The user can refund once
+/// `refund_timeout_slots` have passed since the pull (if the admin enabled it), the
+/// admin right away (several refund instructions can be batched in one transaction).
+/// Either way, only while the pull cannot be settled (machine halted or randomness
+/// stuck). Refunds of transfer-fee mints are subject to
+/// the mint's transfer fee.
+///
+/// Args:
+/// - ctx: Context containing player_state, payment config, vault and refund destination
+///
+/// Returns: Result indicating success or failure
+pub fn refund(ctx: Context<Refund>) -> Result<()> {
+    let clock = Clock::get()?;
+    let gacha_state = &ctx.accounts.gacha_state;
+    let player_state = &ctx.accounts.player_state;
+    let authority = ctx.accounts.authority.key();
+
+    // ============ REFUND VALIDATIONS ============
+    require!(!player_state.is_settled, GachaError::AlreadySettled);
+    require!(!player_state.is_refunded, GachaError::AlreadyRefunded);
+    require_refund_allowed(
+        gacha_state,
+        authority,
+        player_state.user,
+        player_state.pull_slot,
+        &ctx.accounts.randomness_account_data,
+        &player_state.commitment,
+        clock.slot,
+    )?;
+
+    // ============ TRANSFER EXECUTION ============
+    let amount = player_state.price_paid;
+    release_payment(
+        &ctx.accounts.payment_config,
+        &ctx.accounts.payment_mint,
+        &ctx.accounts.payment_vault,
+        &ctx.accounts.user_payment_account,
+        ctx.accounts.token_program.as_ref(),
+        player_state.user,
+        amount,
+    )?;
+
+    // ============ REFUND COMPLETION ============
+    ctx.accounts.payment_config.escrowed_amount -= amount;
+    ctx.accounts.player_state.is_refunded = true;
+    ctx.accounts.gacha_state.refund_count += 1;
+
+    emit!(GachaRefunded {
+        user: ctx.accounts.player_state.user,
+        nonce: ctx.accounts.player_state.nonce,
+        payment_mint: ctx.accounts.payment_config.mint,
+        amount,
+        refunded_by: authority,
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Claim the winning key of a settled pull
+///
+/// Copies the encrypted key at `player_state.result_index` from the key page that
+/// holds it into the player state. Anyone can call this once the pull is settled,
+/// since the result is already fixed.
+///
+/// Args:
+/// - ctx: Context containing player_state, gacha_state, and the matching key page
+///
+/// Returns: Result indicating success or failure
+pub fn claim_key(ctx: Context<ClaimKey>) -> Result<()> {
+    let player_state = &mut ctx.accounts.player_state;
+
+    require!(
+        ctx.accounts.gacha_state.key_storage == KeyStorage::Paged,
+        GachaError::KeyStorageMismatch
+    );
+    require!(player_state.is_settled, GachaError::NotSettled);
+    require!(!player_state.is_claimed, GachaError::AlreadyClaimed);
+
+    // Get the actual encrypted key from its page
+    let encrypted_key = ctx
+        .accounts
+        .key_page
+        .keys
+        .get(player_state.result_index as usize % KEYS_PER_PAGE)
+        .ok_or(GachaError::IndexOutOfBounds)?
+        .clone();
+
+    player_state.is_claimed = true;
+    player_state.winning_encrypted_key = encrypted_key;
+    ctx.accounts.gacha_state.claim_count += 1;
+
+    emit!(KeyClaimed {
+        user: player_state.user,
+        nonce: player_state.nonce,
+        key_index: player_state.result_index,
         encrypted_key: player_state.winning_encrypted_key.clone(),
         gacha_state: ctx.accounts.gacha_state.key(),
     });
 
     Ok(())
 }
+
+/// Claim the winning key of a settled pull on a Merkle-committed machine
+///
+/// The caller supplies the encrypted key at `player_state.result_index` together
+/// with its Merkle proof. The key is only stored once the proof verifies against
+/// the root committed at finalization.
+///
+/// Args:
+/// - ctx: Context containing player_state and gacha_state
+/// - encrypted_key: The encrypted key at the settled index
+/// - proof: Sibling hashes from the key's leaf up to the committed root
+///
+/// Returns: Result indicating success or failure
+pub fn claim_key_with_proof(
+    ctx: Context<ClaimKeyWithProof>,
+    encrypted_key: String,
+    proof: Vec<[u8; 32]>,
+) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let player_state = &mut ctx.accounts.player_state;
+
+    require!(
+        gacha_state.key_storage == KeyStorage::Merkle,
+        GachaError::KeyStorageMismatch
+    );
+    require!(player_state.is_settled, GachaError::NotSettled);
+    require!(!player_state.is_claimed, GachaError::AlreadyClaimed);
+    require!(!encrypted_key.is_empty(), GachaError::EmptyKeyProvided);
+    require!(
+        encrypted_key.len() <= MAX_KEY_LENGTH,
+        GachaError::KeyTooLong
+    );
+
+    // Verify the key against the committed root before revealing it
+    require!(
+        verify_key_proof(
+            &gacha_state.key_root,
+            player_state.result_index,
+            &encrypted_key,
+            &proof
+        ),
+        GachaError::InvalidKeyProof
+    );
+
+    player_state.is_claimed = true;
+    player_state.winning_encrypted_key = encrypted_key;
+    gacha_state.claim_count += 1;
+
+    emit!(KeyClaimed {
+        user: player_state.user,
+        nonce: player_state.nonce,
+        key_index: player_state.result_index,
+        encrypted_key: player_state.winning_encrypted_key.clone(),
+        gacha_state: gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Close a finished player state and return its rent to the user
+///
+/// A pull is finished once it has been settled and its key claimed, or once it
+/// has been refunded. A receipt event preserving the outcome of the pull is
+/// emitted before the account is closed, so indexers keep the full history.
+///
+/// Args:
+/// - ctx: Context containing the player_state to close and its user
+///
+/// Returns: Result indicating success or failure
+pub fn close_player_state(ctx: Context<ClosePlayerState>) -> Result<()> {
+    let player_state = &ctx.accounts.player_state;
+
+    require!(
+        (player_state.is_settled && player_state.is_claimed) || player_state.is_refunded,
+        GachaError::PullNotFinished
+    );
+
+    emit!(PlayerStateClosed {
+        user: player_state.user,
+        nonce: player_state.nonce,
+        payment_mint: player_state.payment_mint,
+        price_paid: player_state.price_paid,
+        is_refunded: player_state.is_refunded,
+        result_index: player_state.result_index,
+        result_tier: player_state.result_tier,
+        winning_encrypted_key: player_state.winning_encrypted_key.clone(),
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+// ========================================
+// Multi-Pull Instructions
+// ========================================
+
+/// Perform several gacha pulls with a single payment
+///
+/// Reserves `count` pulls in one multi-pull account and charges `count * price`,
+/// minus the payment config's bulk discount. All reserved pulls are settled
+/// together by `settle_many` using a single randomness value.
+///
+/// Args:
+/// - ctx: Context containing all required accounts for the multi-pull
+/// - count: Number of pulls to reserve (1..=MAX_MULTI_PULL)
+/// - max_price: Highest total amount the user accepts to be debited, transfer fees
+///   included (fails with PriceExceedsMax above it)
+/// - client_seed: Seed mixed into the randomness of commit-reveal machines
+///
+/// Returns: Result indicating success or failure
+pub fn pull_many(
+    ctx: Context<PullMany>,
+    count: u8,
+    max_price: u64,
+    client_seed: [u8; 32],
+) -> Result<()> {
+    let clock = Clock::get()?;
+
+    require!(
+        count > 0 && count <= MAX_MULTI_PULL,
+        GachaError::InvalidPullCount
+    );
+
+    // ============ GACHA MACHINE VALIDATIONS ============
+    require!(!ctx.accounts.gacha_state.is_paused, GachaError::GachaPaused);
+    require!(
+        ctx.accounts.gacha_state.is_finalized,
+        GachaError::GachaNotFinalized
+    );
+    // Every reserved pull needs a key still available in the pool
+    require!(
+        ctx.accounts.gacha_state.pull_count - ctx.accounts.gacha_state.refund_count + count as u64
+            <= ctx.accounts.gacha_state.total_keys as u64,
+        GachaError::NotEnoughKeys
+    );
+
+    // ============ PAYMENT VALIDATION ============
+    require!(
+        ctx.accounts
+            .gacha_state
+            .payment_configs
+            .contains(&ctx.accounts.payment_config.key()),
+        GachaError::InvalidPaymentConfig
+    );
+
+    // ============ PRICE VALIDATION ============
+    let unit_price = pull_unit_price(
+        &ctx.accounts.payment_config,
+        &ctx.accounts.payment_mint,
+        ctx.accounts.price_feed.as_deref(),
+        clock.slot,
+    )?;
+    let price = multi_pull_price(&ctx.accounts.payment_config, unit_price, count)?;
+    require!(
+        gross_payment_amount(&ctx.accounts.payment_mint, price)? <= max_price,
+        GachaError::PriceExceedsMax
+    );
+
+    // ============ RANDOMNESS VALIDATION ============
+    let randomness_account = &ctx.accounts.randomness_account_data;
+    let commitment = bind_pull_randomness(
+        &ctx.accounts.gacha_state,
+        randomness_account,
+        ctx.accounts.randomness_usage.as_deref_mut(),
+        ctx.bumps.randomness_usage,
+        client_seed,
+        clock.slot,
+    )?;
+
+    // ============ PAYMENT PROCESSING ============
+    if ctx.accounts.payment_config.mint == system_program::ID {
+        process_sol_payment(&ctx.accounts.payment_accounts(), price)?;
+    } else {
+        process_spl_payment(&ctx.accounts.payment_accounts(), price)?;
+    }
+
+    // The payment stays in escrow until the multi-pull is settled or refunded
+    ctx.accounts.payment_config.escrowed_amount += price;
+
+    // ============ MULTI-PULL STATE SETUP ============
+    let multi_pull_state = &mut ctx.accounts.multi_pull_state;
+    multi_pull_state.user = ctx.accounts.user.key();
+    multi_pull_state.gacha_state = ctx.accounts.gacha_state.key();
+    multi_pull_state.randomness_account = randomness_account.key();
+    multi_pull_state.payment_mint = ctx.accounts.payment_config.mint.key();
+    multi_pull_state.count = count;
+    multi_pull_state.is_settled = false;
+    multi_pull_state.is_refunded = false;
+    multi_pull_state.price_paid = price;
+    multi_pull_state.pull_slot = clock.slot;
+    multi_pull_state.nonce = ctx.accounts.gacha_state.pull_count;
+    multi_pull_state.commitment = commitment;
+    multi_pull_state.bump = ctx.bumps.multi_pull_state;
+
+    init_user_stats(
+        &mut ctx.accounts.user_stats,
+        ctx.accounts.user.key(),
+        ctx.accounts.gacha_state.key(),
+        ctx.bumps.user_stats,
+    );
+
+    // Each reserved pull consumes one pull nonce
+    ctx.accounts.gacha_state.pull_count += count as u64;
+
+    emit!(GachaMultiPulled {
+        user: ctx.accounts.user.key(),
+        nonce: multi_pull_state.nonce,
+        count,
+        payment_mint: ctx.accounts.payment_config.mint,
+        price,
+        usd_price: ctx
+            .accounts
+            .payment_config
+            .usd_pricing
+            .map_or(0, |usd_pricing| usd_pricing.usd_price),
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Seal a commit-reveal multi-pull with its slot hash
+///
+/// Same as `seal_pull`, for a multi-pull.
+///
+/// Args:
+/// - ctx: Context containing multi_pull_state, gacha_state, and the SlotHashes sysvar
+///
+/// Returns: Result indicating success or failure
+pub fn seal_multi_pull(ctx: Context<SealMultiPull>) -> Result<()> {
+    let multi_pull_state = &mut ctx.accounts.multi_pull_state;
+
+    require!(
+        ctx.accounts.gacha_state.randomness_source == RandomnessSource::CommitReveal,
+        GachaError::RandomnessSourceMismatch
+    );
+    require!(!multi_pull_state.is_settled, GachaError::AlreadySettled);
+    require!(!multi_pull_state.is_refunded, GachaError::AlreadyRefunded);
+    require!(
+        !multi_pull_state.commitment.is_sealed(),
+        GachaError::PullAlreadySealed
+    );
+
+    let slot_hash = sealing_slot_hash(&ctx.accounts.slot_hashes, multi_pull_state.pull_slot)?;
+    multi_pull_state.commitment.slot_hash = slot_hash;
+
+    emit!(PullSealed {
+        user: multi_pull_state.user,
+        nonce: multi_pull_state.nonce,
+        slot_hash,
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Settle a multi-pull to determine all of its rewards
+///
+/// Expands the resolved Switchboard randomness into one value per reserved pull
+/// and draws `count` distinct indices, each through a weighted tier draw. Like
+/// `settle`, anyone can call it; a third-party caller receives the crank tip.
+///
+/// Args:
+/// - ctx: Context containing multi_pull_state, gacha_state, and randomness account
+///
+/// Returns: Result indicating success or failure
+pub fn settle_many(ctx: Context<SettleMany>) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let multi_pull_state = &mut ctx.accounts.multi_pull_state;
+    let clock = Clock::get()?;
+
+    // ============ SETTLEMENT VALIDATIONS ============
+    require!(!multi_pull_state.is_settled, GachaError::AlreadySettled);
+    require!(!multi_pull_state.is_refunded, GachaError::AlreadyRefunded);
+    require!(gacha_state.is_finalized, GachaError::GachaNotFinalized);
+    require!(!gacha_state.is_halted, GachaError::GachaHalted);
+    require!(
+        clock.slot > multi_pull_state.pull_slot,
+        GachaError::SlotNotPassed
+    );
+    require!(
+        gacha_state.remaining_keys() >= multi_pull_state.count as usize,
+        GachaError::GachaIsEmpty
+    );
+
+    // ============ RANDOMNESS EXTRACTION ============
+    let multi_pull = &mut **multi_pull_state;
+    let random_value_bytes = resolve_pull_randomness(
+        gacha_state,
+        &gacha_state.key(),
+        &ctx.accounts.randomness_account_data,
+        &mut multi_pull.commitment,
+        &multi_pull.user,
+        multi_pull.nonce,
+        multi_pull.pull_slot,
+        ctx.accounts.slot_hashes.as_deref(),
+        clock.slot,
+    )?;
+
+    // ============ REWARD SELECTION ============
+    // Each draw removes its index from its tier's pool, so all results are distinct
+    let mut key_indices = Vec::with_capacity(multi_pull_state.count as usize);
+    let mut tiers = Vec::with_capacity(multi_pull_state.count as usize);
+    let user_stats = &mut ctx.accounts.user_stats;
+    let pity_threshold = gacha_state.pity_threshold;
+    for draw in 0..multi_pull_state.count {
+        let draw_bytes = expand_randomness(&random_value_bytes, draw);
+        let pulls_without_top_tier = user_stats.pulls_since_top_tier;
+        let (tier, key_index, pity_triggered) = draw_key_with_pity(
+            &mut gacha_state.tiers,
+            pity_threshold,
+            user_stats,
+            &draw_bytes,
+        )?;
+        if pity_triggered {
+            emit!(PityTriggered {
+                user: multi_pull_state.user,
+                nonce: multi_pull_state.nonce + draw as u64,
+                key_index,
+                tier,
+                pulls_without_top_tier,
+                gacha_state: gacha_state.key(),
+            });
+        }
+        tiers.push(tier);
+        key_indices.push(key_index);
+    }
+
+    // ============ SETTLEMENT COMPLETION ============
+    multi_pull_state.is_settled = true;
+    multi_pull_state.result_indices = key_indices.clone();
+    multi_pull_state.result_tiers = tiers.clone();
+    multi_pull_state.winning_encrypted_keys = vec![String::new(); key_indices.len()];
+
+    // A third-party caller earns the crank tip; the rest is earned by the machine
+    let crank_tip = pay_crank_tip(
+        gacha_state,
+        &ctx.accounts.payment_config,
+        ctx.accounts.caller.key(),
+        multi_pull_state.user,
+        multi_pull_state.price_paid,
+        ctx.accounts.payment_mint.as_ref(),
+        ctx.accounts.payment_vault.as_ref(),
+        ctx.accounts.caller_payment_account.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+    )?;
+    let payment_config = &mut ctx.accounts.payment_config;
+    payment_config.escrowed_amount -= multi_pull_state.price_paid;
+    payment_config.settled_amount += multi_pull_state.price_paid - crank_tip;
+    if crank_tip > 0 {
+        emit!(CrankTipPaid {
+            caller: ctx.accounts.caller.key(),
+            user: multi_pull_state.user,
+            nonce: multi_pull_state.nonce,
+            payment_mint: payment_config.mint,
+            amount: crank_tip,
+            gacha_state: gacha_state.key(),
+        });
+    }
+
+    gacha_state.settle_count += multi_pull_state.count as u64;
+
+    emit!(GachaMultiResult {
+        user: multi_pull_state.user,
+        nonce: multi_pull_state.nonce,
+        key_indices,
+        tiers,
+        gacha_state: gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Refund an unsettled multi-pull
+///
+/// Returns the escrowed payment of all pulls reserved by the multi-pull. The same
+/// rules as `refund` apply: only while the multi-pull cannot be settled, right away
+/// for the admin and once the refund timeout has elapsed for the user.
+///
+/// Args:
+/// - ctx: Context containing multi_pull_state, payment config, vault and refund destination
+///
+/// Returns: Result indicating success or failure
+pub fn refund_many(ctx: Context<RefundMany>) -> Result<()> {
+    let clock = Clock::get()?;
+    let multi_pull_state = &ctx.accounts.multi_pull_state;
+    let authority = ctx.accounts.authority.key();
+
+    // ============ REFUND VALIDATIONS ============
+    require!(!multi_pull_state.is_settled, GachaError::AlreadySettled);
+    require!(!multi_pull_state.is_refunded, GachaError::AlreadyRefunded);
+    require_refund_allowed(
+        &ctx.accounts.gacha_state,
+        authority,
+        multi_pull_state.user,
+        multi_pull_state.pull_slot,
+        &ctx.accounts.randomness_account_data,
+        &multi_pull_state.commitment,
+        clock.slot,
+    )?;
+
+    // ============ TRANSFER EXECUTION ============
+    let amount = multi_pull_state.price_paid;
+    release_payment(
+        &ctx.accounts.payment_config,
+        &ctx.accounts.payment_mint,
+        &ctx.accounts.payment_vault,
+        &ctx.accounts.user_payment_account,
+        ctx.accounts.token_program.as_ref(),
+        multi_pull_state.user,
+        amount,
+    )?;
+
+    // ============ REFUND COMPLETION ============
+    ctx.accounts.payment_config.escrowed_amount -= amount;
+    ctx.accounts.multi_pull_state.is_refunded = true;
+    ctx.accounts.gacha_state.refund_count += ctx.accounts.multi_pull_state.count as u64;
+
+    emit!(GachaRefunded {
+        user: ctx.accounts.multi_pull_state.user,
+        nonce: ctx.accounts.multi_pull_state.nonce,
+        payment_mint: ctx.accounts.payment_config.mint,
+        amount,
+        refunded_by: authority,
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Claim the winning keys of a settled multi-pull
+///
+/// Copies every unclaimed key of the multi-pull from its key page. The key pages
+/// holding the results are passed as remaining accounts, in any order.
+///
+/// Args:
+/// - ctx: Context containing multi_pull_state and gacha_state; key pages as remaining accounts
+///
+/// Returns: Result indicating success or failure
+pub fn claim_many_keys<'info>(
+    ctx: Context<'_, '_, 'info, 'info, ClaimManyKeys<'info>>,
+) -> Result<()> {
+    let gacha_state_key = ctx.accounts.gacha_state.key();
+    let multi_pull_state = &mut ctx.accounts.multi_pull_state;
+
+    require!(
+        ctx.accounts.gacha_state.key_storage == KeyStorage::Paged,
+        GachaError::KeyStorageMismatch
+    );
+    require!(multi_pull_state.is_settled, GachaError::NotSettled);
+
+    // Deserialize and validate the provided key pages
+    let mut key_pages = Vec::with_capacity(ctx.remaining_accounts.len());
+    for account in ctx.remaining_accounts.iter() {
+        let key_page = Account::<KeyPage>::try_from(account)?;
+        let (expected_key, _) = Pubkey::find_program_address(
+            &[
+                KEY_PAGE,
+                gacha_state_key.as_ref(),
+                &key_page.page_index.to_le_bytes(),
+            ],
+            ctx.program_id,
+        );
+        require_keys_eq!(account.key(), expected_key, GachaError::InvalidKeyPage);
+        key_pages.push(key_page);
+    }
+
+    let mut claimed = 0;
+    for position in 0..multi_pull_state.result_indices.len() {
+        if !multi_pull_state.winning_encrypted_keys[position].is_empty() {
+            continue;
+        }
+
+        let key_index = multi_pull_state.result_indices[position];
+        let key_page = key_pages
+            .iter()
+            .find(|page| page.page_index as usize == key_index as usize / KEYS_PER_PAGE)
+            .ok_or(GachaError::InvalidKeyPage)?;
+        let encrypted_key = key_page
+            .keys
+            .get(key_index as usize % KEYS_PER_PAGE)
+            .ok_or(GachaError::IndexOutOfBounds)?
+            .clone();
+
+        multi_pull_state.winning_encrypted_keys[position] = encrypted_key.clone();
+        claimed += 1;
+
+        emit!(KeyClaimed {
+            user: multi_pull_state.user,
+            nonce: multi_pull_state.nonce + position as u64,
+            key_index,
+            encrypted_key,
+            gacha_state: gacha_state_key,
+        });
+    }
+    ctx.accounts.gacha_state.claim_count += claimed;
+
+    Ok(())
+}
+
+/// Claim one winning key of a settled multi-pull on a Merkle-committed machine
+///
+/// Works like `claim_key_with_proof` for the pull at `position` within the multi-pull.
+///
+/// Args:
+/// - ctx: Context containing multi_pull_state and gacha_state
+/// - position: Position of the pull within the multi-pull
+/// - encrypted_key: The encrypted key at the settled index
+/// - proof: Sibling hashes from the key's leaf up to the committed root
+///
+/// Returns: Result indicating success or failure
+pub fn claim_many_key_with_proof(
+    ctx: Context<ClaimManyKeyWithProof>,
+    position: u8,
+    encrypted_key: String,
+    proof: Vec<[u8; 32]>,
+) -> Result<()> {
+    let gacha_state = &mut ctx.accounts.gacha_state;
+    let multi_pull_state = &mut ctx.accounts.multi_pull_state;
+
+    require!(
+        gacha_state.key_storage == KeyStorage::Merkle,
+        GachaError::KeyStorageMismatch
+    );
+    require!(multi_pull_state.is_settled, GachaError::NotSettled);
+    let position = position as usize;
+    let key_index = *multi_pull_state
+        .result_indices
+        .get(position)
+        .ok_or(GachaError::IndexOutOfBounds)?;
+    require!(
+        multi_pull_state.winning_encrypted_keys[position].is_empty(),
+        GachaError::AlreadyClaimed
+    );
+    require!(!encrypted_key.is_empty(), GachaError::EmptyKeyProvided);
+    require!(
+        encrypted_key.len() <= MAX_KEY_LENGTH,
+        GachaError::KeyTooLong
+    );
+
+    // Verify the key against the committed root before revealing it
+    require!(
+        verify_key_proof(&gacha_state.key_root, key_index, &encrypted_key, &proof),
+        GachaError::InvalidKeyProof
+    );
+
+    multi_pull_state.winning_encrypted_keys[position] = encrypted_key.clone();
+    gacha_state.claim_count += 1;
+
+    emit!(KeyClaimed {
+        user: multi_pull_state.user,
+        nonce: multi_pull_state.nonce + position as u64,
+        key_index,
+        encrypted_key,
+        gacha_state: gacha_state.key(),
+    });
+
+    Ok(())
+}
+
+/// Close a finished multi-pull state and return its rent to the user
+///
+/// Works like `close_player_state`: the multi-pull must be settled with every key
+/// claimed, or refunded.
+///
+/// Args:
+/// - ctx: Context containing the multi_pull_state to close and its user
+///
+/// Returns: Result indicating success or failure
+pub fn close_multi_pull_state(ctx: Context<CloseMultiPullState>) -> Result<()> {
+    let multi_pull_state = &ctx.accounts.multi_pull_state;
+
+    let all_claimed = multi_pull_state
+        .winning_encrypted_keys
+        .iter()
+        .all(|key| !key.is_empty());
+    require!(
+        (multi_pull_state.is_settled && all_claimed) || multi_pull_state.is_refunded,
+        GachaError::PullNotFinished
+    );
+
+    emit!(MultiPullStateClosed {
+        user: multi_pull_state.user,
+        nonce: multi_pull_state.nonce,
+        payment_mint: multi_pull_state.payment_mint,
+        price_paid: multi_pull_state.price_paid,
+        is_refunded: multi_pull_state.is_refunded,
+        key_indices: multi_pull_state.result_indices.clone(),
+        tiers: multi_pull_state.result_tiers.clone(),
+        winning_encrypted_keys: multi_pull_state.winning_encrypted_keys.clone(),
+        gacha_state: ctx.accounts.gacha_state.key(),
+    });
+
+    Ok(())
+}
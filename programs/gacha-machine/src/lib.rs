@@ -33,8 +33,8 @@ declare_id!("6CqbYs5CWkA54CWaZwkCw2rKEuFZ9uVr65Xoq6Fi7Te3");
 pub mod gacha_machine {
     use super::*;
 
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
-        instructions::initialize(ctx)
+    pub fn initialize(ctx: Context<Initialize>, machine_id: u64) -> Result<()> {
+        instructions::initialize(ctx, machine_id)
     }
 
     pub fn add_payment_config(
@@ -58,35 +58,212 @@ pub mod gacha_machine {
         instructions::remove_payment_config(ctx, payment_mint)
     }
 
-    pub fn add_key(ctx: Context<AddKey>, encrypted_key: String) -> Result<()> {
-        instructions::add_key(ctx, encrypted_key)
+    pub fn update_payment_config(
+        ctx: Context<UpdatePaymentConfig>,
+        payment_mint: Pubkey,
+        payment_price: Option<u64>,
+    ) -> Result<()> {
+        instructions::update_payment_config(ctx, payment_mint, payment_price)
     }
 
-    pub fn finalize(ctx: Context<Finalize>) -> Result<()> {
-        instructions::finalize(ctx)
+    pub fn set_usd_pricing(
+        ctx: Context<SetUsdPricing>,
+        payment_mint: Pubkey,
+        usd_pricing: Option<UsdPricing>,
+    ) -> Result<()> {
+        instructions::set_usd_pricing(ctx, payment_mint, usd_pricing)
     }
 
-    pub fn set_paused(ctx: Context<AdminAction>, paused: bool) -> Result<()> {
+    pub fn set_bulk_discount(
+        ctx: Context<SetBulkDiscount>,
+        payment_mint: Pubkey,
+        bulk_discount_bps: u16,
+    ) -> Result<()> {
+        instructions::set_bulk_discount(ctx, payment_mint, bulk_discount_bps)
+    }
+
+    pub fn withdraw(ctx: Context<Withdraw>, payment_mint: Pubkey, amount: u64) -> Result<()> {
+        instructions::withdraw(ctx, payment_mint, amount)
+    }
+
+    pub fn add_key_page(ctx: Context<AddKeyPage>) -> Result<()> {
+        instructions::add_key_page(ctx)
+    }
+
+    pub fn add_key(ctx: Context<AddKey>, encrypted_key: String, tier: u8) -> Result<()> {
+        instructions::add_key(ctx, encrypted_key, tier)
+    }
+
+    pub fn add_keys(ctx: Context<AddKeys>, encrypted_keys: Vec<String>, tier: u8) -> Result<()> {
+        instructions::add_keys(ctx, encrypted_keys, tier)
+    }
+
+    pub fn set_tier_weights(ctx: Context<SetTierWeights>, weights: Vec<u32>) -> Result<()> {
+        instructions::set_tier_weights(ctx, weights)
+    }
+
+    pub fn finalize(ctx: Context<Finalize>, server_seed_hash: Option<[u8; 32]>) -> Result<()> {
+        instructions::finalize(ctx, server_seed_hash)
+    }
+
+    pub fn finalize_with_root(
+        ctx: Context<FinalizeWithRoot>,
+        key_root: [u8; 32],
+        total_keys: u16,
+        tier_key_counts: Vec<u16>,
+        server_seed_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        instructions::finalize_with_root(
+            ctx,
+            key_root,
+            total_keys,
+            tier_key_counts,
+            server_seed_hash,
+        )
+    }
+
+    pub fn reveal_server_seed(ctx: Context<RevealServerSeed>, server_seed: [u8; 32]) -> Result<()> {
+        instructions::reveal_server_seed(ctx, server_seed)
+    }
+
+    pub fn set_paused(ctx: Context<PauserAction>, paused: bool) -> Result<()> {
         instructions::set_paused(ctx, paused)
     }
 
-    pub fn set_halted(ctx: Context<AdminAction>, halted: bool) -> Result<()> {
+    pub fn set_halted(ctx: Context<PauserAction>, halted: bool) -> Result<()> {
         instructions::set_halted(ctx, halted)
     }
 
-    pub fn transfer_admin(ctx: Context<AdminAction>, new_admin: Pubkey) -> Result<()> {
-        instructions::transfer_admin(ctx, new_admin)
+    pub fn grant_role(ctx: Context<GrantRole>, account: Pubkey, role: Role) -> Result<()> {
+        instructions::grant_role(ctx, account, role)
+    }
+
+    pub fn revoke_role(ctx: Context<RevokeRole>, account: Pubkey, role: Role) -> Result<()> {
+        instructions::revoke_role(ctx, account, role)
+    }
+
+    pub fn set_refund_timeout(ctx: Context<AdminAction>, refund_timeout_slots: u64) -> Result<()> {
+        instructions::set_refund_timeout(ctx, refund_timeout_slots)
+    }
+
+    pub fn set_randomness_source(
+        ctx: Context<AdminAction>,
+        randomness_source: RandomnessSource,
+    ) -> Result<()> {
+        instructions::set_randomness_source(ctx, randomness_source)
+    }
+
+    pub fn set_pity_threshold(ctx: Context<AdminAction>, pity_threshold: u32) -> Result<()> {
+        instructions::set_pity_threshold(ctx, pity_threshold)
+    }
+
+    pub fn set_crank_tip(ctx: Context<AdminAction>, crank_tip_bps: u16) -> Result<()> {
+        instructions::set_crank_tip(ctx, crank_tip_bps)
+    }
+
+    pub fn set_instant_mode(ctx: Context<AdminAction>, max_prize_value: u64) -> Result<()> {
+        instructions::set_instant_mode(ctx, max_prize_value)
+    }
+
+    pub fn propose_admin(ctx: Context<AdminAction>, new_admin: Pubkey) -> Result<()> {
+        instructions::propose_admin(ctx, new_admin)
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        instructions::accept_admin(ctx)
+    }
+
+    pub fn cancel_admin_transfer(ctx: Context<AdminAction>) -> Result<()> {
+        instructions::cancel_admin_transfer(ctx)
     }
 
     pub fn release_decryption_key(ctx: Context<AdminAction>, decryption_key: String) -> Result<()> {
         instructions::release_decryption_key(ctx, decryption_key)
     }
 
-    pub fn pull(ctx: Context<Pull>) -> Result<()> {
-        instructions::pull(ctx)
+    pub fn close_key_page(ctx: Context<CloseKeyPage>) -> Result<()> {
+        instructions::close_key_page(ctx)
+    }
+
+    pub fn close_machine<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CloseMachine<'info>>,
+    ) -> Result<()> {
+        instructions::close_machine(ctx)
+    }
+
+    pub fn pull(ctx: Context<Pull>, max_price: u64, client_seed: [u8; 32]) -> Result<()> {
+        instructions::pull(ctx, max_price, client_seed)
+    }
+
+    pub fn seal_pull(ctx: Context<SealPull>) -> Result<()> {
+        instructions::seal_pull(ctx)
     }
 
     pub fn settle(ctx: Context<Settle>) -> Result<()> {
         instructions::settle(ctx)
     }
+
+    pub fn pull_instant(ctx: Context<PullInstant>, max_price: u64) -> Result<()> {
+        instructions::pull_instant(ctx, max_price)
+    }
+
+    pub fn refund(ctx: Context<Refund>) -> Result<()> {
+        instructions::refund(ctx)
+    }
+
+    pub fn claim_key(ctx: Context<ClaimKey>) -> Result<()> {
+        instructions::claim_key(ctx)
+    }
+
+    pub fn claim_key_with_proof(
+        ctx: Context<ClaimKeyWithProof>,
+        encrypted_key: String,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        instructions::claim_key_with_proof(ctx, encrypted_key, proof)
+    }
+
+    pub fn close_player_state(ctx: Context<ClosePlayerState>) -> Result<()> {
+        instructions::close_player_state(ctx)
+    }
+
+    pub fn pull_many(
+        ctx: Context<PullMany>,
+        count: u8,
+        max_price: u64,
+        client_seed: [u8; 32],
+    ) -> Result<()> {
+        instructions::pull_many(ctx, count, max_price, client_seed)
+    }
+
+    pub fn seal_multi_pull(ctx: Context<SealMultiPull>) -> Result<()> {
+        instructions::seal_multi_pull(ctx)
+    }
+
+    pub fn settle_many(ctx: Context<SettleMany>) -> Result<()> {
+        instructions::settle_many(ctx)
+    }
+
+    pub fn refund_many(ctx: Context<RefundMany>) -> Result<()> {
+        instructions::refund_many(ctx)
+    }
+
+    pub fn claim_many_keys<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ClaimManyKeys<'info>>,
+    ) -> Result<()> {
+        instructions::claim_many_keys(ctx)
+    }
+
+    pub fn claim_many_key_with_proof(
+        ctx: Context<ClaimManyKeyWithProof>,
+        position: u8,
+        encrypted_key: String,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        instructions::claim_many_key_with_proof(ctx, position, encrypted_key, proof)
+    }
+
+    pub fn close_multi_pull_state(ctx: Context<CloseMultiPullState>) -> Result<()> {
+        instructions::close_multi_pull_state(ctx)
+    }
 }
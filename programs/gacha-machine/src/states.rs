@@ -6,12 +6,19 @@ use anchor_lang::prelude::*;
 
 /// Main state account for a gacha machine
 ///
-/// Stores all configuration, keys, and operational state for a gacha instance.
+/// Stores all configuration and operational state for a gacha instance.
+/// The encrypted keys themselves live in separate `KeyPage` accounts.
 /// Uses PDAs for deterministic addressing and supports dynamic resizing.
 #[account]
 pub struct GachaState {
     /// Public key of the admin who controls this gacha machine
     pub admin: Pubkey,
+    /// Admin proposed by the current admin, pending acceptance
+    pub pending_admin: Option<Pubkey>,
+    /// Account that created the machine (part of the PDA seeds, never changes)
+    pub creator: Pubkey,
+    /// Creator-chosen identifier distinguishing machines of the same creator
+    pub machine_id: u64,
     /// PDA bump seed for this account
     pub bump: u8,
     /// Whether the machine has been finalized (no more keys can be added)
@@ -24,29 +31,199 @@ pub struct GachaState {
     pub pull_count: u64,
     /// Total number of settlements completed
     pub settle_count: u64,
-    /// Pool of encrypted reward keys
-    pub encrypted_keys: Vec<String>,
+    /// Total number of pulls refunded instead of settled
+    pub refund_count: u64,
+    /// Total number of settled pulls whose key has been claimed
+    pub claim_count: u64,
+    /// Slots after a pull before its user may refund it (0 disables user refunds)
+    pub refund_timeout_slots: u64,
+    /// Consecutive settled pulls without a top-tier result after which the next
+    /// pull is forced into the top tier (0 disables pity)
+    pub pity_threshold: u32,
+    /// Provider of the randomness used to settle pulls
+    pub randomness_source: RandomnessSource,
+    /// Commit-reveal: hash commitment of the next server seed to reveal (the
+    /// latest revealed seed once a batch has been revealed)
+    pub seed_commitment: [u8; 32],
+    /// Commit-reveal: number of server seeds revealed so far; pulls made in epoch
+    /// `e` are settled with the `e + 1`-th revealed seed
+    pub seed_epoch: u32,
+    /// Share of a pull's price paid to a third party settling it, in basis points
+    /// (0 disables crank tips)
+    pub crank_tip_bps: u16,
+    /// Value (micro-USD) of the machine's most valuable prize as declared by the
+    /// admin; instant pulls are enabled when non-zero. Advisory only: no pull or
+    /// prize is checked against it
+    pub instant_max_prize_value: u64,
+    /// Where the encrypted keys are stored (key pages or a Merkle commitment)
+    pub key_storage: KeyStorage,
+    /// Merkle root of the key list (only used with KeyStorage::Merkle)
+    pub key_root: [u8; 32],
+    /// Total number of encrypted keys added across all key pages (or committed to)
+    pub total_keys: u16,
+    /// Number of key pages created for this machine
+    pub key_page_count: u16,
     // Decryption Key, will be revealed after all pulls(max_len: 120 )
     pub decryption_key: String,
-    /// Remaining indices for fair randomization (Fisher-Yates approach)
-    pub remaining_indices: Vec<u16>,
+    /// Rarity tiers with their drop weights and remaining key indices
+    /// (tier 0 is the most common, the last tier is the top tier)
+    pub tiers: Vec<RarityTier>,
     /// List of valid payment configuration accounts
     pub payment_configs: Vec<Pubkey>,
+    /// Roles granted by the admin to other accounts
+    pub roles: Vec<RoleGrant>,
 }
 
 /// Calculate initial size for GachaState account allocation
 impl GachaState {
     pub const INITIAL_SIZE: usize = 32 // admin pubkey
+    + 1 + 32 // pending_admin (option tag + pubkey)
+    + 32 // creator pubkey
+    + 8 // machine_id
     + 1 // bump
     + 1 // is_finalized
     + 1 // is_paused
     + 1 // is_halted
     + 8 // pull_count
     + 8 // settle_count
-    + 4 // encrypted_keys vector discriminator (empty initially)
+    + 8 // refund_count
+    + 8 // claim_count
+    + 8 // refund_timeout_slots
+    + 4 // pity_threshold
+    + 1 // randomness_source
+    + 32 // seed_commitment
+    + 4 // seed_epoch
+    + 2 // crank_tip_bps
+    + 8 // instant_max_prize_value
+    + 1 // key_storage
+    + 32 // key_root
+    + 2 // total_keys
+    + 2 // key_page_count
     + 4 + 120 // decryption_key (discriminator + max_len)
-    + 4 // remaining_indices vector discriminator (empty initially)
-    + 4; // payment_configs vector discriminator (empty initially)
+    + 4 + RarityTier::INITIAL_SIZE // tiers vector discriminator + default tier (no keys)
+    + 4 // payment_configs vector discriminator (empty initially)
+    + 4; // roles vector discriminator (empty initially)
+}
+
+impl GachaState {
+    /// Whether the machine can be torn down: every key has been settled (or
+    /// every pull, if any, was refunded) and every settled pull has claimed its
+    /// key, so no result still needs the key pages or the machine state
+    ///
+    /// Multi-pulls count once per pull in both `pull_count` and `refund_count`.
+    pub fn is_closable(&self) -> bool {
+        (self.settle_count == self.total_keys as u64 || self.pull_count == self.refund_count)
+            && self.claim_count == self.settle_count
+    }
+
+    /// Whether `account` may perform actions restricted to `role`
+    ///
+    /// The admin holds every role.
+    pub fn has_role(&self, account: &Pubkey, role: Role) -> bool {
+        *account == self.admin
+            || self
+                .roles
+                .iter()
+                .any(|grant| grant.account == *account && grant.role == role)
+    }
+
+    /// Number of keys not drawn yet across all tiers
+    pub fn remaining_keys(&self) -> usize {
+        self.tiers
+            .iter()
+            .map(|tier| tier.remaining_indices.len())
+            .sum()
+    }
+}
+
+/// A permission the admin can delegate to another account
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum Role {
+    /// Can pause/unpause pulls and halt/unhalt settlements
+    Pauser,
+    /// Can add keys, configure tiers and finalize the machine
+    KeyManager,
+    /// Can add, remove and update payment configurations
+    PricingManager,
+    /// Can withdraw settled payments to the configured recipients
+    Treasurer,
+}
+
+/// A role granted to an account
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct RoleGrant {
+    /// Account holding the role
+    pub account: Pubkey,
+    /// The granted role
+    pub role: Role,
+}
+
+/// A rarity tier of a gacha machine
+///
+/// Settlement first picks a tier with probability proportional to its weight
+/// (among tiers that still have keys), then a key uniformly within the tier.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct RarityTier {
+    /// Relative drop weight of this tier
+    pub weight: u32,
+    /// Total number of keys assigned to this tier
+    pub key_count: u16,
+    /// Indices of this tier's keys that have not been drawn yet
+    pub remaining_indices: Vec<u16>,
+}
+
+/// Calculate initial size for RarityTier allocation
+impl RarityTier {
+    pub const INITIAL_SIZE: usize = 4 // weight
+    + 2 // key_count
+    + 4; // remaining_indices vector discriminator (empty initially)
+}
+
+/// Provider of the randomness a gacha machine settles pulls with
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum RandomnessSource {
+    /// Switchboard on-demand randomness accounts committed by the user
+    Switchboard,
+    /// Deterministic values derived from the machine, user and nonce; only
+    /// available in builds with the `test-randomness` feature
+    Deterministic,
+    /// Server seeds committed at finalization and revealed in batches, mixed with
+    /// a client seed and the slot hash of each pull
+    CommitReveal,
+}
+
+/// Storage mode of a gacha machine's encrypted keys
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum KeyStorage {
+    /// Keys are stored on-chain in `KeyPage` accounts
+    Paged,
+    /// Only a Merkle root of the keys is stored; winners prove their key when claiming
+    Merkle,
+}
+
+/// A page of encrypted reward keys belonging to a gacha machine
+///
+/// Keys are split across pages of `KEYS_PER_PAGE` entries so a machine is not
+/// limited by the size of a single account. Key `i` lives in page
+/// `i / KEYS_PER_PAGE` at position `i % KEYS_PER_PAGE`.
+#[account]
+pub struct KeyPage {
+    /// Reference to the gacha machine this page belongs to
+    pub gacha_state: Pubkey,
+    /// Position of this page among the machine's pages
+    pub page_index: u16,
+    /// PDA bump seed for this account
+    pub bump: u8,
+    /// Encrypted reward keys stored in this page
+    pub keys: Vec<String>,
+}
+
+/// Calculate initial size for KeyPage account allocation
+impl KeyPage {
+    pub const INITIAL_SIZE: usize = 32 // gacha_state pubkey
+    + 2 // page_index
+    + 1 // bump
+    + 4; // keys vector discriminator (empty initially)
 }
 
 /// Player state for tracking individual pulls and settlements
@@ -66,9 +243,17 @@ pub struct PlayerState {
     pub payment_mint: Pubkey,
     /// Whether this pull has been settled
     pub is_settled: bool,
+    /// Whether this pull has been refunded instead of settled
+    pub is_refunded: bool,
+    /// Amount paid for this pull (held in the payment vault until settlement)
+    pub price_paid: u64,
+    /// Whether the winning key has been copied from its key page
+    pub is_claimed: bool,
     /// Index of the winning key (set during settlement)
     pub result_index: u16,
-    /// The actual encrypted key won (set during settlement)
+    /// Rarity tier of the winning key (set during settlement)
+    pub result_tier: u8,
+    /// The actual encrypted key won (set when the key is claimed)
     #[max_len(120)]
     pub winning_encrypted_key: String,
     /// PDA bump seed for this account
@@ -77,12 +262,118 @@ pub struct PlayerState {
     pub pull_slot: u64,
     /// Nonce from gacha machine (for PDA derivation)
     pub nonce: u64,
+    /// Randomness commitment bound to this pull
+    pub commitment: PullCommitment,
+}
+
+/// Multi-pull state tracking a batch of pulls paid in a single transaction
+///
+/// A multi-pull reserves `count` consecutive pull nonces starting at `nonce` and is
+/// settled with a single randomness value. Each reserved slot is claimed separately;
+/// `winning_encrypted_keys` holds an empty string for slots not claimed yet.
+#[account]
+#[derive(InitSpace)]
+pub struct MultiPullState {
+    /// Public key of the user who performed the multi-pull
+    pub user: Pubkey,
+    /// Reference to the gacha machine used
+    pub gacha_state: Pubkey,
+    /// Switchboard randomness account used for this multi-pull
+    pub randomness_account: Pubkey,
+    /// Payment mint used for this multi-pull
+    pub payment_mint: Pubkey,
+    /// Number of pulls reserved by this multi-pull
+    pub count: u8,
+    /// Whether this multi-pull has been settled
+    pub is_settled: bool,
+    /// Whether this multi-pull has been refunded instead of settled
+    pub is_refunded: bool,
+    /// Total amount paid for all pulls (held in the payment vault until settlement)
+    pub price_paid: u64,
+    /// Indices of the winning keys, one per reserved pull (set during settlement)
+    #[max_len(10)]
+    pub result_indices: Vec<u16>,
+    /// Rarity tiers of the winning keys, one per reserved pull (set during settlement)
+    #[max_len(10)]
+    pub result_tiers: Vec<u8>,
+    /// Encrypted keys won, one per reserved pull (set when each key is claimed)
+    #[max_len(10, 120)]
+    pub winning_encrypted_keys: Vec<String>,
+    /// PDA bump seed for this account
+    pub bump: u8,
+    /// Slot when the multi-pull was performed (for randomness validation)
+    pub pull_slot: u64,
+    /// First pull nonce reserved by this multi-pull (for PDA derivation)
+    pub nonce: u64,
+    /// Randomness commitment bound to this multi-pull
+    pub commitment: PullCommitment,
+}
+
+/// Randomness commitment a pull is bound to, recorded at pull time
+///
+/// Only the fields of the machine's randomness source are meaningful.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Default)]
+pub struct PullCommitment {
+    /// Seed slot of the Switchboard randomness commitment
+    pub seed_slot: u64,
+    /// Server seed epoch the pull is settled with (commit-reveal)
+    pub seed_epoch: u32,
+    /// User-supplied client seed (commit-reveal)
+    pub client_seed: [u8; 32],
+    /// Hash of the first slot after the pull, sealed by `seal_pull` or at settlement
+    /// (commit-reveal; zero until sealed)
+    pub slot_hash: [u8; 32],
+}
+
+impl PullCommitment {
+    /// Whether the slot hash of a commit-reveal pull has been sealed
+    pub fn is_sealed(&self) -> bool {
+        self.slot_hash != [0; 32]
+    }
+}
+
+/// Per-user statistics for a gacha machine
+///
+/// Created on the user's first pull and updated at every settlement to drive the
+/// pity mechanic.
+#[account]
+#[derive(InitSpace)]
+pub struct UserStats {
+    /// Public key of the user
+    pub user: Pubkey,
+    /// Reference to the gacha machine
+    pub gacha_state: Pubkey,
+    /// Settled pulls since the user's last top-tier result
+    pub pulls_since_top_tier: u32,
+    /// Total number of settled pulls of the user
+    pub total_settled: u64,
+    /// PDA bump seed for this account
+    pub bump: u8,
+}
+
+/// Marker of the last randomness commitment consumed by a pull
+///
+/// One per Switchboard randomness account (across all machines). A pull records the
+/// seed slot of the commitment it binds to, so the same commitment can never back
+/// two pulls; committing the account again yields a new seed slot.
+#[account]
+#[derive(InitSpace)]
+pub struct RandomnessUsage {
+    /// The Switchboard randomness account
+    pub randomness_account: Pubkey,
+    /// Seed slot of the last commitment consumed by a pull
+    pub seed_slot: u64,
+    /// PDA bump seed for this account
+    pub bump: u8,
 }
 
 /// Configuration for a payment method accepted by the gacha machine
 ///
 /// Defines how users can pay for pulls, including the token type, price, and destination.
 /// Supports both native SOL and SPL tokens.
+///
+/// The config also acts as the payment vault: SOL payments are held as lamports on this
+/// account, SPL payments in its associated token account (authority = this PDA).
 #[account]
 #[derive(InitSpace)]
 pub struct PaymentConfig {
@@ -96,4 +387,37 @@ pub struct PaymentConfig {
     pub admin_recipient_account: Pubkey,
     /// PDA bump seed for this account
     pub bump: u8,
+    /// Payments held in the vault for pulls that are not settled yet (refundable)
+    pub escrowed_amount: u64,
+    /// Payments released from escrow by settled pulls
+    pub settled_amount: u64,
+    /// Settled payments withdrawn by the admin
+    pub withdrawn_amount: u64,
+    /// Discount in basis points applied to multi-pulls of more than one pull
+    pub bulk_discount_bps: u16,
+    /// USD-pegged pricing; when set, `price` is ignored and the charge is
+    /// converted from USD with the price feed at pull time
+    pub usd_pricing: Option<UsdPricing>,
+}
+
+/// USD price of a payment config, converted with a Switchboard pull feed
+///
+/// The feed must quote the USD value of one whole token (e.g. SOL/USD).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct UsdPricing {
+    /// Price of a pull in micro-USD (6 decimals)
+    pub usd_price: u64,
+    /// Switchboard on-demand pull feed quoting the token in USD
+    pub price_feed: Pubkey,
+    /// Maximum age of the feed result in slots
+    pub max_staleness_slots: u64,
+    /// Maximum spread (max - min of the oracle samples) relative to the feed value, in basis points
+    pub max_deviation_bps: u16,
+}
+
+impl PaymentConfig {
+    /// Settled payments still held in the vault and available for withdrawal
+    pub fn withdrawable_amount(&self) -> u64 {
+        self.settled_amount - self.withdrawn_amount
+    }
 }
@@ -22,6 +22,9 @@ async function checkPaymentConfigs(network: string) {
         console.log(`  - mint: ${config.mint}`);
         console.log(`  - price: ${config.price}`);
         console.log(`  - admin recipient: ${config.adminRecipientAccount}`);
+        console.log(`  - escrowed: ${config.escrowedAmount}`);
+        console.log(`  - settled: ${config.settledAmount}`);
+        console.log(`  - withdrawn: ${config.withdrawnAmount}`);
         console.log("");
       } catch (e: any) {
         console.log(`Failed to fetch config ${configPda}: ${e.message}`);
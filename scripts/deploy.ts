@@ -17,7 +17,7 @@ import {
   keypairFromPrivateKey,
   createAnchorProgramAndClient,
 } from "./utils";
-import { GachaClient } from "./gacha-client";
+import { GachaClient, KEYS_PER_PAGE } from "./gacha-client";
 
 async function addPaymentConfig(
   client: GachaClient,
@@ -97,6 +97,24 @@ async function addPaymentConfig(
       console.log(
         `Payment config for ${currency.ticker} successful! Tx: ${paymentTx}`
       );
+
+      // SPL payments are escrowed in the payment config's associated token account
+      if (!paymentMint.equals(SystemProgram.programId)) {
+        const tokenProgram = await client.getTokenProgram(
+          paymentMint.toBase58()
+        );
+        const vault = await getOrCreateAssociatedTokenAccount(
+          client.provider.connection,
+          admin,
+          paymentMint,
+          paymentConfig,
+          true,
+          undefined,
+          undefined,
+          tokenProgram
+        );
+        console.log(`Payment Vault: ${vault.address.toBase58()}`);
+      }
     } catch (error) {
       console.error(
         `Failed to add payment config for ${currency.ticker}:`,
@@ -132,8 +150,7 @@ async function populateKeys(
   const urls: string[] = JSON.parse(await fs.readFile(URLS_FILE_PATH, "utf-8"));
   console.log(`Found ${urls.length} URLs to add.`);
 
-  const currentState = await client.getGachaState();
-  const existingEncryptedKeys = currentState.encryptedKeys;
+  const existingEncryptedKeys = await client.getEncryptedKeys();
   console.log(
     `${existingEncryptedKeys.length} keys already present in the contract.`
   );
@@ -155,23 +172,29 @@ async function populateKeys(
     `${keysToAdd.length} new keys to add out of ${urls.length} total URLs.`
   );
 
-  // Add the filtered keys
-  for (let i = 0; i < keysToAdd.length; i++) {
-    const encryptedUrl = keysToAdd[i];
-    const originalIndex = allEncryptedUrls.indexOf(encryptedUrl);
+  // Add the filtered keys in batches that never cross a key page boundary and
+  // stay well within the transaction size limit
+  const MAX_KEYS_PER_BATCH = 5;
+  let nextIndex = existingEncryptedKeys.length;
+  for (let i = 0; i < keysToAdd.length; ) {
+    const roomInPage = KEYS_PER_PAGE - (nextIndex % KEYS_PER_PAGE);
+    const batch = keysToAdd.slice(
+      i,
+      i + Math.min(MAX_KEYS_PER_BATCH, roomInPage)
+    );
 
     console.log(
-      `Adding key ${i + 1}/${keysToAdd.length} (original URL ${
-        originalIndex + 1
-      }): ${encryptedUrl}...`
+      `Adding keys ${i + 1}-${i + batch.length}/${keysToAdd.length}...`
     );
     try {
-      const addKeyTx = await client.addKey(admin, encryptedUrl);
-      console.log(` -> Success! Tx: ${addKeyTx}`);
+      const addKeysTx = await client.addKeys(admin, batch);
+      console.log(` -> Success! Tx: ${addKeysTx}`);
+      nextIndex += batch.length;
     } catch (err) {
-      console.error(` -> Failed to add key ${i + 1}:`, err.message);
+      console.error(` -> Failed to add keys ${i + 1}-${i + batch.length}:`, err.message);
       // Optional: break or continue on failure
     }
+    i += batch.length;
 
     // Avoid RPC rate limits on devnet
     await sleep(500); // 0.5 second delay
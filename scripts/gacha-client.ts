@@ -5,22 +5,49 @@ import { Program } from "@coral-xyz/anchor";
 import { GachaMachine } from "../target/types/gacha_machine"; // Give it to Client
 
 // import { GachaMachine } from "../../target/types/GachaMachine"; // Adjust path to your Anchor types
-import { PublicKey, SystemProgram, Keypair } from "@solana/web3.js";
-import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import {
+  PublicKey,
+  SystemProgram,
+  Keypair,
+  SYSVAR_SLOT_HASHES_PUBKEY,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+} from "@solana/web3.js";
+import { randomBytes } from "crypto";
+import {
+  TOKEN_PROGRAM_ID,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
 import { PaymentType } from "./utils";
 
+// Must match KEYS_PER_PAGE in programs/gacha-machine/src/constants.rs
+export const KEYS_PER_PAGE = 64;
+
+// Roles the admin can delegate (variants of the program's Role enum)
+export type Role = "pauser" | "keyManager" | "pricingManager" | "treasurer";
+
 export class GachaClient {
   program: Program<GachaMachine>;
   provider: anchor.AnchorProvider;
   gachaStatePDA: PublicKey;
+  machineId: anchor.BN;
 
-  constructor(program: Program<GachaMachine>, provider: anchor.AnchorProvider) {
+  constructor(
+    program: Program<GachaMachine>,
+    provider: anchor.AnchorProvider,
+    creator: PublicKey,
+    machineId: anchor.BN
+  ) {
     this.program = program;
     this.provider = provider;
+    this.machineId = machineId;
 
-    // Derive the main GachaState PDA
+    // Derive the GachaState PDA of the machine (creator + machine id)
     const [pda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("gacha_state")],
+      [
+        Buffer.from("gacha_state"),
+        creator.toBuffer(),
+        machineId.toArrayLike(Buffer, "le", 8),
+      ],
       this.program.programId
     );
     this.gachaStatePDA = pda;
@@ -42,13 +69,75 @@ export class GachaClient {
     return paymentConfigPDA;
   }
 
+  // Payments are held by the payment config: as lamports for SOL, in its ATA for SPL
+  findPaymentVault(paymentMint: string, tokenProgram = TOKEN_PROGRAM_ID) {
+    const paymentConfigPDA = this.findPaymentConfigPDA(paymentMint);
+    const mint = new PublicKey(paymentMint);
+    if (mint.equals(SystemProgram.programId)) {
+      return paymentConfigPDA;
+    }
+    return getAssociatedTokenAddressSync(
+      mint,
+      paymentConfigPDA,
+      true,
+      tokenProgram
+    );
+  }
+
+  // The token program owning a mint (Token or Token-2022), null for SOL
+  async getTokenProgram(paymentMint: string): Promise<PublicKey | null> {
+    const mint = new PublicKey(paymentMint);
+    if (mint.equals(SystemProgram.programId)) {
+      return null;
+    }
+    const mintAccount = await this.provider.connection.getAccountInfo(mint);
+    if (!mintAccount) {
+      throw new Error(`Mint ${paymentMint} not found`);
+    }
+    return mintAccount.owner;
+  }
+
   findPlayerStatePDA(user: PublicKey, nonce: anchor.BN) {
     const [playerStatePDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from("player_state"), user.toBuffer(), nonce.toBuffer("le", 8)],
+      [
+        Buffer.from("player_state"),
+        this.gachaStatePDA.toBuffer(),
+        user.toBuffer(),
+        nonce.toBuffer("le", 8),
+      ],
       this.program.programId
     );
     return playerStatePDA;
   }
+  findMultiPullStatePDA(user: PublicKey, nonce: anchor.BN) {
+    const [multiPullStatePDA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("multi_pull_state"),
+        this.gachaStatePDA.toBuffer(),
+        user.toBuffer(),
+        nonce.toBuffer("le", 8),
+      ],
+      this.program.programId
+    );
+    return multiPullStatePDA;
+  }
+  findUserStatsPDA(user: PublicKey) {
+    const [userStatsPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user_stats"), this.gachaStatePDA.toBuffer(), user.toBuffer()],
+      this.program.programId
+    );
+    return userStatsPDA;
+  }
+  findKeyPagePDA(pageIndex: number) {
+    const pageIndexBuffer = Buffer.alloc(2);
+    pageIndexBuffer.writeUInt16LE(pageIndex);
+    const [keyPagePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("key_page"), this.gachaStatePDA.toBuffer(), pageIndexBuffer],
+      this.program.programId
+    );
+    return keyPagePDA;
+  }
+
   // ========================================
   // Fetch Methods
   // ========================================
@@ -59,19 +148,45 @@ export class GachaClient {
     );
     return {
       admin: gachaState.admin.toBase58(),
+      creator: gachaState.creator.toBase58(),
+      machineId: gachaState.machineId.toNumber(),
       bump: gachaState.bump,
       isFinalized: gachaState.isFinalized,
       isPaused: gachaState.isPaused,
       pullCount: gachaState.pullCount.toNumber(),
       settleCount: gachaState.settleCount.toNumber(),
-      encryptedKeys: gachaState.encryptedKeys,
-      remainingIndices: gachaState.remainingIndices,
+      totalKeys: gachaState.totalKeys,
+      keyPageCount: gachaState.keyPageCount,
+      tiers: gachaState.tiers.map((tier) => ({
+        weight: tier.weight,
+        keyCount: tier.keyCount,
+        remainingIndices: tier.remainingIndices,
+      })),
       paymentConfigs: gachaState.paymentConfigs.map((config) =>
         config.toBase58()
       ),
     };
   }
 
+  async getEncryptedKeys(): Promise<string[]> {
+    const { keyPageCount } = await this.getGachaState();
+    const keys: string[] = [];
+    for (let pageIndex = 0; pageIndex < keyPageCount; pageIndex++) {
+      const keyPage = await this.program.account.keyPage.fetch(
+        this.findKeyPagePDA(pageIndex)
+      );
+      keys.push(...keyPage.keys);
+    }
+    return keys;
+  }
+
+  // Pity counter of a user (null before the user's first pull)
+  async getUserStats(user: PublicKey) {
+    return this.program.account.userStats.fetchNullable(
+      this.findUserStatsPDA(user)
+    );
+  }
+
   async getPaymentConfig(paymentMint: string) {
     const paymentConfigPDA = this.findPaymentConfigPDA(paymentMint);
     const paymentConfig = await this.program.account.paymentConfig.fetch(
@@ -84,14 +199,14 @@ export class GachaClient {
       price: paymentConfig.price.toNumber(),
       adminRecipientAccount: paymentConfig.adminRecipientAccount.toBase58(),
       bump: paymentConfig.bump,
+      escrowedAmount: paymentConfig.escrowedAmount.toNumber(),
+      settledAmount: paymentConfig.settledAmount.toNumber(),
+      withdrawnAmount: paymentConfig.withdrawnAmount.toNumber(),
     };
   }
 
   async getPlayerState(user: PublicKey, nonce: anchor.BN) {
-    const [playerStatePDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from("player_state"), user.toBuffer(), nonce.toBuffer("le", 8)],
-      this.program.programId
-    );
+    const playerStatePDA = this.findPlayerStatePDA(user, nonce);
     const playerState = await this.program.account.playerState.fetch(
       playerStatePDA
     );
@@ -101,6 +216,9 @@ export class GachaClient {
       randomnessAccount: playerState.randomnessAccount.toBase58(),
       paymentMint: playerState.paymentMint.toBase58(),
       isSettled: playerState.isSettled,
+      isRefunded: playerState.isRefunded,
+      pricePaid: playerState.pricePaid.toNumber(),
+      isClaimed: playerState.isClaimed,
       resultIndex: playerState.resultIndex,
       winningEncryptedKey: playerState.winningEncryptedKey,
       bump: playerState.bump,
@@ -115,7 +233,7 @@ export class GachaClient {
 
   async initialize(admin: Keypair) {
     return this.program.methods
-      .initialize()
+      .initialize(this.machineId)
       .accountsPartial({
         gachaState: this.gachaStatePDA,
         admin: admin.publicKey,
@@ -134,35 +252,353 @@ export class GachaClient {
   ) {
     return this.program.methods
       .addPaymentConfig(paymentMint, paymentPrice, paymentRecipientAccount)
-      .accounts({
-        // paymentConfig,
-        // admin,
+      .accountsPartial({
+        paymentConfig,
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+        paymentMintAccount: paymentMint,
+        recipientAccount: paymentRecipientAccount,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Change the price and/or recipient of a payment config without removing it
+  async updatePaymentConfig(
+    admin: Keypair,
+    paymentMint: string,
+    paymentPrice: anchor.BN | null,
+    newRecipientAccount: PublicKey | null = null
+  ) {
+    return this.program.methods
+      .updatePaymentConfig(new PublicKey(paymentMint), paymentPrice)
+      .accountsPartial({
+        paymentConfig: this.findPaymentConfigPDA(paymentMint),
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+        newRecipientAccount,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Peg a payment config to a USD price converted with a Switchboard pull feed
+  // (usdPrice in micro-USD); pass null to go back to the fixed price
+  async setUsdPricing(
+    admin: Keypair,
+    paymentMint: string,
+    usdPricing: {
+      usdPrice: anchor.BN;
+      priceFeed: PublicKey;
+      maxStalenessSlots: anchor.BN;
+      maxDeviationBps: number;
+    } | null
+  ) {
+    return this.program.methods
+      .setUsdPricing(new PublicKey(paymentMint), usdPricing)
+      .accountsPartial({
+        paymentConfig: this.findPaymentConfigPDA(paymentMint),
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+        priceFeed: usdPricing ? usdPricing.priceFeed : null,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Price feed a pull must pass for USD-priced payment configs
+  async getPriceFeed(paymentConfig: PublicKey): Promise<PublicKey | null> {
+    const { usdPricing } = await this.program.account.paymentConfig.fetch(
+      paymentConfig
+    );
+    return usdPricing ? usdPricing.priceFeed : null;
+  }
+
+  // Usage marker a pull must pass: only Switchboard machines track their
+  // randomness accounts, other sources create no marker
+  async getRandomnessUsage(
+    randomnessAccount: PublicKey
+  ): Promise<PublicKey | null> {
+    const { randomnessSource } = await this.program.account.gachaState.fetch(
+      this.gachaStatePDA
+    );
+    if (!("switchboard" in randomnessSource)) {
+      return null;
+    }
+    const [randomnessUsagePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("randomness_usage"), randomnessAccount.toBuffer()],
+      this.program.programId
+    );
+    return randomnessUsagePDA;
+  }
+
+  // Discount (in basis points) applied to multi-pulls of more than one pull
+  async setBulkDiscount(
+    admin: Keypair,
+    paymentMint: string,
+    bulkDiscountBps: number
+  ) {
+    return this.program.methods
+      .setBulkDiscount(new PublicKey(paymentMint), bulkDiscountBps)
+      .accountsPartial({
+        paymentConfig: this.findPaymentConfigPDA(paymentMint),
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Withdraw settled payments of a payment config to its admin recipient
+  async withdraw(admin: Keypair, paymentMint: string, amount: anchor.BN) {
+    const mint = new PublicKey(paymentMint);
+    const { adminRecipientAccount } = await this.getPaymentConfig(paymentMint);
+    const tokenProgram = await this.getTokenProgram(paymentMint);
+
+    return this.program.methods
+      .withdraw(mint, amount)
+      .accountsPartial({
+        paymentConfig: this.findPaymentConfigPDA(paymentMint),
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+        paymentMintAccount: mint,
+        paymentVault: this.findPaymentVault(
+          paymentMint,
+          tokenProgram ?? undefined
+        ),
+        adminRecipientAccount: new PublicKey(adminRecipientAccount),
+        tokenProgram,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async addKeyPage(admin: Keypair) {
+    return this.program.methods
+      .addKeyPage()
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async addKey(admin: Keypair, encryptedKey: string, tier = 0) {
+    // Keys are appended to the page holding the next key index
+    const { totalKeys, keyPageCount } = await this.getGachaState();
+    const pageIndex = Math.floor(totalKeys / KEYS_PER_PAGE);
+    if (pageIndex >= keyPageCount) {
+      await this.addKeyPage(admin);
+    }
+
+    return this.program.methods
+      .addKey(encryptedKey, tier)
+      .accountsPartial({
+        keyPage: this.findKeyPagePDA(pageIndex),
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async addKeys(admin: Keypair, encryptedKeys: string[], tier = 0) {
+    // A batch must fit in the page holding the next key index
+    const { totalKeys, keyPageCount } = await this.getGachaState();
+    const pageIndex = Math.floor(totalKeys / KEYS_PER_PAGE);
+    if (pageIndex >= keyPageCount) {
+      await this.addKeyPage(admin);
+    }
+
+    return this.program.methods
+      .addKeys(encryptedKeys, tier)
+      .accountsPartial({
+        keyPage: this.findKeyPagePDA(pageIndex),
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Drop weights per tier (tier 0 first, top tier last)
+  async setTierWeights(admin: Keypair, weights: number[]) {
+    return this.program.methods
+      .setTierWeights(weights)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Commit-reveal machines commit the head of their server seed hash chain here
+  async finalize(admin: Keypair, serverSeedHash: Buffer | null = null) {
+    return this.program.methods
+      .finalize(serverSeedHash ? Array.from(serverSeedHash) : null)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // The committed key list must be ordered by tier (tier 0 first)
+  async finalizeWithRoot(
+    admin: Keypair,
+    keyRoot: Buffer,
+    totalKeys: number,
+    tierKeyCounts: number[] = [totalKeys],
+    serverSeedHash: Buffer | null = null
+  ) {
+    return this.program.methods
+      .finalizeWithRoot(
+        Array.from(keyRoot),
+        totalKeys,
+        tierKeyCounts,
+        serverSeedHash ? Array.from(serverSeedHash) : null
+      )
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Reveal the preimage of the current server seed commitment (commit-reveal)
+  async revealServerSeed(admin: Keypair, serverSeed: Buffer) {
+    return this.program.methods
+      .revealServerSeed(Array.from(serverSeed))
+      .accountsPartial({
         gachaState: this.gachaStatePDA,
+        authority: admin.publicKey,
       })
       .signers([admin])
       .rpc();
   }
 
-  async addKey(admin: Keypair, encryptedKey: string) {
+  // Two-step admin transfer: propose, then accept with the new admin's signature
+  async proposeAdmin(admin: Keypair, newAdmin: PublicKey) {
+    return this.program.methods
+      .proposeAdmin(newAdmin)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async acceptAdmin(newAdmin: Keypair) {
+    return this.program.methods
+      .acceptAdmin()
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        newAdmin: newAdmin.publicKey,
+      })
+      .signers([newAdmin])
+      .rpc();
+  }
+
+  async cancelAdminTransfer(admin: Keypair) {
     return this.program.methods
-      .addKey(encryptedKey)
-      .accounts({
+      .cancelAdminTransfer()
+      .accountsPartial({
         gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
       })
       .signers([admin])
       .rpc();
   }
 
-  async finalize(admin: Keypair) {
+  // Delegate a role (pauser, key manager, pricing manager, treasurer) to an account
+  async grantRole(admin: Keypair, account: PublicKey, role: Role) {
     return this.program.methods
-      .finalize()
-      .accounts({
+      .grantRole(account, { [role]: {} } as any)
+      .accountsPartial({
         gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
       })
       .signers([admin])
       .rpc();
   }
 
+  async revokeRole(admin: Keypair, account: PublicKey, role: Role) {
+    return this.program.methods
+      .revokeRole(account, { [role]: {} } as any)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Close the last key page of a finished machine
+  async closeKeyPage(admin: Keypair) {
+    const { keyPageCount } = await this.getGachaState();
+
+    return this.program.methods
+      .closeKeyPage()
+      .accountsPartial({
+        keyPage: this.findKeyPagePDA(keyPageCount - 1),
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Close a finished machine, all of its payment configs and their SPL vaults
+  async closeMachine(admin: Keypair) {
+    const { paymentConfigs } = await this.getGachaState();
+
+    // Each SPL config is followed by its vault, mint and token program
+    const remainingAccounts = [];
+    for (const config of paymentConfigs) {
+      const paymentConfig = await this.program.account.paymentConfig.fetch(
+        config
+      );
+      remainingAccounts.push({
+        pubkey: new PublicKey(config),
+        isSigner: false,
+        isWritable: true,
+      });
+      const tokenProgram = await this.getTokenProgram(
+        paymentConfig.mint.toBase58()
+      );
+      if (!tokenProgram) {
+        continue;
+      }
+      remainingAccounts.push(
+        {
+          pubkey: this.findPaymentVault(
+            paymentConfig.mint.toBase58(),
+            tokenProgram
+          ),
+          isSigner: false,
+          isWritable: true,
+        },
+        { pubkey: paymentConfig.mint, isSigner: false, isWritable: true },
+        { pubkey: tokenProgram, isSigner: false, isWritable: false }
+      );
+    }
+
+    return this.program.methods
+      .closeMachine()
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .remainingAccounts(remainingAccounts)
+      .signers([admin])
+      .rpc();
+  }
+
   // ========================================
   // User Instructions
   // ========================================
@@ -173,27 +609,30 @@ export class GachaClient {
     paymentMint: PublicKey,
     paymentConfig: PublicKey,
     userPaymentAccount: PublicKey,
-    adminRecipientAccount: PublicKey,
-    randomnessAccount: PublicKey
+    paymentVault: PublicKey,
+    randomnessAccount: PublicKey,
+    maxPrice: anchor.BN,
+    clientSeed: Buffer = randomBytes(32)
   ): Promise<anchor.web3.TransactionInstruction> {
-    const instruction = this.program.methods.pull().accountsPartial({
-      user: user,
-      paymentMint: paymentMint,
-      paymentConfig: paymentConfig,
-      userPaymentAccount: userPaymentAccount,
-      adminRecipientAccount: adminRecipientAccount,
-      randomnessAccountData: randomnessAccount,
-    });
-    // Add token program only for SPL payments
-    if (paymentType === PaymentType.SPL) {
-      instruction.remainingAccounts([
-        {
-          pubkey: TOKEN_PROGRAM_ID,
-          isSigner: false,
-          isWritable: false,
-        },
-      ]);
-    }
+    // Token program only for SPL payments (Token or Token-2022, from the mint owner)
+    const tokenProgram =
+      paymentType === PaymentType.SPL
+        ? await this.getTokenProgram(paymentMint.toBase58())
+        : null;
+    const priceFeed = await this.getPriceFeed(paymentConfig);
+    const instruction = this.program.methods
+      .pull(maxPrice, Array.from(clientSeed))
+      .accountsPartial({
+        user: user,
+        paymentMint: paymentMint,
+        paymentConfig: paymentConfig,
+        userPaymentAccount: userPaymentAccount,
+        paymentVault: paymentVault,
+        randomnessAccountData: randomnessAccount,
+        randomnessUsage: await this.getRandomnessUsage(randomnessAccount),
+        priceFeed,
+        tokenProgram,
+      });
     return await instruction.instruction();
 
     // return this.program.methods
@@ -211,16 +650,425 @@ export class GachaClient {
     //   .rpc();
   }
 
-  async settle(user: Keypair, nonce: anchor.BN, randomnessAccount: PublicKey) {
-    const [playerStatePDA] = PublicKey.findProgramAddressSync(
-      [
-        Buffer.from("player_state"),
-        user.publicKey.toBuffer(),
-        nonce.toBuffer("le", 8),
-      ],
-      this.program.programId
+  // Instant pull (machines with instant mode enabled): pays and draws the key in
+  // one instruction, which must be the last one of its transaction
+  async pullInstantInstruction(
+    user: PublicKey,
+    paymentType: PaymentType,
+    paymentMint: PublicKey,
+    paymentConfig: PublicKey,
+    userPaymentAccount: PublicKey,
+    paymentVault: PublicKey,
+    maxPrice: anchor.BN
+  ): Promise<anchor.web3.TransactionInstruction> {
+    const tokenProgram =
+      paymentType === PaymentType.SPL
+        ? await this.getTokenProgram(paymentMint.toBase58())
+        : null;
+    const priceFeed = await this.getPriceFeed(paymentConfig);
+    return this.program.methods
+      .pullInstant(maxPrice)
+      .accountsPartial({
+        user,
+        paymentMint,
+        paymentConfig,
+        userPaymentAccount,
+        paymentVault,
+        slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+        instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+        priceFeed,
+        tokenProgram,
+      })
+      .instruction();
+  }
+
+  // Seal a commit-reveal pull with the hash of the first slot after it (paid by
+  // the provider wallet); anyone can seal, and must do so within 512 slots
+  // unless the pull is settled by then
+  async sealPull(user: PublicKey, nonce: anchor.BN) {
+    return this.program.methods
+      .sealPull()
+      .accountsPartial({
+        playerState: this.findPlayerStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+        slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      })
+      .rpc();
+  }
+
+  // Settle a pull; anyone can settle. A `caller` other than the user passes
+  // `callerPaymentAccount` (the caller for SOL, its token account for SPL) to
+  // receive the crank tip.
+  async settle(
+    caller: Keypair,
+    user: PublicKey,
+    nonce: anchor.BN,
+    randomnessAccount: PublicKey,
+    callerPaymentAccount: PublicKey | null = null
+  ) {
+    const playerStatePDA = this.findPlayerStatePDA(user, nonce);
+    const { paymentMint } = await this.getPlayerState(user, nonce);
+
+    return this.program.methods
+      .settle()
+      .accountsPartial({
+        playerState: playerStatePDA,
+        gachaState: this.gachaStatePDA,
+        paymentConfig: this.findPaymentConfigPDA(paymentMint),
+        userStats: this.findUserStatsPDA(user),
+        user,
+        caller: caller.publicKey,
+        randomnessAccountData: randomnessAccount,
+        slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+        ...(await this.crankTipAccounts(paymentMint, callerPaymentAccount)),
+      })
+      .signers([caller])
+      .rpc();
+  }
+
+  // Accounts paying the crank tip to `callerPaymentAccount` (none when null)
+  async crankTipAccounts(
+    paymentMint: string,
+    callerPaymentAccount: PublicKey | null
+  ) {
+    if (!callerPaymentAccount) {
+      return {
+        paymentMint: null,
+        paymentVault: null,
+        callerPaymentAccount: null,
+        tokenProgram: null,
+      };
+    }
+    const tokenProgram = await this.getTokenProgram(paymentMint);
+    return {
+      paymentMint: new PublicKey(paymentMint),
+      paymentVault: this.findPaymentVault(
+        paymentMint,
+        tokenProgram ?? undefined
+      ),
+      callerPaymentAccount,
+      tokenProgram,
+    };
+  }
+
+  // Refund an unsettled pull; `authority` is the pull's user or the admin
+  async refund(
+    authority: Keypair,
+    user: PublicKey,
+    nonce: anchor.BN,
+    userPaymentAccount: PublicKey
+  ) {
+    const { paymentMint, randomnessAccount } = await this.getPlayerState(
+      user,
+      nonce
     );
+    const mint = new PublicKey(paymentMint);
+    const tokenProgram = await this.getTokenProgram(paymentMint);
+
+    return this.program.methods
+      .refund()
+      .accountsPartial({
+        playerState: this.findPlayerStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+        paymentConfig: this.findPaymentConfigPDA(paymentMint),
+        authority: authority.publicKey,
+        user,
+        paymentMint: mint,
+        userPaymentAccount,
+        paymentVault: this.findPaymentVault(
+          paymentMint,
+          tokenProgram ?? undefined
+        ),
+        randomnessAccountData: new PublicKey(randomnessAccount),
+        tokenProgram,
+      })
+      .signers([authority])
+      .rpc();
+  }
+
+  // Consecutive pulls without a top-tier result before pity applies (0 disables)
+  // Select the randomness provider (only before finalization); "deterministic"
+  // requires a program built with the test-randomness feature
+  async setRandomnessSource(
+    admin: Keypair,
+    source: "switchboard" | "commitReveal" | "deterministic"
+  ) {
+    return this.program.methods
+      .setRandomnessSource({ [source]: {} } as any)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
 
-    return this.program.methods.settle().accounts({}).signers([user]).rpc();
+  async setPityThreshold(admin: Keypair, pityThreshold: number) {
+    return this.program.methods
+      .setPityThreshold(pityThreshold)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async setCrankTip(admin: Keypair, crankTipBps: number) {
+    return this.program.methods
+      .setCrankTip(crankTipBps)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  // Enable instant pulls by declaring the max prize value in micro-USD (0 disables)
+  async setInstantMode(admin: Keypair, maxPrizeValue: anchor.BN) {
+    return this.program.methods
+      .setInstantMode(maxPrizeValue)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async setRefundTimeout(admin: Keypair, refundTimeoutSlots: anchor.BN) {
+    return this.program.methods
+      .setRefundTimeout(refundTimeoutSlots)
+      .accountsPartial({
+        gachaState: this.gachaStatePDA,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async claimKeyWithProof(
+    payer: Keypair,
+    user: PublicKey,
+    nonce: anchor.BN,
+    encryptedKey: string,
+    proof: Buffer[]
+  ) {
+    return this.program.methods
+      .claimKeyWithProof(
+        encryptedKey,
+        proof.map((node) => Array.from(node))
+      )
+      .accountsPartial({
+        playerState: this.findPlayerStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+      })
+      .signers([payer])
+      .rpc();
+  }
+
+  async claimKey(payer: Keypair, user: PublicKey, nonce: anchor.BN) {
+    const { resultIndex } = await this.getPlayerState(user, nonce);
+
+    return this.program.methods
+      .claimKey()
+      .accountsPartial({
+        playerState: this.findPlayerStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+        keyPage: this.findKeyPagePDA(Math.floor(resultIndex / KEYS_PER_PAGE)),
+      })
+      .signers([payer])
+      .rpc();
+  }
+
+  // Close a claimed or refunded pull and return its rent to the user
+  async closePlayerState(user: Keypair, nonce: anchor.BN) {
+    return this.program.methods
+      .closePlayerState()
+      .accountsPartial({
+        playerState: this.findPlayerStatePDA(user.publicKey, nonce),
+        gachaState: this.gachaStatePDA,
+        user: user.publicKey,
+      })
+      .signers([user])
+      .rpc();
+  }
+
+  // ========================================
+  // Multi-Pull Instructions
+  // ========================================
+
+  async getMultiPullState(user: PublicKey, nonce: anchor.BN) {
+    return this.program.account.multiPullState.fetch(
+      this.findMultiPullStatePDA(user, nonce)
+    );
+  }
+
+  async pullManyInstruction(
+    user: PublicKey,
+    count: number,
+    paymentType: PaymentType,
+    paymentMint: PublicKey,
+    paymentConfig: PublicKey,
+    userPaymentAccount: PublicKey,
+    paymentVault: PublicKey,
+    randomnessAccount: PublicKey,
+    maxPrice: anchor.BN,
+    clientSeed: Buffer = randomBytes(32)
+  ): Promise<anchor.web3.TransactionInstruction> {
+    const tokenProgram =
+      paymentType === PaymentType.SPL
+        ? await this.getTokenProgram(paymentMint.toBase58())
+        : null;
+    const priceFeed = await this.getPriceFeed(paymentConfig);
+    return this.program.methods
+      .pullMany(count, maxPrice, Array.from(clientSeed))
+      .accountsPartial({
+        user,
+        paymentMint,
+        paymentConfig,
+        userPaymentAccount,
+        paymentVault,
+        randomnessAccountData: randomnessAccount,
+        randomnessUsage: await this.getRandomnessUsage(randomnessAccount),
+        priceFeed,
+        tokenProgram,
+      })
+      .instruction();
+  }
+
+  // Seal a commit-reveal multi-pull (see sealPull)
+  async sealMultiPull(user: PublicKey, nonce: anchor.BN) {
+    return this.program.methods
+      .sealMultiPull()
+      .accountsPartial({
+        multiPullState: this.findMultiPullStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+        slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      })
+      .rpc();
+  }
+
+  async settleMany(
+    caller: Keypair,
+    user: PublicKey,
+    nonce: anchor.BN,
+    randomnessAccount: PublicKey,
+    callerPaymentAccount: PublicKey | null = null
+  ) {
+    const { paymentMint } = await this.getMultiPullState(user, nonce);
+
+    return this.program.methods
+      .settleMany()
+      .accountsPartial({
+        multiPullState: this.findMultiPullStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+        paymentConfig: this.findPaymentConfigPDA(paymentMint.toBase58()),
+        userStats: this.findUserStatsPDA(user),
+        user,
+        caller: caller.publicKey,
+        randomnessAccountData: randomnessAccount,
+        slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+        ...(await this.crankTipAccounts(
+          paymentMint.toBase58(),
+          callerPaymentAccount
+        )),
+      })
+      .signers([caller])
+      .rpc();
+  }
+
+  // Refund an unsettled multi-pull; `authority` is the user or the admin
+  async refundMany(
+    authority: Keypair,
+    user: PublicKey,
+    nonce: anchor.BN,
+    userPaymentAccount: PublicKey
+  ) {
+    const { paymentMint, randomnessAccount } = await this.getMultiPullState(
+      user,
+      nonce
+    );
+    const mint = paymentMint.toBase58();
+    const tokenProgram = await this.getTokenProgram(mint);
+
+    return this.program.methods
+      .refundMany()
+      .accountsPartial({
+        multiPullState: this.findMultiPullStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+        paymentConfig: this.findPaymentConfigPDA(mint),
+        authority: authority.publicKey,
+        user,
+        paymentMint,
+        userPaymentAccount,
+        paymentVault: this.findPaymentVault(mint, tokenProgram ?? undefined),
+        randomnessAccountData: randomnessAccount,
+        tokenProgram,
+      })
+      .signers([authority])
+      .rpc();
+  }
+
+  // Claim all keys of a settled multi-pull from their key pages
+  async claimManyKeys(payer: Keypair, user: PublicKey, nonce: anchor.BN) {
+    const { resultIndices } = await this.getMultiPullState(user, nonce);
+    const pageIndices = [
+      ...new Set(
+        resultIndices.map((index: number) => Math.floor(index / KEYS_PER_PAGE))
+      ),
+    ];
+
+    return this.program.methods
+      .claimManyKeys()
+      .accountsPartial({
+        multiPullState: this.findMultiPullStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+      })
+      .remainingAccounts(
+        pageIndices.map((pageIndex) => ({
+          pubkey: this.findKeyPagePDA(pageIndex),
+          isSigner: false,
+          isWritable: false,
+        }))
+      )
+      .signers([payer])
+      .rpc();
+  }
+
+  async claimManyKeyWithProof(
+    payer: Keypair,
+    user: PublicKey,
+    nonce: anchor.BN,
+    position: number,
+    encryptedKey: string,
+    proof: Buffer[]
+  ) {
+    return this.program.methods
+      .claimManyKeyWithProof(
+        position,
+        encryptedKey,
+        proof.map((node) => Array.from(node))
+      )
+      .accountsPartial({
+        multiPullState: this.findMultiPullStatePDA(user, nonce),
+        gachaState: this.gachaStatePDA,
+      })
+      .signers([payer])
+      .rpc();
+  }
+
+  // Close a fully claimed or refunded multi-pull and return its rent to the user
+  async closeMultiPullState(user: Keypair, nonce: anchor.BN) {
+    return this.program.methods
+      .closeMultiPullState()
+      .accountsPartial({
+        multiPullState: this.findMultiPullStatePDA(user.publicKey, nonce),
+        gachaState: this.gachaStatePDA,
+        user: user.publicKey,
+      })
+      .signers([user])
+      .rpc();
   }
 }
@@ -103,7 +103,7 @@ async function executePullWithSwitchboard(
   paymentMint: PublicKey,
   paymentConfig: PublicKey,
   userPaymentAccount: PublicKey,
-  adminRecipientAccount: PublicKey,
+  paymentVault: PublicKey,
   randomness: sb.Randomness,
   gachaState: any
 ): Promise<string> {
@@ -120,6 +120,12 @@ async function executePullWithSwitchboard(
   const commitIx = await createCommitInstruction(randomness, queue);
   console.log("✅ Commit instruction created successfully");
 
+  // Never pay more than the price read here, even if the admin changes it meanwhile
+  // (USD-priced configs should quote the price from their feed instead)
+  const { price } = await client.program.account.paymentConfig.fetch(
+    paymentConfig
+  );
+
   // Get the pull instruction using the dedicated function
   const pullIx = await client.pullInstruction(
     user.publicKey,
@@ -127,8 +133,9 @@ async function executePullWithSwitchboard(
     paymentMint,
     paymentConfig,
     userPaymentAccount,
-    adminRecipientAccount,
-    randomness.pubkey
+    paymentVault,
+    randomness.pubkey,
+    price
   );
 
   // const pullIx = await createPullInstruction(
@@ -287,8 +294,11 @@ export async function pullGacha(
     );
   }
 
-  const { adminRecipientAccount } = await client.getPaymentConfig(
-    paymentMint.toBase58()
+  // Payments are escrowed in the payment config's vault until settlement
+  const tokenProgram = await client.getTokenProgram(paymentMint.toBase58());
+  const paymentVault = client.findPaymentVault(
+    paymentMint.toBase58(),
+    tokenProgram ?? undefined
   );
 
   // Create Switchboard randomness account
@@ -312,7 +322,7 @@ export async function pullGacha(
       paymentMint,
       paymentConfigPDA,
       userPaymentAccount,
-      new PublicKey(adminRecipientAccount),
+      paymentVault,
       randomness,
       gachaState
     )
@@ -388,7 +398,7 @@ async function settleGacha(
   // Create settle instruction
   const [settleIx, settleIxError] = await safeAsync(
     createSettleInstruction(
-      program,
+      client,
       user.publicKey,
       new anchor.BN(nonce),
       randomnessAccount
@@ -417,29 +427,19 @@ async function settleGacha(
  * Creates the settle instruction for the gacha machine.
  */
 async function createSettleInstruction(
-  gachaProgram: any,
+  client: GachaClient,
   userPublicKey: PublicKey,
   nonce: anchor.BN,
   randomnessAccount: PublicKey
 ): Promise<anchor.web3.TransactionInstruction> {
-  const [gachaStatePDA] = PublicKey.findProgramAddressSync(
-    [Buffer.from("gacha_state")],
-    gachaProgram.programId
-  );
-  const [playerStatePDA] = PublicKey.findProgramAddressSync(
-    [
-      Buffer.from("player_state"),
-      userPublicKey.toBuffer(),
-      nonce.toBuffer("le", 8),
-    ],
-    gachaProgram.programId
-  );
-  return await gachaProgram.methods
+  const playerStatePDA = client.findPlayerStatePDA(userPublicKey, nonce);
+  return await client.program.methods
     .settle()
     .accountsPartial({
       playerState: playerStatePDA,
-      gachaState: gachaStatePDA,
+      gachaState: client.gachaStatePDA,
       user: userPublicKey,
+      caller: userPublicKey,
       randomnessAccountData: randomnessAccount,
     })
     .instruction();
@@ -23,25 +23,35 @@ async function viewGachaState(network: string) {
     console.log(`🎯 Total Pulls: ${Number(gachaState.pullCount)}`);
     console.log(`🎯 Total Settles: ${Number(gachaState.settleCount)}`);
     console.log(`🔒 Is Finalized: ${gachaState.isFinalized}`);
-    console.log(`🗝️  Total Encrypted Keys: ${gachaState.encryptedKeys.length}`);
+    console.log(`🗝️  Total Encrypted Keys: ${gachaState.totalKeys}`);
+    console.log(`📚 Key Pages: ${gachaState.keyPageCount}`);
     console.log(`🏗️  Bump: ${gachaState.bump}`);
 
-    if (gachaState.encryptedKeys.length > 0) {
-      console.log(
-        `\n📝 All Encrypted Keys (${gachaState.encryptedKeys.length} total):`
-      );
-      for (let i = 0; i < gachaState.encryptedKeys.length; i++) {
-        const key = gachaState.encryptedKeys[i];
+    const encryptedKeys = await client.getEncryptedKeys();
+    if (encryptedKeys.length > 0) {
+      console.log(`\n📝 All Encrypted Keys (${encryptedKeys.length} total):`);
+      for (let i = 0; i < encryptedKeys.length; i++) {
+        const key = encryptedKeys[i];
         console.log(`   ${i + 1}. ${key}`);
       }
     }
 
-    if (gachaState.remainingIndices && gachaState.remainingIndices.length > 0) {
+    const totalWeight = gachaState.tiers.reduce(
+      (sum, tier) => sum + (tier.remainingIndices.length > 0 ? tier.weight : 0),
+      0
+    );
+    gachaState.tiers.forEach((tier, tierIndex) => {
+      const odds =
+        totalWeight > 0 && tier.remainingIndices.length > 0
+          ? ((tier.weight / totalWeight) * 100).toFixed(2)
+          : "0.00";
       console.log(
-        `\n🔢 All Remaining Indices (${gachaState.remainingIndices.length} total):`
+        `\n🎲 Tier ${tierIndex}: weight ${tier.weight} (${odds}%), ${tier.remainingIndices.length}/${tier.keyCount} keys remaining`
       );
-      console.log(`   [${gachaState.remainingIndices.join(", ")}]`);
-    }
+      if (tier.remainingIndices.length > 0) {
+        console.log(`   [${tier.remainingIndices.join(", ")}]`);
+      }
+    });
 
     return gachaState;
   } catch (error) {
@@ -69,13 +79,9 @@ async function viewPlayerState(
   try {
     if (nonce !== undefined) {
       // View specific player state by nonce
-      const [playerStatePDA] = PublicKey.findProgramAddressSync(
-        [
-          Buffer.from("player_state"),
-          user.toBuffer(),
-          new anchor.BN(nonce).toBuffer("le", 8),
-        ],
-        client.program.programId
+      const playerStatePDA = client.findPlayerStatePDA(
+        user,
+        new anchor.BN(nonce)
       );
 
       console.log(`🔍 Looking for player state with nonce: ${nonce}`);
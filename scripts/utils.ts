@@ -17,6 +17,7 @@ import {
   getMint,
 } from "@solana/spl-token";
 import bs58 from "bs58";
+import { createHash } from "crypto";
 
 // IMPORT CONFIGURATION
 import gachaIDL from "../target/idl/gacha_machine.json";
@@ -77,6 +78,57 @@ export function decryptWithKey(ciphertext: string, key: string): string {
   return originalText;
 }
 
+const sha256 = (...parts: Buffer[]) =>
+  createHash("sha256").update(Buffer.concat(parts)).digest();
+
+/**
+ * Computes the Merkle leaf of an encrypted key (mirrors `key_leaf_hash` in the program).
+ */
+export function keyLeafHash(index: number, encryptedKey: string): Buffer {
+  const indexBuffer = Buffer.alloc(2);
+  indexBuffer.writeUInt16LE(index);
+  return sha256(Buffer.from([0]), indexBuffer, Buffer.from(encryptedKey));
+}
+
+/**
+ * Builds the Merkle tree over a list of encrypted keys.
+ * @returns The root and a function returning the proof of a key index.
+ */
+export function buildKeyMerkleTree(encryptedKeys: string[]): {
+  root: Buffer;
+  getProof: (index: number) => Buffer[];
+} {
+  const levels: Buffer[][] = [encryptedKeys.map((key, i) => keyLeafHash(i, key))];
+  while (levels[levels.length - 1].length > 1) {
+    const level = levels[levels.length - 1];
+    const next: Buffer[] = [];
+    for (let i = 0; i < level.length; i += 2) {
+      if (i + 1 === level.length) {
+        // Odd node is promoted unchanged
+        next.push(level[i]);
+        continue;
+      }
+      const [a, b] = [level[i], level[i + 1]].sort(Buffer.compare);
+      next.push(sha256(Buffer.from([1]), a, b));
+    }
+    levels.push(next);
+  }
+
+  const getProof = (index: number) => {
+    const proof: Buffer[] = [];
+    for (let level = 0; level < levels.length - 1; level++) {
+      const sibling = index ^ 1;
+      if (sibling < levels[level].length) {
+        proof.push(levels[level][sibling]);
+      }
+      index = Math.floor(index / 2);
+    }
+    return proof;
+  };
+
+  return { root: levels[levels.length - 1][0], getProof };
+}
+
 /**
  * Converts a private key string (base58 or hex) to a Keypair object.
  * @param privateKeyString The private key as a string (base58 encoded or hex)
@@ -117,6 +169,8 @@ export const getConfigurationFromNetwork = (network: string) => {
   const programId = sharedConfig[network].programId;
   const encryptionKey = sharedConfig[network].encryptionKey;
   const rpcUrl = sharedConfig[network].rpcUrl;
+  const machineId = sharedConfig[network].machineId;
+  const machineCreator = sharedConfig[network].machineCreator;
   const SOL = sharedConfig[network].SOL;
   const usdt = sharedConfig[network].USDT;
   const usdc = sharedConfig[network].USDC;
@@ -135,6 +189,8 @@ export const getConfigurationFromNetwork = (network: string) => {
     programId,
     encryptionKey,
     rpcUrl,
+    machineId,
+    machineCreator,
     SOL,
     usdt,
     usdc,
@@ -171,7 +227,7 @@ export function createAnchorProgramAndClient(
   program: anchor.Program;
   client: GachaClient;
 } {
-  const { rpcUrl, programId, encryptionKey } =
+  const { rpcUrl, programId, machineId, machineCreator } =
     getConfigurationFromNetwork(network);
   const connection = new Connection(rpcUrl, "confirmed");
   const provider = anchorProviderFromWallet(connection, user);
@@ -180,9 +236,15 @@ export function createAnchorProgramAndClient(
   // Overwrite the address from the config
   gachaIDL.address = programId;
   const program = new anchor.Program(gachaIDL as GachaMachine, provider);
+  // The machine creator defaults to the connecting wallet (the admin when deploying)
+  const creator = machineCreator
+    ? new PublicKey(machineCreator)
+    : user.publicKey;
   const client = new GachaClient(
     program as unknown as anchor.Program<GachaMachine>,
-    provider
+    provider,
+    creator,
+    new anchor.BN(machineId ?? 0)
   );
 
   return { connection, program, client };
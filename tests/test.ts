@@ -1,21 +1,34 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { expect } from "chai";
+import { createHash } from "crypto";
 import {
   PublicKey,
   Keypair,
   SystemProgram,
   LAMPORTS_PER_SOL,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  SYSVAR_SLOT_HASHES_PUBKEY,
+  ComputeBudgetProgram,
   Transaction,
+  TransactionInstruction,
   sendAndConfirmTransaction,
 } from "@solana/web3.js";
 import {
   TOKEN_PROGRAM_ID,
+  TOKEN_2022_PROGRAM_ID,
+  ExtensionType,
   createMint,
   createAccount,
+  createAssociatedTokenAccountIdempotent,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  getAssociatedTokenAddressSync,
+  getMintLen,
   mintTo,
   getAccount,
 } from "@solana/spl-token";
+import { buildKeyMerkleTree } from "../scripts/utils";
 // import { RandomnessAccountData } from "@switchboard-xyz/on-demand";
 
 describe("gacha_machine", () => {
@@ -34,7 +47,9 @@ describe("gacha_machine", () => {
   let userPaymentAccount: PublicKey;
   let adminPaymentAccount: PublicKey;
   let paymentConfig: PublicKey;
+  let paymentVault: PublicKey;
   let randomnessAccount: Keypair;
+  let keyPage: PublicKey;
 
   // Test data
   const testKeys = [
@@ -45,6 +60,44 @@ describe("gacha_machine", () => {
     "encrypted_key_5_qrst7890",
   ];
   const paymentPrice = new anchor.BN(1000000); // 1 USDT (6 decimals)
+  const machineId = new anchor.BN(1);
+
+  // Creates a Token-2022 mint charging `feeBasisPoints` on every transfer
+  async function createTransferFeeMint(
+    feeBasisPoints: number,
+    maxFee: bigint
+  ): Promise<PublicKey> {
+    const mint = Keypair.generate();
+    const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    const tx = new Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: admin.publicKey,
+        newAccountPubkey: mint.publicKey,
+        lamports: await provider.connection.getMinimumBalanceForRentExemption(
+          mintLen
+        ),
+        space: mintLen,
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      createInitializeTransferFeeConfigInstruction(
+        mint.publicKey,
+        admin.publicKey,
+        admin.publicKey,
+        feeBasisPoints,
+        maxFee,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createInitializeMintInstruction(
+        mint.publicKey,
+        6,
+        admin.publicKey,
+        null,
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    await sendAndConfirmTransaction(provider.connection, tx, [admin, mint]);
+    return mint.publicKey;
+  }
 
   before(async () => {
     // Initialize test accounts
@@ -72,7 +125,17 @@ describe("gacha_machine", () => {
 
     // Derive PDA for gacha state
     [gachaState] = PublicKey.findProgramAddressSync(
-      [Buffer.from("gacha_state")],
+      [
+        Buffer.from("gacha_state"),
+        admin.publicKey.toBuffer(),
+        machineId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+
+    // Derive the first key page PDA
+    [keyPage] = PublicKey.findProgramAddressSync(
+      [Buffer.from("key_page"), gachaState.toBuffer(), Buffer.from([0, 0])],
       program.programId
     );
 
@@ -119,12 +182,15 @@ describe("gacha_machine", () => {
       ],
       program.programId
     );
+
+    // SPL payments are held in the payment config's associated token account
+    paymentVault = getAssociatedTokenAddressSync(paymentMint, paymentConfig, true);
   });
 
   describe("initialization", () => {
     it("should initialize gacha machine successfully", async () => {
       const tx = await program.methods
-        .initialize()
+        .initialize(machineId)
         .accountsPartial({
           gachaState,
           admin: admin.publicKey,
@@ -141,15 +207,50 @@ describe("gacha_machine", () => {
       expect(gachaStateAccount.admin.toString()).to.equal(
         admin.publicKey.toString()
       );
+      expect(gachaStateAccount.creator.toString()).to.equal(
+        admin.publicKey.toString()
+      );
+      expect(gachaStateAccount.machineId.toNumber()).to.equal(1);
       expect(gachaStateAccount.isFinalized).to.be.false;
       expect(gachaStateAccount.isPaused).to.be.false;
       expect(gachaStateAccount.pullCount.toNumber()).to.equal(0);
       expect(gachaStateAccount.settleCount.toNumber()).to.equal(0);
-      expect(gachaStateAccount.encryptedKeys).to.deep.equal([]);
-      expect(gachaStateAccount.remainingIndices).to.deep.equal([]);
+      expect(gachaStateAccount.totalKeys).to.equal(0);
+      expect(gachaStateAccount.keyPageCount).to.equal(0);
+      expect(gachaStateAccount.tiers).to.have.length(1);
+      expect(gachaStateAccount.tiers[0].weight).to.equal(1);
+      expect(gachaStateAccount.tiers[0].remainingIndices).to.deep.equal([]);
       expect(gachaStateAccount.paymentConfigs).to.deep.equal([]);
     });
 
+    it("should initialize an independent machine with another id", async () => {
+      const otherMachineId = new anchor.BN(2);
+      const [otherGachaState] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("gacha_state"),
+          admin.publicKey.toBuffer(),
+          otherMachineId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .initialize(otherMachineId)
+        .accountsPartial({
+          gachaState: otherGachaState,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+      const otherAccount = await program.account.gachaState.fetch(
+        otherGachaState
+      );
+      expect(otherAccount.machineId.toNumber()).to.equal(2);
+      expect(otherGachaState.toString()).to.not.equal(gachaState.toString());
+    });
+
     it("should emit GachaInitialized event", async () => {
       // This test would need event listening setup
       // For now, we'll just verify the transaction succeeded
@@ -164,7 +265,9 @@ describe("gacha_machine", () => {
         .accountsPartial({
           paymentConfig,
           gachaState,
-          admin: admin.publicKey,
+          authority: admin.publicKey,
+          paymentMintAccount: paymentMint,
+          recipientAccount: adminPaymentAccount,
           systemProgram: SystemProgram.programId,
         })
         .signers([admin])
@@ -219,7 +322,9 @@ describe("gacha_machine", () => {
           .accountsPartial({
             paymentConfig: differentPaymentConfig,
             gachaState,
-            admin: user.publicKey,
+            authority: user.publicKey,
+            paymentMintAccount: differentMint,
+            recipientAccount: adminPaymentAccount,
             systemProgram: SystemProgram.programId,
           })
           .signers([user])
@@ -227,379 +332,2991 @@ describe("gacha_machine", () => {
 
         expect.fail("Should have failed with non-admin signer");
       } catch (error) {
-        expect(error.toString()).to.include("ConstraintHasOne");
+        expect(error.toString()).to.include("Unauthorized");
       }
     });
-  });
 
-  describe("key management", () => {
-    it("should add keys successfully", async () => {
-      for (let i = 0; i < testKeys.length; i++) {
+    it("should fail to add a duplicate payment config", async () => {
+      try {
         await program.methods
-          .addKey(testKeys[i])
+          .addPaymentConfig(paymentMint, paymentPrice, adminPaymentAccount)
           .accountsPartial({
+            paymentConfig,
             gachaState,
-            admin: admin.publicKey,
+            authority: admin.publicKey,
+            paymentMintAccount: paymentMint,
+            recipientAccount: adminPaymentAccount,
             systemProgram: SystemProgram.programId,
           })
           .signers([admin])
           .rpc();
+
+        expect.fail("Should have failed with a duplicate payment config");
+      } catch (error) {
+        expect(error.toString()).to.include("PaymentConfigAlreadyExists");
       }
+    });
 
-      // Verify keys were added
-      const gachaStateAccount = await program.account.gachaState.fetch(
-        gachaState
+    it("should fail to add payment config with a recipient of another mint", async () => {
+      const differentMint = await createMint(
+        provider.connection,
+        admin,
+        admin.publicKey,
+        admin.publicKey,
+        6
+      );
+
+      const [differentPaymentConfig] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("payment_config"),
+          gachaState.toBuffer(),
+          differentMint.toBuffer(),
+        ],
+        program.programId
       );
-      expect(gachaStateAccount.encryptedKeys).to.have.length(testKeys.length);
-      expect(gachaStateAccount.encryptedKeys).to.deep.equal(testKeys);
-    });
 
-    it("should fail to add empty key", async () => {
       try {
         await program.methods
-          .addKey("")
+          .addPaymentConfig(differentMint, paymentPrice, adminPaymentAccount)
           .accountsPartial({
+            paymentConfig: differentPaymentConfig,
             gachaState,
-            admin: admin.publicKey,
+            authority: admin.publicKey,
+            paymentMintAccount: differentMint,
+            recipientAccount: adminPaymentAccount,
             systemProgram: SystemProgram.programId,
           })
           .signers([admin])
           .rpc();
 
-        expect.fail("Should have failed with empty key");
+        expect.fail("Should have failed with a recipient of another mint");
       } catch (error) {
-        expect(error.toString()).to.include("EmptyKeyProvided");
+        expect(error.toString()).to.include("MintMismatch");
       }
     });
 
-    it("should fail to add key with non-admin signer", async () => {
+    it("should update price and recipient in place", async () => {
+      await program.methods
+        .updatePaymentConfig(paymentMint, paymentPrice.muln(2))
+        .accountsPartial({
+          paymentConfig,
+          gachaState,
+          authority: admin.publicKey,
+          newRecipientAccount: userPaymentAccount,
+        })
+        .signers([admin])
+        .rpc();
+
+      let paymentConfigAccount = await program.account.paymentConfig.fetch(
+        paymentConfig
+      );
+      expect(paymentConfigAccount.price.toString()).to.equal(
+        paymentPrice.muln(2).toString()
+      );
+      expect(paymentConfigAccount.adminRecipientAccount.toString()).to.equal(
+        userPaymentAccount.toString()
+      );
+
+      // Restore the original config for other tests
+      await program.methods
+        .updatePaymentConfig(paymentMint, paymentPrice)
+        .accountsPartial({
+          paymentConfig,
+          gachaState,
+          authority: admin.publicKey,
+          newRecipientAccount: adminPaymentAccount,
+        })
+        .signers([admin])
+        .rpc();
+
+      paymentConfigAccount = await program.account.paymentConfig.fetch(
+        paymentConfig
+      );
+      expect(paymentConfigAccount.price.toString()).to.equal(
+        paymentPrice.toString()
+      );
+      expect(paymentConfigAccount.adminRecipientAccount.toString()).to.equal(
+        adminPaymentAccount.toString()
+      );
+    });
+
+    it("should let a pricing manager change the price but not the recipient", async () => {
+      await program.methods
+        .grantRole(user.publicKey, { pricingManager: {} })
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      await program.methods
+        .updatePaymentConfig(paymentMint, paymentPrice.muln(3))
+        .accountsPartial({
+          paymentConfig,
+          gachaState,
+          authority: user.publicKey,
+          newRecipientAccount: null,
+        })
+        .signers([user])
+        .rpc();
+
       try {
         await program.methods
-          .addKey("test_key")
+          .updatePaymentConfig(paymentMint, null)
           .accountsPartial({
+            paymentConfig,
             gachaState,
-            admin: user.publicKey,
-            systemProgram: SystemProgram.programId,
+            authority: user.publicKey,
+            newRecipientAccount: userPaymentAccount,
           })
           .signers([user])
           .rpc();
 
-        expect.fail("Should have failed with non-admin signer");
+        expect.fail("Should have failed to redirect revenue as pricing manager");
       } catch (error) {
-        expect(error.toString()).to.include("ConstraintHasOne");
+        expect(error.toString()).to.include("Unauthorized");
       }
-    });
-  });
 
-  describe("finalization", () => {
-    it("should finalize gacha machine successfully", async () => {
+      // A treasurer can change the recipient
       await program.methods
-        .finalize()
+        .revokeRole(user.publicKey, { pricingManager: {} })
         .accountsPartial({
           gachaState,
           admin: admin.publicKey,
-          systemProgram: SystemProgram.programId,
         })
         .signers([admin])
         .rpc();
+      await program.methods
+        .grantRole(user.publicKey, { treasurer: {} })
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      await program.methods
+        .updatePaymentConfig(paymentMint, null)
+        .accountsPartial({
+          paymentConfig,
+          gachaState,
+          authority: user.publicKey,
+          newRecipientAccount: adminPaymentAccount,
+        })
+        .signers([user])
+        .rpc();
 
-      // Verify finalization
-      const gachaStateAccount = await program.account.gachaState.fetch(
-        gachaState
+      // Restore the original price and roles for other tests
+      await program.methods
+        .revokeRole(user.publicKey, { treasurer: {} })
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      await program.methods
+        .updatePaymentConfig(paymentMint, paymentPrice)
+        .accountsPartial({
+          paymentConfig,
+          gachaState,
+          authority: admin.publicKey,
+          newRecipientAccount: null,
+        })
+        .signers([admin])
+        .rpc();
+
+      const paymentConfigAccount = await program.account.paymentConfig.fetch(
+        paymentConfig
       );
-      expect(gachaStateAccount.isFinalized).to.be.true;
-      expect(gachaStateAccount.remainingIndices).to.have.length(
-        testKeys.length
+      expect(paymentConfigAccount.price.toString()).to.equal(
+        paymentPrice.toString()
       );
-
-      // Verify indices are correct
-      const expectedIndices = Array.from(
-        { length: testKeys.length },
-        (_, i) => i
+      expect(paymentConfigAccount.adminRecipientAccount.toString()).to.equal(
+        adminPaymentAccount.toString()
       );
-      expect(gachaStateAccount.remainingIndices).to.deep.equal(expectedIndices);
     });
 
-    it("should fail to finalize already finalized gacha", async () => {
+    it("should fail to update recipient to an account of another mint", async () => {
       try {
         await program.methods
-          .finalize()
+          .updatePaymentConfig(paymentMint, null)
           .accountsPartial({
+            paymentConfig,
             gachaState,
-            admin: admin.publicKey,
-            systemProgram: SystemProgram.programId,
+            authority: admin.publicKey,
+            newRecipientAccount: admin.publicKey,
           })
           .signers([admin])
           .rpc();
 
-        expect.fail("Should have failed when already finalized");
+        expect.fail("Should have failed with a non-token recipient");
       } catch (error) {
-        expect(error.toString()).to.include("GachaAlreadyFinalized");
+        expect(error.toString()).to.include("IncorrectOwner");
       }
     });
 
-    it("should fail to add key after finalization", async () => {
+    it("should fail to set USD pricing with a non-Switchboard feed", async () => {
       try {
         await program.methods
-          .addKey("post_finalization_key")
+          .setUsdPricing(paymentMint, {
+            usdPrice: new anchor.BN(100_000_000),
+            priceFeed: adminPaymentAccount,
+            maxStalenessSlots: new anchor.BN(150),
+            maxDeviationBps: 100,
+          })
           .accountsPartial({
+            paymentConfig,
             gachaState,
-            admin: admin.publicKey,
-            systemProgram: SystemProgram.programId,
+            authority: admin.publicKey,
+            priceFeed: adminPaymentAccount,
           })
           .signers([admin])
           .rpc();
 
-        expect.fail("Should have failed to add key after finalization");
+        expect.fail("Should have failed with an invalid price feed");
       } catch (error) {
-        expect(error.toString()).to.include("GachaAlreadyFinalized");
+        expect(error.toString()).to.include("InvalidPriceFeed");
       }
     });
   });
 
-  describe("admin controls", () => {
-    it("should pause and unpause gacha machine", async () => {
-      // Pause
-      await program.methods
-        .setPaused(true)
-        .accountsPartial({
-          gachaState,
-          admin: admin.publicKey,
-        })
-        .signers([admin])
-        .rpc();
-
-      let gachaStateAccount = await program.account.gachaState.fetch(
-        gachaState
-      );
-      expect(gachaStateAccount.isPaused).to.be.true;
-
-      // Unpause
+  describe("key management", () => {
+    it("should add a key page successfully", async () => {
       await program.methods
-        .setPaused(false)
+        .addKeyPage()
         .accountsPartial({
+          keyPage,
           gachaState,
-          admin: admin.publicKey,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
         })
         .signers([admin])
         .rpc();
 
-      gachaStateAccount = await program.account.gachaState.fetch(gachaState);
-      expect(gachaStateAccount.isPaused).to.be.false;
+      const keyPageAccount = await program.account.keyPage.fetch(keyPage);
+      expect(keyPageAccount.pageIndex).to.equal(0);
+      expect(keyPageAccount.keys).to.deep.equal([]);
     });
 
-    it("should transfer admin successfully", async () => {
-      await program.methods
-        .transferAdmin(newAdmin.publicKey)
-        .accountsPartial({
-          gachaState,
-          admin: admin.publicKey,
-        })
-        .signers([admin])
-        .rpc();
+    it("should add keys successfully", async () => {
+      for (let i = 0; i < testKeys.length; i++) {
+        await program.methods
+          .addKey(testKeys[i], 0)
+          .accountsPartial({
+            keyPage,
+            gachaState,
+            authority: admin.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([admin])
+          .rpc();
+      }
 
+      // Verify keys were added
       const gachaStateAccount = await program.account.gachaState.fetch(
         gachaState
       );
-      expect(gachaStateAccount.admin.toString()).to.equal(
-        newAdmin.publicKey.toString()
-      );
-
-      // Transfer back for other tests
-      await program.methods
-        .transferAdmin(admin.publicKey)
-        .accountsPartial({
-          gachaState,
-          admin: newAdmin.publicKey,
-        })
-        .signers([newAdmin])
-        .rpc();
+      expect(gachaStateAccount.totalKeys).to.equal(testKeys.length);
+      const keyPageAccount = await program.account.keyPage.fetch(keyPage);
+      expect(keyPageAccount.keys).to.deep.equal(testKeys);
     });
 
-    it("should fail admin actions with non-admin signer", async () => {
+    it("should add a batch of keys atomically", async () => {
+      const batch = ["batch_key_1", "", "batch_key_3"];
       try {
         await program.methods
-          .setPaused(true)
+          .addKeys(batch, 0)
           .accountsPartial({
+            keyPage,
             gachaState,
-            admin: user.publicKey,
+            authority: admin.publicKey,
+            systemProgram: SystemProgram.programId,
           })
-          .signers([user])
+          .signers([admin])
           .rpc();
-
-        expect.fail("Should have failed with non-admin signer");
+        expect.fail("Should have failed with an empty key in the batch");
       } catch (error) {
-        expect(error.toString()).to.include("ConstraintHasOne");
+        expect(error.toString()).to.include("EmptyKeyProvided");
       }
+
+      // Nothing from the rejected batch was stored
+      const gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
+      );
+      expect(gachaStateAccount.totalKeys).to.equal(testKeys.length);
     });
-  });
 
-  describe("pull mechanism", () => {
-    let playerState: PublicKey;
-    let mockRandomnessData: Buffer;
+    it("should fail to add empty key", async () => {
+      try {
+        await program.methods
+          .addKey("", 0)
+          .accountsPartial({
+            keyPage,
+            gachaState,
+            authority: admin.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([admin])
+          .rpc();
 
-    before(async () => {
-      // Create mock randomness account data
-      mockRandomnessData = Buffer.alloc(1000);
+        expect.fail("Should have failed with empty key");
+      } catch (error) {
+        expect(error.toString()).to.include("EmptyKeyProvided");
+      }
+    });
 
-      // Setup randomness account with mock data
-      const createRandomnessAccountIx = SystemProgram.createAccount({
-        fromPubkey: admin.publicKey,
-        newAccountPubkey: randomnessAccount.publicKey,
-        lamports: await provider.connection.getMinimumBalanceForRentExemption(
-          mockRandomnessData.length
-        ),
-        space: mockRandomnessData.length,
-        programId: new PublicKey("SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv"), // Switchboard program ID
-      });
+    it("should fail to add key to an unconfigured tier", async () => {
+      try {
+        await program.methods
+          .addKey("tiered_key", 1)
+          .accountsPartial({
+            keyPage,
+            gachaState,
+            authority: admin.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([admin])
+          .rpc();
 
-      const tx = new Transaction().add(createRandomnessAccountIx);
-      await sendAndConfirmTransaction(provider.connection, tx, [
-        admin,
-        randomnessAccount,
-      ]);
+        expect.fail("Should have failed with an unconfigured tier");
+      } catch (error) {
+        expect(error.toString()).to.include("InvalidTier");
+      }
     });
 
-    beforeEach(async () => {
-      // Get current pull count for PDA derivation
-      const gachaStateAccount = await program.account.gachaState.fetch(
-        gachaState
-      );
+    it("should fail to add key with non-admin signer", async () => {
+      try {
+        await program.methods
+          .addKey("test_key", 0)
+          .accountsPartial({
+            keyPage,
+            gachaState,
+            authority: user.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([user])
+          .rpc();
 
-      [playerState] = PublicKey.findProgramAddressSync(
-        [
-          Buffer.from("player_state"),
-          user.publicKey.toBuffer(),
-          gachaStateAccount.pullCount.toArrayLike(Buffer, "le", 8),
-        ],
-        program.programId
-      );
+        expect.fail("Should have failed with non-admin signer");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
     });
+  });
 
-    it("should fail pull when paused", async () => {
-      // Pause the gacha
+  describe("finalization", () => {
+    it("should finalize gacha machine successfully", async () => {
       await program.methods
-        .setPaused(true)
+        .finalize(null)
         .accountsPartial({
           gachaState,
-          admin: admin.publicKey,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
         })
         .signers([admin])
         .rpc();
 
+      // Verify finalization
+      const gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
+      );
+      expect(gachaStateAccount.isFinalized).to.be.true;
+      expect(gachaStateAccount.tiers[0].remainingIndices).to.have.length(
+        testKeys.length
+      );
+
+      // Verify indices are correct
+      const expectedIndices = Array.from(
+        { length: testKeys.length },
+        (_, i) => i
+      );
+      expect(gachaStateAccount.tiers[0].remainingIndices).to.deep.equal(
+        expectedIndices
+      );
+    });
+
+    it("should fail to finalize already finalized gacha", async () => {
       try {
         await program.methods
-          .pull()
+          .finalize(null)
           .accountsPartial({
-            playerState,
             gachaState,
-            paymentConfig,
-            user: user.publicKey,
-            paymentMint,
-            userPaymentAccount,
-            adminRecipientAccount: adminPaymentAccount,
-            randomnessAccountData: randomnessAccount.publicKey,
+            authority: admin.publicKey,
             systemProgram: SystemProgram.programId,
-            tokenProgram: TOKEN_PROGRAM_ID,
           })
-          .signers([user])
+          .signers([admin])
           .rpc();
 
-        expect.fail("Should have failed when paused");
+        expect.fail("Should have failed when already finalized");
       } catch (error) {
-        expect(error.toString()).to.include("GachaPaused");
+        expect(error.toString()).to.include("GachaAlreadyFinalized");
       }
+    });
 
-      // Unpause for other tests
+    it("should fail to add key after finalization", async () => {
+      try {
+        await program.methods
+          .addKey("post_finalization_key", 0)
+          .accountsPartial({
+            keyPage,
+            gachaState,
+            authority: admin.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([admin])
+          .rpc();
+
+        expect.fail("Should have failed to add key after finalization");
+      } catch (error) {
+        expect(error.toString()).to.include("GachaAlreadyFinalized");
+      }
+    });
+
+    it("should fail to change the randomness source after finalization", async () => {
+      try {
+        await program.methods
+          .setRandomnessSource({ switchboard: {} })
+          .accountsPartial({
+            gachaState,
+            admin: admin.publicKey,
+          })
+          .signers([admin])
+          .rpc();
+
+        expect.fail("Should have failed to change the source after finalization");
+      } catch (error) {
+        expect(error.toString()).to.include("GachaAlreadyFinalized");
+      }
+    });
+  });
+
+  describe("admin controls", () => {
+    it("should pause and unpause gacha machine", async () => {
+      // Pause
+      await program.methods
+        .setPaused(true)
+        .accountsPartial({
+          gachaState,
+          authority: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      let gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
+      );
+      expect(gachaStateAccount.isPaused).to.be.true;
+
+      // Unpause
       await program.methods
         .setPaused(false)
         .accountsPartial({
           gachaState,
-          admin: admin.publicKey,
+          authority: admin.publicKey,
         })
         .signers([admin])
         .rpc();
+
+      gachaStateAccount = await program.account.gachaState.fetch(gachaState);
+      expect(gachaStateAccount.isPaused).to.be.false;
     });
 
-    it("should fail pull with invalid payment config", async () => {
-      // Create a fake payment config PDA that doesn't exist
-      const [fakePaymentConfig] = PublicKey.findProgramAddressSync(
-        [
-          Buffer.from("payment_config"),
-          gachaState.toBuffer(),
-          Keypair.generate().publicKey.toBuffer(), // Random mint
-        ],
-        program.programId
+    it("should set the crank tip and reject a tip of the whole price", async () => {
+      await program.methods
+        .setCrankTip(50)
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      const gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
       );
+      expect(gachaStateAccount.crankTipBps).to.equal(50);
 
       try {
         await program.methods
-          .pull()
+          .setCrankTip(10_000)
           .accountsPartial({
-            playerState,
             gachaState,
-            paymentConfig: fakePaymentConfig,
-            user: user.publicKey,
-            paymentMint,
-            userPaymentAccount,
-            adminRecipientAccount: adminPaymentAccount,
-            randomnessAccountData: randomnessAccount.publicKey,
-            systemProgram: SystemProgram.programId,
-            tokenProgram: TOKEN_PROGRAM_ID,
+            admin: admin.publicKey,
           })
-          .signers([user])
+          .signers([admin])
           .rpc();
 
-        expect.fail("Should have failed with invalid payment config");
+        expect.fail("Should have failed with a crank tip of 10000 bps");
       } catch (error) {
-        // This will fail at account deserialization level since the account doesn't exist
-        expect(error).to.exist;
+        expect(error.toString()).to.include("InvalidCrankTip");
       }
     });
 
-    // Note: Full pull testing would require proper Switchboard randomness setup
-    // which is complex for unit tests. In practice, you'd use a mock or localnet setup.
-  });
-
-  describe("settlement", () => {
-    it("should fail to settle non-existent player state", async () => {
-      const [nonExistentPlayerState] = PublicKey.findProgramAddressSync(
-        [
-          Buffer.from("player_state"),
-          Keypair.generate().publicKey.toBuffer(),
-          Buffer.from([0, 0, 0, 0, 0, 0, 0, 0]), // nonce 0
-        ],
-        program.programId
-      );
-
+    it("should reject an instant mode prize value above the maximum", async () => {
       try {
         await program.methods
-          .settle()
+          .setInstantMode(new anchor.BN(10_000_001)) // $10.000001
           .accountsPartial({
-            playerState: nonExistentPlayerState,
             gachaState,
-            user: user.publicKey,
-            randomnessAccountData: randomnessAccount.publicKey,
+            admin: admin.publicKey,
           })
-          .signers([user])
+          .signers([admin])
           .rpc();
 
-        expect.fail("Should have failed with non-existent player state");
+        expect.fail("Should have failed with a prize value above the maximum");
       } catch (error) {
-        // Will fail at account deserialization
-        expect(error).to.exist;
+        expect(error.toString()).to.include("InvalidInstantPrizeValue");
       }
     });
+
+    it("should transfer admin in two steps", async () => {
+      await program.methods
+        .proposeAdmin(newAdmin.publicKey)
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      // The proposal alone does not change the admin
+      let gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
+      );
+      expect(gachaStateAccount.admin.toString()).to.equal(
+        admin.publicKey.toString()
+      );
+      expect(gachaStateAccount.pendingAdmin.toString()).to.equal(
+        newAdmin.publicKey.toString()
+      );
+
+      await program.methods
+        .acceptAdmin()
+        .accountsPartial({
+          gachaState,
+          newAdmin: newAdmin.publicKey,
+        })
+        .signers([newAdmin])
+        .rpc();
+
+      gachaStateAccount = await program.account.gachaState.fetch(gachaState);
+      expect(gachaStateAccount.admin.toString()).to.equal(
+        newAdmin.publicKey.toString()
+      );
+      expect(gachaStateAccount.pendingAdmin).to.be.null;
+
+      // Transfer back for other tests
+      await program.methods
+        .proposeAdmin(admin.publicKey)
+        .accountsPartial({
+          gachaState,
+          admin: newAdmin.publicKey,
+        })
+        .signers([newAdmin])
+        .rpc();
+      await program.methods
+        .acceptAdmin()
+        .accountsPartial({
+          gachaState,
+          newAdmin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("should fail to accept admin without being the pending admin", async () => {
+      await program.methods
+        .proposeAdmin(newAdmin.publicKey)
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptAdmin()
+          .accountsPartial({
+            gachaState,
+            newAdmin: user.publicKey,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with a non-pending admin");
+      } catch (error) {
+        expect(error.toString()).to.include("NotPendingAdmin");
+      }
+
+      await program.methods
+        .cancelAdminTransfer()
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      const gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
+      );
+      expect(gachaStateAccount.pendingAdmin).to.be.null;
+    });
+
+    it("should fail admin actions with non-admin signer", async () => {
+      try {
+        await program.methods
+          .setPaused(true)
+          .accountsPartial({
+            gachaState,
+            authority: user.publicKey,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with non-admin signer");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("should let a granted pauser pause but not release the key", async () => {
+      await program.methods
+        .grantRole(user.publicKey, { pauser: {} })
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      await program.methods
+        .setPaused(true)
+        .accountsPartial({
+          gachaState,
+          authority: user.publicKey,
+        })
+        .signers([user])
+        .rpc();
+      let gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
+      );
+      expect(gachaStateAccount.isPaused).to.be.true;
+
+      try {
+        await program.methods
+          .releaseDecryptionKey("not_a_key")
+          .accountsPartial({
+            gachaState,
+            admin: user.publicKey,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with a pauser signer");
+      } catch (error) {
+        expect(error.toString()).to.include("ConstraintHasOne");
+      }
+
+      // Revoke the role and unpause for other tests
+      await program.methods
+        .revokeRole(user.publicKey, { pauser: {} })
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      await program.methods
+        .setPaused(false)
+        .accountsPartial({
+          gachaState,
+          authority: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      gachaStateAccount = await program.account.gachaState.fetch(gachaState);
+      expect(gachaStateAccount.roles).to.deep.equal([]);
+      expect(gachaStateAccount.isPaused).to.be.false;
+    });
+
+    it("should reject duplicate grants and give back the space on revoke", async () => {
+      const sizeBefore = (await provider.connection.getAccountInfo(gachaState))
+        .data.length;
+
+      await program.methods
+        .grantRole(newAdmin.publicKey, { keyManager: {} })
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      const sizeGranted = (await provider.connection.getAccountInfo(gachaState))
+        .data.length;
+      expect(sizeGranted).to.equal(sizeBefore + 33); // RoleGrant::INIT_SPACE
+
+      try {
+        await program.methods
+          .grantRole(newAdmin.publicKey, { keyManager: {} })
+          .accountsPartial({
+            gachaState,
+            admin: admin.publicKey,
+          })
+          .signers([admin])
+          .rpc();
+
+        expect.fail("Should have failed with a duplicate grant");
+      } catch (error) {
+        expect(error.toString()).to.include("RoleAlreadyGranted");
+      }
+      expect(
+        (await provider.connection.getAccountInfo(gachaState)).data.length
+      ).to.equal(sizeGranted);
+
+      await program.methods
+        .revokeRole(newAdmin.publicKey, { keyManager: {} })
+        .accountsPartial({
+          gachaState,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      expect(
+        (await provider.connection.getAccountInfo(gachaState)).data.length
+      ).to.equal(sizeBefore);
+
+      try {
+        await program.methods
+          .revokeRole(newAdmin.publicKey, { keyManager: {} })
+          .accountsPartial({
+            gachaState,
+            admin: admin.publicKey,
+          })
+          .signers([admin])
+          .rpc();
+
+        expect.fail("Should have failed without a grant to revoke");
+      } catch (error) {
+        expect(error.toString()).to.include("RoleNotGranted");
+      }
+    });
+  });
+
+  describe("pull mechanism", () => {
+    let playerState: PublicKey;
+    let mockRandomnessData: Buffer;
+
+    before(async () => {
+      // Create mock randomness account data
+      mockRandomnessData = Buffer.alloc(1000);
+
+      // Setup randomness account with mock data
+      const createRandomnessAccountIx = SystemProgram.createAccount({
+        fromPubkey: admin.publicKey,
+        newAccountPubkey: randomnessAccount.publicKey,
+        lamports: await provider.connection.getMinimumBalanceForRentExemption(
+          mockRandomnessData.length
+        ),
+        space: mockRandomnessData.length,
+        programId: new PublicKey("SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv"), // Switchboard program ID
+      });
+
+      const tx = new Transaction().add(createRandomnessAccountIx);
+      await sendAndConfirmTransaction(provider.connection, tx, [
+        admin,
+        randomnessAccount,
+      ]);
+    });
+
+    beforeEach(async () => {
+      // Get current pull count for PDA derivation
+      const gachaStateAccount = await program.account.gachaState.fetch(
+        gachaState
+      );
+
+      [playerState] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("player_state"),
+          gachaState.toBuffer(),
+          user.publicKey.toBuffer(),
+          gachaStateAccount.pullCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+    });
+
+    it("should fail pull when paused", async () => {
+      // Pause the gacha
+      await program.methods
+        .setPaused(true)
+        .accountsPartial({
+          gachaState,
+          authority: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+      try {
+        await program.methods
+          .pull(paymentPrice, Array(32).fill(0))
+          .accountsPartial({
+            playerState,
+            gachaState,
+            paymentConfig,
+            user: user.publicKey,
+            paymentMint,
+            userPaymentAccount,
+            paymentVault,
+            randomnessAccountData: randomnessAccount.publicKey,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed when paused");
+      } catch (error) {
+        expect(error.toString()).to.include("GachaPaused");
+      }
+
+      // Unpause for other tests
+      await program.methods
+        .setPaused(false)
+        .accountsPartial({
+          gachaState,
+          authority: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("should fail pull with invalid payment config", async () => {
+      // Create a fake payment config PDA that doesn't exist
+      const [fakePaymentConfig] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("payment_config"),
+          gachaState.toBuffer(),
+          Keypair.generate().publicKey.toBuffer(), // Random mint
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .pull(paymentPrice, Array(32).fill(0))
+          .accountsPartial({
+            playerState,
+            gachaState,
+            paymentConfig: fakePaymentConfig,
+            user: user.publicKey,
+            paymentMint,
+            userPaymentAccount,
+            paymentVault,
+            randomnessAccountData: randomnessAccount.publicKey,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with invalid payment config");
+      } catch (error) {
+        // This will fail at account deserialization level since the account doesn't exist
+        expect(error).to.exist;
+      }
+    });
+
+    it("should fail instant pull when instant mode is disabled", async () => {
+      try {
+        await program.methods
+          .pullInstant(paymentPrice)
+          .accountsPartial({
+            playerState,
+            gachaState,
+            paymentConfig,
+            user: user.publicKey,
+            paymentMint,
+            userPaymentAccount,
+            paymentVault,
+            slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+            instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with instant mode disabled");
+      } catch (error) {
+        expect(error.toString()).to.include("InstantModeDisabled");
+      }
+    });
+
+    it("should fail pull when the price exceeds max_price", async () => {
+      try {
+        await program.methods
+          .pull(paymentPrice.subn(1), Array(32).fill(0))
+          .accountsPartial({
+            playerState,
+            gachaState,
+            paymentConfig,
+            user: user.publicKey,
+            paymentMint,
+            userPaymentAccount,
+            paymentVault,
+            randomnessAccountData: randomnessAccount.publicKey,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with the price above max_price");
+      } catch (error) {
+        expect(error.toString()).to.include("PriceExceedsMax");
+      }
+    });
+
+    it("should compare max_price with the price including transfer fees", async () => {
+      // 1% transfer fee: paying the net price costs the user 1% more
+      const feeMint = await createTransferFeeMint(100, BigInt(1_000_000_000));
+      const adminFeeAccount = await createAccount(
+        provider.connection,
+        admin,
+        feeMint,
+        admin.publicKey,
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const userFeeAccount = await createAccount(
+        provider.connection,
+        user,
+        feeMint,
+        user.publicKey,
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        provider.connection,
+        admin,
+        feeMint,
+        userFeeAccount,
+        admin.publicKey,
+        10_000_000,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const [feePaymentConfig] = PublicKey.findProgramAddressSync(
+        [Buffer.from("payment_config"), gachaState.toBuffer(), feeMint.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .addPaymentConfig(feeMint, paymentPrice, adminFeeAccount)
+        .accountsPartial({
+          paymentConfig: feePaymentConfig,
+          gachaState,
+          authority: admin.publicKey,
+          paymentMintAccount: feeMint,
+          recipientAccount: adminFeeAccount,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+      try {
+        // The net price alone is not enough once the fee is added
+        await program.methods
+          .pull(paymentPrice, Array(32).fill(0))
+          .accountsPartial({
+            playerState,
+            gachaState,
+            paymentConfig: feePaymentConfig,
+            user: user.publicKey,
+            paymentMint: feeMint,
+            userPaymentAccount: userFeeAccount,
+            paymentVault: getAssociatedTokenAddressSync(
+              feeMint,
+              feePaymentConfig,
+              true,
+              TOKEN_2022_PROGRAM_ID
+            ),
+            randomnessAccountData: randomnessAccount.publicKey,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with the fee pushing the price above max_price");
+      } catch (error) {
+        expect(error.toString()).to.include("PriceExceedsMax");
+      }
+
+      // Leave the machine with its original payment configs
+      await program.methods
+        .removePaymentConfig(feeMint)
+        .accountsPartial({
+          paymentConfig: feePaymentConfig,
+          gachaState,
+          authority: admin.publicKey,
+          paymentVault: getAssociatedTokenAddressSync(
+            feeMint,
+            feePaymentConfig,
+            true,
+            TOKEN_2022_PROGRAM_ID
+          ),
+          paymentMintAccount: feeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([admin])
+        .rpc();
+    });
+  });
+
+  describe("settlement", () => {
+    it("should fail to settle non-existent player state", async () => {
+      const [nonExistentPlayerState] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("player_state"),
+          gachaState.toBuffer(),
+          Keypair.generate().publicKey.toBuffer(),
+          Buffer.from([0, 0, 0, 0, 0, 0, 0, 0]), // nonce 0
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .settle()
+          .accountsPartial({
+            playerState: nonExistentPlayerState,
+            gachaState,
+            user: user.publicKey,
+            caller: user.publicKey,
+            randomnessAccountData: randomnessAccount.publicKey,
+          })
+          .signers([user])
+          .rpc();
+
+        expect.fail("Should have failed with non-existent player state");
+      } catch (error) {
+        // Will fail at account deserialization
+        expect(error).to.exist;
+      }
+    });
+  });
+
+  describe("end-to-end flows (deterministic randomness)", () => {
+    // These flows need the program built with `--features test-randomness` (as
+    // `yarn test` does): deterministic randomness lets them predict every draw.
+    const solMint = SystemProgram.programId;
+    const solPrice = new anchor.BN(LAMPORTS_PER_SOL / 100);
+    const tierKeys = [
+      Array.from({ length: 62 }, (_, i) => `e2e_common_${i}`),
+      Array.from({ length: 8 }, (_, i) => `e2e_rare_${i}`),
+    ];
+    const allKeys = ([] as string[]).concat(...tierKeys);
+
+    let machine: PublicKey;
+    let solConfig: PublicKey;
+    let cranker: Keypair;
+
+    const pda = (...seeds: Buffer[]) =>
+      PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+    const le8 = (value: anchor.BN) => value.toArrayLike(Buffer, "le", 8);
+    const keyPageOf = (gacha: PublicKey, pageIndex: number) =>
+      pda(
+        Buffer.from("key_page"),
+        gacha.toBuffer(),
+        new anchor.BN(pageIndex).toArrayLike(Buffer, "le", 2)
+      );
+    const userStatsOf = (gacha: PublicKey, owner: PublicKey) =>
+      pda(Buffer.from("user_stats"), gacha.toBuffer(), owner.toBuffer());
+    const sha256 = (...parts: Buffer[]) =>
+      createHash("sha256").update(Buffer.concat(parts)).digest();
+
+    // Settlement requires a slot after the pull
+    async function waitForNextSlot() {
+      const slot = await provider.connection.getSlot();
+      while ((await provider.connection.getSlot()) <= slot) {
+        await new Promise((resolve) => setTimeout(resolve, 100));
+      }
+    }
+
+    // Creates a SOL-priced machine using deterministic randomness, ready for keys
+    async function setupDeterministicMachine(
+      id: number,
+      weights: number[]
+    ): Promise<{ gacha: PublicKey; config: PublicKey }> {
+      const gacha = pda(
+        Buffer.from("gacha_state"),
+        admin.publicKey.toBuffer(),
+        le8(new anchor.BN(id))
+      );
+      const config = pda(
+        Buffer.from("payment_config"),
+        gacha.toBuffer(),
+        solMint.toBuffer()
+      );
+
+      await program.methods
+        .initialize(new anchor.BN(id))
+        .accountsPartial({
+          gachaState: gacha,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+      await program.methods
+        .setRandomnessSource({ deterministic: {} })
+        .accountsPartial({ gachaState: gacha, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      await program.methods
+        .addPaymentConfig(solMint, solPrice, admin.publicKey)
+        .accountsPartial({
+          paymentConfig: config,
+          gachaState: gacha,
+          authority: admin.publicKey,
+          paymentMintAccount: solMint,
+          recipientAccount: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+      await program.methods
+        .setTierWeights(weights)
+        .accountsPartial({
+          gachaState: gacha,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+      return { gacha, config };
+    }
+
+    // Creates a finalized deterministic machine with `keysByTier[t]` added to tier `t`,
+    // or a commit-reveal machine committed to `serverSeedHash` when it is given
+    async function createDeterministicMachine(
+      id: number,
+      keysByTier: string[][],
+      weights: number[],
+      serverSeedHash: Buffer | null = null
+    ): Promise<{ gacha: PublicKey; config: PublicKey }> {
+      const { gacha, config } = await setupDeterministicMachine(id, weights);
+      if (serverSeedHash) {
+        await program.methods
+          .setRandomnessSource({ commitReveal: {} })
+          .accountsPartial({ gachaState: gacha, admin: admin.publicKey })
+          .signers([admin])
+          .rpc();
+      }
+
+      // Keys are added in small batches that never cross a page boundary
+      let totalKeys = 0;
+      for (const [tier, keys] of keysByTier.entries()) {
+        let start = 0;
+        while (start < keys.length) {
+          const pageIndex = Math.floor(totalKeys / 64);
+          if (totalKeys % 64 === 0) {
+            await program.methods
+              .addKeyPage()
+              .accountsPartial({
+                keyPage: keyPageOf(gacha, pageIndex),
+                gachaState: gacha,
+                authority: admin.publicKey,
+                systemProgram: SystemProgram.programId,
+              })
+              .signers([admin])
+              .rpc();
+          }
+          const count = Math.min(
+            16,
+            keys.length - start,
+            64 - (totalKeys % 64)
+          );
+          await program.methods
+            .addKeys(keys.slice(start, start + count), tier)
+            .accountsPartial({
+              keyPage: keyPageOf(gacha, pageIndex),
+              gachaState: gacha,
+              authority: admin.publicKey,
+              systemProgram: SystemProgram.programId,
+            })
+            .signers([admin])
+            .rpc();
+          start += count;
+          totalKeys += count;
+        }
+      }
+
+      await program.methods
+        .finalize(serverSeedHash && Array.from(serverSeedHash))
+        .accountsPartial({ gachaState: gacha, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      return { gacha, config };
+    }
+
+    // The value the test-randomness provider resolves for a pull
+    function deterministicValue(
+      gacha: PublicKey,
+      owner: PublicKey,
+      nonce: anchor.BN
+    ): Buffer {
+      return sha256(
+        Buffer.from("gacha-machine/test-randomness"),
+        gacha.toBuffer(),
+        owner.toBuffer(),
+        le8(nonce)
+      );
+    }
+
+    // Mirrors `draw_key_with_pity`: a tier by weight among the tiers with keys
+    // left, then a key within it, or the top tier directly when pity is due.
+    // Mutates `tiers` and `stats` like the program does.
+    function predictDraw(
+      tiers: { weight: number; remainingIndices: number[] }[],
+      pityThreshold: number,
+      stats: { pullsSinceTopTier: number },
+      value: Buffer
+    ): { tier: number; keyIndex: number; pity: boolean } {
+      const topTier = tiers.length - 1;
+      const randomU64 = (offset: number) =>
+        new anchor.BN(value.subarray(offset, offset + 8), "le");
+      const drawFrom = (tier: number) => {
+        const pool = tiers[tier].remainingIndices;
+        const position = randomU64(8).modn(pool.length);
+        const keyIndex = pool[position];
+        pool[position] = pool[pool.length - 1];
+        pool.pop();
+        return keyIndex;
+      };
+
+      const pity =
+        pityThreshold > 0 &&
+        stats.pullsSinceTopTier >= pityThreshold &&
+        tiers[topTier].remainingIndices.length > 0;
+      let tier = topTier;
+      if (!pity) {
+        const candidates = tiers
+          .map((rarityTier, index) => ({ ...rarityTier, index }))
+          .filter((rarityTier) => rarityTier.remainingIndices.length > 0);
+        const totalWeight = candidates.reduce(
+          (sum, rarityTier) => sum + rarityTier.weight,
+          0
+        );
+        let roll = randomU64(0).modn(totalWeight);
+        for (const candidate of candidates) {
+          if (roll < candidate.weight) {
+            tier = candidate.index;
+            break;
+          }
+          roll -= candidate.weight;
+        }
+      }
+      const keyIndex = drawFrom(tier);
+
+      stats.pullsSinceTopTier =
+        tier === topTier ? 0 : stats.pullsSinceTopTier + 1;
+      return { tier, keyIndex, pity };
+    }
+
+    // Mirrors `recompute_commit_reveal_draw`: the draw of a sealed commit-reveal
+    // pull from the server seed of its epoch and a snapshot taken before settling
+    async function commitRevealDraw(
+      gacha: PublicKey,
+      playerState: PublicKey,
+      serverSeed: Buffer,
+      snapshot: Awaited<ReturnType<typeof drawSnapshot>>
+    ) {
+      const { user: owner, nonce, commitment } =
+        await program.account.playerState.fetch(playerState);
+      const value = sha256(
+        Buffer.from("gacha-machine/commit-reveal"),
+        serverSeed,
+        Buffer.from(commitment.clientSeed),
+        Buffer.from(commitment.slotHash),
+        gacha.toBuffer(),
+        owner.toBuffer(),
+        le8(nonce)
+      );
+      return predictDraw(
+        snapshot.tiers,
+        snapshot.pityThreshold,
+        snapshot.stats,
+        value
+      );
+    }
+
+    // Snapshot of the machine's tiers and the user's pity counter to predict from
+    async function drawSnapshot(gacha: PublicKey, owner: PublicKey) {
+      const gachaStateAccount = await program.account.gachaState.fetch(gacha);
+      const userStats = await program.account.userStats.fetchNullable(
+        userStatsOf(gacha, owner)
+      );
+      return {
+        tiers: gachaStateAccount.tiers.map((tier) => ({
+          weight: tier.weight,
+          remainingIndices: [...tier.remainingIndices],
+        })),
+        pityThreshold: gachaStateAccount.pityThreshold,
+        stats: {
+          pullsSinceTopTier: userStats ? userStats.pullsSinceTopTier : 0,
+        },
+      };
+    }
+
+    async function pullSol(
+      gacha: PublicKey,
+      config: PublicKey,
+      puller: Keypair,
+      clientSeed: Buffer = Buffer.alloc(32)
+    ): Promise<{ nonce: anchor.BN; playerState: PublicKey }> {
+      const { pullCount: nonce } = await program.account.gachaState.fetch(
+        gacha
+      );
+      const playerState = pda(
+        Buffer.from("player_state"),
+        gacha.toBuffer(),
+        puller.publicKey.toBuffer(),
+        le8(nonce)
+      );
+      await program.methods
+        .pull(solPrice, Array.from(clientSeed))
+        .accountsPartial({
+          playerState,
+          userStats: userStatsOf(gacha, puller.publicKey),
+          gachaState: gacha,
+          paymentConfig: config,
+          user: puller.publicKey,
+          paymentMint: solMint,
+          userPaymentAccount: puller.publicKey,
+          paymentVault: config,
+          randomnessAccountData: puller.publicKey,
+          randomnessUsage: null,
+          priceFeed: null,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: null,
+        })
+        .signers([puller])
+        .rpc();
+      return { nonce, playerState };
+    }
+
+    // Settles a pull as its user, or as a third-party `caller` collecting a SOL tip.
+    // Unsealed commit-reveal pulls pass the SlotHashes sysvar to be sealed.
+    async function settlePull(
+      gacha: PublicKey,
+      config: PublicKey,
+      owner: PublicKey,
+      playerState: PublicKey,
+      caller: Keypair,
+      slotHashes: PublicKey | null = null
+    ): Promise<string> {
+      const thirdParty = !caller.publicKey.equals(owner);
+      return program.methods
+        .settle()
+        .accountsPartial({
+          playerState,
+          gachaState: gacha,
+          paymentConfig: config,
+          userStats: userStatsOf(gacha, owner),
+          user: owner,
+          caller: caller.publicKey,
+          randomnessAccountData: owner,
+          slotHashes,
+          paymentMint: thirdParty ? solMint : null,
+          paymentVault: thirdParty ? config : null,
+          callerPaymentAccount: thirdParty ? caller.publicKey : null,
+          tokenProgram: null,
+        })
+        .signers([caller])
+        .rpc({ commitment: "confirmed" });
+    }
+
+    async function claimPull(
+      gacha: PublicKey,
+      playerState: PublicKey
+    ): Promise<string> {
+      const { resultIndex } = await program.account.playerState.fetch(
+        playerState
+      );
+      await program.methods
+        .claimKey()
+        .accountsPartial({
+          playerState,
+          gachaState: gacha,
+          keyPage: keyPageOf(gacha, Math.floor(resultIndex / 64)),
+        })
+        .rpc();
+      return (await program.account.playerState.fetch(playerState))
+        .winningEncryptedKey;
+    }
+
+    async function pullManySol(
+      gacha: PublicKey,
+      config: PublicKey,
+      puller: Keypair,
+      count: number,
+      maxPrice = solPrice.muln(count)
+    ): Promise<{ nonce: anchor.BN; multiPullState: PublicKey }> {
+      const { pullCount: nonce } = await program.account.gachaState.fetch(
+        gacha
+      );
+      const multiPullState = pda(
+        Buffer.from("multi_pull_state"),
+        gacha.toBuffer(),
+        puller.publicKey.toBuffer(),
+        le8(nonce)
+      );
+      await program.methods
+        .pullMany(count, maxPrice, Array(32).fill(0))
+        .accountsPartial({
+          multiPullState,
+          userStats: userStatsOf(gacha, puller.publicKey),
+          gachaState: gacha,
+          paymentConfig: config,
+          user: puller.publicKey,
+          paymentMint: solMint,
+          userPaymentAccount: puller.publicKey,
+          paymentVault: config,
+          randomnessAccountData: puller.publicKey,
+          randomnessUsage: null,
+          priceFeed: null,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: null,
+        })
+        .signers([puller])
+        .rpc();
+      return { nonce, multiPullState };
+    }
+
+    // Settles a multi-pull as its user, or as a third-party `caller` collecting a SOL tip
+    async function settleMultiPull(
+      gacha: PublicKey,
+      config: PublicKey,
+      owner: PublicKey,
+      multiPullState: PublicKey,
+      caller: Keypair
+    ) {
+      const thirdParty = !caller.publicKey.equals(owner);
+      await program.methods
+        .settleMany()
+        .accountsPartial({
+          multiPullState,
+          gachaState: gacha,
+          paymentConfig: config,
+          userStats: userStatsOf(gacha, owner),
+          user: owner,
+          caller: caller.publicKey,
+          randomnessAccountData: owner,
+          slotHashes: null,
+          paymentMint: thirdParty ? solMint : null,
+          paymentVault: thirdParty ? config : null,
+          callerPaymentAccount: thirdParty ? caller.publicKey : null,
+          tokenProgram: null,
+        })
+        .signers([caller])
+        .rpc();
+    }
+
+    async function claimMultiPull(
+      gacha: PublicKey,
+      multiPullState: PublicKey,
+      pageIndices: number[]
+    ): Promise<string[]> {
+      await program.methods
+        .claimManyKeys()
+        .accountsPartial({ multiPullState, gachaState: gacha })
+        .remainingAccounts(
+          pageIndices.map((pageIndex) => ({
+            pubkey: keyPageOf(gacha, pageIndex),
+            isWritable: false,
+            isSigner: false,
+          }))
+        )
+        .rpc();
+      return (await program.account.multiPullState.fetch(multiPullState))
+        .winningEncryptedKeys;
+    }
+
+    // Refunds a pull to its user, signed by the user or the admin
+    async function refundSol(
+      gacha: PublicKey,
+      config: PublicKey,
+      owner: PublicKey,
+      playerState: PublicKey,
+      authority: Keypair
+    ) {
+      await program.methods
+        .refund()
+        .accountsPartial({
+          playerState,
+          gachaState: gacha,
+          paymentConfig: config,
+          authority: authority.publicKey,
+          user: owner,
+          paymentMint: solMint,
+          userPaymentAccount: owner,
+          paymentVault: config,
+          randomnessAccountData: owner,
+          tokenProgram: null,
+        })
+        .signers([authority])
+        .rpc();
+    }
+
+    // Closes a finished pull of `user`, returning its rent
+    async function closePull(
+      gacha: PublicKey,
+      playerState: PublicKey
+    ): Promise<string> {
+      return program.methods
+        .closePlayerState()
+        .accountsPartial({
+          playerState,
+          gachaState: gacha,
+          user: user.publicKey,
+        })
+        .signers([user])
+        .rpc({ commitment: "confirmed" });
+    }
+
+    // Withdraws settled SOL payments to the admin
+    async function withdrawSol(
+      gacha: PublicKey,
+      config: PublicKey,
+      authority: Keypair,
+      amount: anchor.BN
+    ): Promise<string> {
+      return program.methods
+        .withdraw(solMint, amount)
+        .accountsPartial({
+          paymentConfig: config,
+          gachaState: gacha,
+          authority: authority.publicKey,
+          paymentMintAccount: solMint,
+          paymentVault: config,
+          adminRecipientAccount: admin.publicKey,
+          tokenProgram: null,
+        })
+        .signers([authority])
+        .rpc({ commitment: "confirmed" });
+    }
+
+    // Decodes the program events emitted by a confirmed transaction
+    async function eventsOf(
+      signature: string
+    ): Promise<{ name: string; data: any }[]> {
+      const transaction = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const parser = new anchor.EventParser(program.programId, program.coder);
+      return Array.from(parser.parseLogs(transaction.meta.logMessages));
+    }
+
+    async function lamports(account: PublicKey): Promise<number> {
+      return provider.connection.getBalance(account);
+    }
+
+    before(async () => {
+      ({ gacha: machine, config: solConfig } = await createDeterministicMachine(
+        3,
+        tierKeys,
+        [3, 1]
+      ));
+
+      cranker = Keypair.generate();
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(
+          cranker.publicKey,
+          LAMPORTS_PER_SOL
+        )
+      );
+    });
+
+    it("should pull, settle and claim the predicted key", async () => {
+      const snapshot = await drawSnapshot(machine, user.publicKey);
+      const userBalanceBefore = await lamports(user.publicKey);
+
+      const { nonce, playerState } = await pullSol(machine, solConfig, user);
+
+      // The payment is escrowed until settlement
+      let config = await program.account.paymentConfig.fetch(solConfig);
+      expect(config.escrowedAmount.toString()).to.equal(solPrice.toString());
+      expect(config.settledAmount.toString()).to.equal("0");
+      const pullRent =
+        (await lamports(playerState)) +
+        (await lamports(userStatsOf(machine, user.publicKey)));
+      expect(userBalanceBefore - (await lamports(user.publicKey))).to.equal(
+        solPrice.toNumber() + pullRent
+      );
+
+      await waitForNextSlot();
+      await settlePull(machine, solConfig, user.publicKey, playerState, user);
+
+      const expected = predictDraw(
+        snapshot.tiers,
+        snapshot.pityThreshold,
+        snapshot.stats,
+        deterministicValue(machine, user.publicKey, nonce)
+      );
+      const playerStateAccount = await program.account.playerState.fetch(
+        playerState
+      );
+      expect(playerStateAccount.isSettled).to.be.true;
+      expect(playerStateAccount.resultIndex).to.equal(expected.keyIndex);
+      expect(playerStateAccount.resultTier).to.equal(expected.tier);
+
+      config = await program.account.paymentConfig.fetch(solConfig);
+      expect(config.escrowedAmount.toString()).to.equal("0");
+      expect(config.settledAmount.toString()).to.equal(solPrice.toString());
+
+      expect(await claimPull(machine, playerState)).to.equal(
+        allKeys[expected.keyIndex]
+      );
+      const gachaStateAccount = await program.account.gachaState.fetch(machine);
+      expect(gachaStateAccount.settleCount.toNumber()).to.equal(1);
+      expect(gachaStateAccount.claimCount.toNumber()).to.equal(1);
+
+      try {
+        await claimPull(machine, playerState);
+        expect.fail("Should not claim the same key twice");
+      } catch (error) {
+        expect(error.toString()).to.include("AlreadyClaimed");
+      }
+    });
+
+    it("should reject a randomness usage marker on non-Switchboard pulls", async () => {
+      const { pullCount } = await program.account.gachaState.fetch(machine);
+      const randomnessUsage = pda(
+        Buffer.from("randomness_usage"),
+        user.publicKey.toBuffer()
+      );
+      try {
+        await program.methods
+          .pull(solPrice, Array.from(Buffer.alloc(32)))
+          .accountsPartial({
+            playerState: pda(
+              Buffer.from("player_state"),
+              machine.toBuffer(),
+              user.publicKey.toBuffer(),
+              le8(pullCount)
+            ),
+            userStats: userStatsOf(machine, user.publicKey),
+            gachaState: machine,
+            paymentConfig: solConfig,
+            user: user.publicKey,
+            paymentMint: solMint,
+            userPaymentAccount: user.publicKey,
+            paymentVault: solConfig,
+            randomnessAccountData: user.publicKey,
+            randomnessUsage,
+            priceFeed: null,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: null,
+          })
+          .signers([user])
+          .rpc();
+        expect.fail("Should not create a marker that is never read");
+      } catch (error) {
+        expect(error.toString()).to.include("RandomnessSourceMismatch");
+      }
+      expect(await provider.connection.getAccountInfo(randomnessUsage)).to.be
+        .null;
+    });
+
+    it("should force a top-tier draw once pity is due and reset the counter", async () => {
+      // Pity is due after `pullsSinceTopTier` unlucky pulls, so at least one is needed
+      let stats = await program.account.userStats.fetch(
+        userStatsOf(machine, user.publicKey)
+      );
+      while (stats.pullsSinceTopTier === 0) {
+        const unlucky = await pullSol(machine, solConfig, user);
+        await waitForNextSlot();
+        await settlePull(
+          machine,
+          solConfig,
+          user.publicKey,
+          unlucky.playerState,
+          user
+        );
+        await claimPull(machine, unlucky.playerState);
+        stats = await program.account.userStats.fetch(
+          userStatsOf(machine, user.publicKey)
+        );
+      }
+      const { pullsSinceTopTier, totalSettled } = stats;
+      await program.methods
+        .setPityThreshold(pullsSinceTopTier)
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      const snapshot = await drawSnapshot(machine, user.publicKey);
+      const { nonce, playerState } = await pullSol(machine, solConfig, user);
+      await waitForNextSlot();
+      const signature = await settlePull(
+        machine,
+        solConfig,
+        user.publicKey,
+        playerState,
+        user
+      );
+
+      const expected = predictDraw(
+        snapshot.tiers,
+        snapshot.pityThreshold,
+        snapshot.stats,
+        deterministicValue(machine, user.publicKey, nonce)
+      );
+      expect(expected.pity).to.be.true;
+      const playerStateAccount = await program.account.playerState.fetch(
+        playerState
+      );
+      expect(playerStateAccount.resultTier).to.equal(tierKeys.length - 1);
+      expect(playerStateAccount.resultIndex).to.equal(expected.keyIndex);
+
+      const pityTriggered = (await eventsOf(signature)).find(
+        (event) => event.name === "pityTriggered"
+      );
+      expect(pityTriggered.data.nonce.toString()).to.equal(nonce.toString());
+      expect(pityTriggered.data.keyIndex).to.equal(expected.keyIndex);
+      expect(pityTriggered.data.tier).to.equal(tierKeys.length - 1);
+      expect(pityTriggered.data.pullsWithoutTopTier).to.equal(
+        pullsSinceTopTier
+      );
+
+      const userStats = await program.account.userStats.fetch(
+        userStatsOf(machine, user.publicKey)
+      );
+      expect(userStats.pullsSinceTopTier).to.equal(0);
+      expect(userStats.totalSettled.toNumber()).to.equal(
+        totalSettled.toNumber() + 1
+      );
+
+      await claimPull(machine, playerState);
+      await program.methods
+        .setPityThreshold(0)
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("should draw weighted tiers and claim keys across pages with a multi-pull", async () => {
+      const count = 10;
+      const snapshot = await drawSnapshot(machine, user.publicKey);
+      const { nonce, multiPullState } = await pullManySol(
+        machine,
+        solConfig,
+        user,
+        count
+      );
+      expect(
+        (await program.account.gachaState.fetch(machine)).pullCount.toNumber()
+      ).to.equal(nonce.toNumber() + count);
+
+      await waitForNextSlot();
+      await settleMultiPull(
+        machine,
+        solConfig,
+        user.publicKey,
+        multiPullState,
+        user
+      );
+
+      // Every draw expands the pull's value and follows the tier weights
+      const value = deterministicValue(machine, user.publicKey, nonce);
+      const expected = Array.from({ length: count }, (_, draw) =>
+        predictDraw(
+          snapshot.tiers,
+          snapshot.pityThreshold,
+          snapshot.stats,
+          sha256(value, Buffer.from([draw]))
+        )
+      );
+      const multiPull = await program.account.multiPullState.fetch(
+        multiPullState
+      );
+      expect(multiPull.resultIndices).to.deep.equal(
+        expected.map((draw) => draw.keyIndex)
+      );
+      expect(multiPull.resultTiers).to.deep.equal(
+        expected.map((draw) => draw.tier)
+      );
+      const gachaStateAccount = await program.account.gachaState.fetch(machine);
+      expect(
+        gachaStateAccount.tiers.map((tier) => tier.remainingIndices.length)
+      ).to.deep.equal(
+        snapshot.tiers.map((tier) => tier.remainingIndices.length)
+      );
+
+      // A page the batch drew from cannot be left out
+      const drawnPage = Math.floor(expected[0].keyIndex / 64);
+      try {
+        await claimMultiPull(machine, multiPullState, [1 - drawnPage]);
+        expect.fail("Should need every page the multi-pull drew from");
+      } catch (error) {
+        expect(error.toString()).to.include("InvalidKeyPage");
+      }
+      expect(
+        await claimMultiPull(machine, multiPullState, [0, 1])
+      ).to.deep.equal(expected.map((draw) => allKeys[draw.keyIndex]));
+    });
+
+    it("should charge discounted multi-pulls and refund the whole batch", async () => {
+      const count = 4;
+      const discountBps = 1000;
+      await program.methods
+        .setBulkDiscount(solMint, discountBps)
+        .accountsPartial({
+          paymentConfig: solConfig,
+          gachaState: machine,
+          authority: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      const discounted = solPrice
+        .muln(count)
+        .muln(10000 - discountBps)
+        .divn(10000);
+
+      try {
+        await pullManySol(machine, solConfig, user, count, discounted.subn(1));
+        expect.fail("Should compare max_price with the discounted total");
+      } catch (error) {
+        expect(error.toString()).to.include("PriceExceedsMax");
+      }
+
+      const escrowBefore = (
+        await program.account.paymentConfig.fetch(solConfig)
+      ).escrowedAmount;
+      const { multiPullState } = await pullManySol(
+        machine,
+        solConfig,
+        user,
+        count,
+        discounted
+      );
+      const multiPull = await program.account.multiPullState.fetch(
+        multiPullState
+      );
+      expect(multiPull.pricePaid.toString()).to.equal(discounted.toString());
+      expect(
+        (
+          await program.account.paymentConfig.fetch(solConfig)
+        ).escrowedAmount.toString()
+      ).to.equal(escrowBefore.add(discounted).toString());
+
+      // The refund returns what was paid for the batch, in one go (only a halt
+      // lets even the admin refund a multi-pull that can still be settled)
+      await program.methods
+        .setHalted(true)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      const userBalanceBefore = await lamports(user.publicKey);
+      await program.methods
+        .refundMany()
+        .accountsPartial({
+          multiPullState,
+          gachaState: machine,
+          paymentConfig: solConfig,
+          authority: admin.publicKey,
+          user: user.publicKey,
+          paymentMint: solMint,
+          userPaymentAccount: user.publicKey,
+          paymentVault: solConfig,
+          randomnessAccountData: user.publicKey,
+          tokenProgram: null,
+        })
+        .signers([admin])
+        .rpc();
+      expect((await lamports(user.publicKey)) - userBalanceBefore).to.equal(
+        discounted.toNumber()
+      );
+      expect(
+        (await program.account.multiPullState.fetch(multiPullState)).isRefunded
+      ).to.be.true;
+      expect(
+        (
+          await program.account.paymentConfig.fetch(solConfig)
+        ).escrowedAmount.toString()
+      ).to.equal(escrowBefore.toString());
+      await program.methods
+        .setHalted(false)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      await waitForNextSlot();
+      try {
+        await settleMultiPull(
+          machine,
+          solConfig,
+          user.publicKey,
+          multiPullState,
+          user
+        );
+        expect.fail("Should not settle a refunded multi-pull");
+      } catch (error) {
+        expect(error.toString()).to.include("AlreadyRefunded");
+      }
+
+      await program.methods
+        .setBulkDiscount(solMint, 0)
+        .accountsPartial({
+          paymentConfig: solConfig,
+          gachaState: machine,
+          authority: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("should let the user refund a stalled pull only after the timeout", async () => {
+      await program.methods
+        .setRefundTimeout(new anchor.BN(1))
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      const { playerState } = await pullSol(machine, solConfig, user);
+      const escrowBefore = (
+        await program.account.paymentConfig.fetch(solConfig)
+      ).escrowedAmount;
+      await waitForNextSlot();
+
+      const refund = () =>
+        refundSol(machine, solConfig, user.publicKey, playerState, user);
+
+      // Deterministic randomness can always be resolved, so only a halt unlocks it
+      try {
+        await refund();
+        expect.fail("Should not refund a pull that can still be settled");
+      } catch (error) {
+        expect(error.toString()).to.include("RandomnessResolvable");
+      }
+
+      await program.methods
+        .setHalted(true)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      const userBalanceBefore = await lamports(user.publicKey);
+      await refund();
+
+      expect((await lamports(user.publicKey)) - userBalanceBefore).to.equal(
+        solPrice.toNumber()
+      );
+      expect(
+        (await program.account.playerState.fetch(playerState)).isRefunded
+      ).to.be.true;
+      expect(
+        (
+          await program.account.paymentConfig.fetch(solConfig)
+        ).escrowedAmount.toString()
+      ).to.equal(escrowBefore.sub(solPrice).toString());
+
+      await program.methods
+        .setHalted(false)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      await program.methods
+        .setRefundTimeout(new anchor.BN(0))
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("should return escrowed payments on refund and reject refunds of finished pulls", async () => {
+      const refunded = await pullSol(machine, solConfig, user);
+      const configBefore = await program.account.paymentConfig.fetch(
+        solConfig
+      );
+      const { refundCount } = await program.account.gachaState.fetch(machine);
+
+      try {
+        await refundSol(
+          machine,
+          solConfig,
+          user.publicKey,
+          refunded.playerState,
+          cranker
+        );
+        expect.fail("Only the user or the admin can refund a pull");
+      } catch (error) {
+        expect(error.toString()).to.include("UnauthorizedRefund");
+      }
+      try {
+        await refundSol(
+          machine,
+          solConfig,
+          user.publicKey,
+          refunded.playerState,
+          user
+        );
+        expect.fail("Users cannot refund while the refund timeout is disabled");
+      } catch (error) {
+        expect(error.toString()).to.include("RefundNotAvailable");
+      }
+
+      // Not even the admin can refund a pull that can still be settled
+      try {
+        await refundSol(
+          machine,
+          solConfig,
+          user.publicKey,
+          refunded.playerState,
+          admin
+        );
+        expect.fail("Should not refund a resolvable pull");
+      } catch (error) {
+        expect(error.toString()).to.include("RandomnessResolvable");
+      }
+
+      // Once halted, the admin can refund without waiting, straight from escrow
+      await program.methods
+        .setHalted(true)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      const userBalanceBefore = await lamports(user.publicKey);
+      const vaultBalanceBefore = await lamports(solConfig);
+      await refundSol(
+        machine,
+        solConfig,
+        user.publicKey,
+        refunded.playerState,
+        admin
+      );
+      await program.methods
+        .setHalted(false)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      expect((await lamports(user.publicKey)) - userBalanceBefore).to.equal(
+        solPrice.toNumber()
+      );
+      expect(vaultBalanceBefore - (await lamports(solConfig))).to.equal(
+        solPrice.toNumber()
+      );
+      const configAfter = await program.account.paymentConfig.fetch(solConfig);
+      expect(configAfter.escrowedAmount.toString()).to.equal(
+        configBefore.escrowedAmount.sub(solPrice).toString()
+      );
+      expect(configAfter.settledAmount.toString()).to.equal(
+        configBefore.settledAmount.toString()
+      );
+      expect(
+        (await program.account.gachaState.fetch(machine)).refundCount.toNumber()
+      ).to.equal(refundCount.toNumber() + 1);
+
+      try {
+        await refundSol(
+          machine,
+          solConfig,
+          user.publicKey,
+          refunded.playerState,
+          admin
+        );
+        expect.fail("Should not refund a pull twice");
+      } catch (error) {
+        expect(error.toString()).to.include("AlreadyRefunded");
+      }
+      await waitForNextSlot();
+      try {
+        await settlePull(
+          machine,
+          solConfig,
+          user.publicKey,
+          refunded.playerState,
+          user
+        );
+        expect.fail("Should not settle a refunded pull");
+      } catch (error) {
+        expect(error.toString()).to.include("AlreadyRefunded");
+      }
+
+      // Settled payments leave escrow for good
+      const settled = await pullSol(machine, solConfig, user);
+      await waitForNextSlot();
+      await settlePull(
+        machine,
+        solConfig,
+        user.publicKey,
+        settled.playerState,
+        user
+      );
+      try {
+        await refundSol(
+          machine,
+          solConfig,
+          user.publicKey,
+          settled.playerState,
+          admin
+        );
+        expect.fail("Should not refund a settled pull");
+      } catch (error) {
+        expect(error.toString()).to.include("AlreadySettled");
+      }
+      await claimPull(machine, settled.playerState);
+    });
+
+    it("should pay the crank tip to a third party settling a pull", async () => {
+      const tipBps = 500;
+      await program.methods
+        .setCrankTip(tipBps)
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      const { playerState } = await pullSol(machine, solConfig, user);
+      await waitForNextSlot();
+      const settledBefore = (
+        await program.account.paymentConfig.fetch(solConfig)
+      ).settledAmount;
+      const crankerBalanceBefore = await lamports(cranker.publicKey);
+
+      await settlePull(
+        machine,
+        solConfig,
+        user.publicKey,
+        playerState,
+        cranker
+      );
+
+      const tip = solPrice.muln(tipBps).divn(10000);
+      expect(
+        (await lamports(cranker.publicKey)) - crankerBalanceBefore
+      ).to.equal(tip.toNumber());
+      expect(
+        (
+          await program.account.paymentConfig.fetch(solConfig)
+        ).settledAmount.toString()
+      ).to.equal(settledBefore.add(solPrice).sub(tip).toString());
+
+      await claimPull(machine, playerState);
+      await program.methods
+        .setCrankTip(0)
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("should require tip accounts from third parties but not from the user", async () => {
+      const tipBps = 1000;
+      await program.methods
+        .setCrankTip(tipBps)
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      const tip = solPrice.muln(tipBps).divn(10000);
+
+      // A third party settling without the tip accounts is rejected
+      const thirdParty = await pullSol(machine, solConfig, user);
+      await waitForNextSlot();
+      try {
+        await program.methods
+          .settle()
+          .accountsPartial({
+            playerState: thirdParty.playerState,
+            gachaState: machine,
+            paymentConfig: solConfig,
+            userStats: userStatsOf(machine, user.publicKey),
+            user: user.publicKey,
+            caller: cranker.publicKey,
+            randomnessAccountData: user.publicKey,
+            slotHashes: null,
+            paymentMint: null,
+            paymentVault: null,
+            callerPaymentAccount: null,
+            tokenProgram: null,
+          })
+          .signers([cranker])
+          .rpc();
+        expect.fail("Should require the tip accounts");
+      } catch (error) {
+        expect(error.toString()).to.include("CrankTipAccountsMissing");
+      }
+
+      // The user settling their own pull keeps the whole price in settled funds
+      const settledBefore = (
+        await program.account.paymentConfig.fetch(solConfig)
+      ).settledAmount;
+      await settlePull(
+        machine,
+        solConfig,
+        user.publicKey,
+        thirdParty.playerState,
+        user
+      );
+      expect(
+        (
+          await program.account.paymentConfig.fetch(solConfig)
+        ).settledAmount.toString()
+      ).to.equal(settledBefore.add(solPrice).toString());
+      await claimPull(machine, thirdParty.playerState);
+
+      // A multi-pull tips on the price paid for the whole batch
+      const count = 2;
+      const { multiPullState } = await pullManySol(
+        machine,
+        solConfig,
+        user,
+        count
+      );
+      await waitForNextSlot();
+      const crankerBalanceBefore = await lamports(cranker.publicKey);
+      await settleMultiPull(
+        machine,
+        solConfig,
+        user.publicKey,
+        multiPullState,
+        cranker
+      );
+      expect(
+        (await lamports(cranker.publicKey)) - crankerBalanceBefore
+      ).to.equal(tip.muln(count).toNumber());
+
+      await claimMultiPull(machine, multiPullState, [0, 1]);
+      await program.methods
+        .setCrankTip(0)
+        .accountsPartial({ gachaState: machine, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("should only withdraw settled payments", async () => {
+      const config = await program.account.paymentConfig.fetch(solConfig);
+      const withdrawable = config.settledAmount.sub(config.withdrawnAmount);
+      const withdraw = (amount: anchor.BN) =>
+        withdrawSol(machine, solConfig, admin, amount);
+
+      try {
+        await withdraw(withdrawable.addn(1));
+        expect.fail("Should not withdraw more than the settled payments");
+      } catch (error) {
+        expect(error.toString()).to.include("InsufficientWithdrawableFunds");
+      }
+
+      const adminBalanceBefore = await lamports(admin.publicKey);
+      await withdraw(withdrawable);
+      expect((await lamports(admin.publicKey)) - adminBalanceBefore).to.equal(
+        withdrawable.toNumber()
+      );
+      const configAfter = await program.account.paymentConfig.fetch(solConfig);
+      expect(configAfter.withdrawnAmount.toString()).to.equal(
+        config.settledAmount.toString()
+      );
+    });
+
+    it("should gross up Token-2022 transfer fees so the vault receives the full price", async () => {
+      // 1% transfer fee, charged on top of the price
+      const feeBps = 100;
+      const tokenPrice = new anchor.BN(1_000_000);
+      const feeMint = await createTransferFeeMint(
+        feeBps,
+        BigInt(1_000_000_000)
+      );
+      const adminFeeAccount = await createAccount(
+        provider.connection,
+        admin,
+        feeMint,
+        admin.publicKey,
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const userFeeAccount = await createAccount(
+        provider.connection,
+        user,
+        feeMint,
+        user.publicKey,
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      await mintTo(
+        provider.connection,
+        admin,
+        feeMint,
+        userFeeAccount,
+        admin.publicKey,
+        10_000_000,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const feeConfig = pda(
+        Buffer.from("payment_config"),
+        machine.toBuffer(),
+        feeMint.toBuffer()
+      );
+      await program.methods
+        .addPaymentConfig(feeMint, tokenPrice, adminFeeAccount)
+        .accountsPartial({
+          paymentConfig: feeConfig,
+          gachaState: machine,
+          authority: admin.publicKey,
+          paymentMintAccount: feeMint,
+          recipientAccount: adminFeeAccount,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+      const feeVault = await createAssociatedTokenAccountIdempotent(
+        provider.connection,
+        admin,
+        feeMint,
+        feeConfig,
+        undefined,
+        TOKEN_2022_PROGRAM_ID,
+        undefined,
+        true
+      );
+
+      const { pullCount: nonce } = await program.account.gachaState.fetch(
+        machine
+      );
+      const playerState = pda(
+        Buffer.from("player_state"),
+        machine.toBuffer(),
+        user.publicKey.toBuffer(),
+        le8(nonce)
+      );
+      const userBalanceBefore = (
+        await getAccount(
+          provider.connection,
+          userFeeAccount,
+          undefined,
+          TOKEN_2022_PROGRAM_ID
+        )
+      ).amount;
+      await program.methods
+        .pull(tokenPrice.muln(2), Array(32).fill(0))
+        .accountsPartial({
+          playerState,
+          userStats: userStatsOf(machine, user.publicKey),
+          gachaState: machine,
+          paymentConfig: feeConfig,
+          user: user.publicKey,
+          paymentMint: feeMint,
+          userPaymentAccount: userFeeAccount,
+          paymentVault: feeVault,
+          randomnessAccountData: user.publicKey,
+          randomnessUsage: null,
+          priceFeed: null,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+      // The user pays the price plus the fee withheld on that transfer
+      const charged = Number(
+        userBalanceBefore -
+          (
+            await getAccount(
+              provider.connection,
+              userFeeAccount,
+              undefined,
+              TOKEN_2022_PROGRAM_ID
+            )
+          ).amount
+      );
+      const fee = Math.ceil((charged * feeBps) / 10000);
+      expect(charged - fee).to.equal(tokenPrice.toNumber());
+      expect(
+        Number(
+          (
+            await getAccount(
+              provider.connection,
+              feeVault,
+              undefined,
+              TOKEN_2022_PROGRAM_ID
+            )
+          ).amount
+        )
+      ).to.equal(tokenPrice.toNumber());
+      expect(
+        (
+          await program.account.playerState.fetch(playerState)
+        ).pricePaid.toString()
+      ).to.equal(tokenPrice.toString());
+      expect(
+        (
+          await program.account.paymentConfig.fetch(feeConfig)
+        ).escrowedAmount.toString()
+      ).to.equal(tokenPrice.toString());
+
+      await waitForNextSlot();
+      await settlePull(machine, feeConfig, user.publicKey, playerState, user);
+      await claimPull(machine, playerState);
+      expect(
+        (
+          await program.account.paymentConfig.fetch(feeConfig)
+        ).settledAmount.toString()
+      ).to.equal(tokenPrice.toString());
+    });
+
+    it("should keep escrowed payments out of withdrawals until settled", async () => {
+      const { playerState } = await pullSol(machine, solConfig, user);
+
+      // The vault holds the new payment, but none of it is settled yet
+      try {
+        await withdrawSol(machine, solConfig, admin, new anchor.BN(1));
+        expect.fail("Should not withdraw escrowed payments");
+      } catch (error) {
+        expect(error.toString()).to.include("InsufficientWithdrawableFunds");
+      }
+
+      await waitForNextSlot();
+      await settlePull(machine, solConfig, user.publicKey, playerState, user);
+      await claimPull(machine, playerState);
+
+      try {
+        await withdrawSol(machine, solConfig, user, solPrice);
+        expect.fail("Only the admin or a treasurer can withdraw");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+
+      const { withdrawnAmount } = await program.account.paymentConfig.fetch(
+        solConfig
+      );
+      const signature = await withdrawSol(machine, solConfig, admin, solPrice);
+      const withdrawn = (await eventsOf(signature)).find(
+        (event) => event.name === "withdrawn"
+      );
+      expect(withdrawn.data.amount.toString()).to.equal(solPrice.toString());
+      expect(withdrawn.data.recipient.toString()).to.equal(
+        admin.publicKey.toString()
+      );
+      expect(withdrawn.data.totalWithdrawn.toString()).to.equal(
+        withdrawnAmount.add(solPrice).toString()
+      );
+    });
+
+    it("should close finished pulls and emit their receipts", async () => {
+      // Unfinished pulls stay open until settled and claimed, or refunded
+      const refunded = await pullSol(machine, solConfig, user);
+      const settled = await pullSol(machine, solConfig, user);
+      try {
+        await closePull(machine, refunded.playerState);
+        expect.fail("Should not close an unsettled pull");
+      } catch (error) {
+        expect(error.toString()).to.include("PullNotFinished");
+      }
+      await waitForNextSlot();
+      await settlePull(
+        machine,
+        solConfig,
+        user.publicKey,
+        settled.playerState,
+        user
+      );
+      try {
+        await closePull(machine, settled.playerState);
+        expect.fail("Should not close a pull whose key is unclaimed");
+      } catch (error) {
+        expect(error.toString()).to.include("PullNotFinished");
+      }
+      const winningKey = await claimPull(machine, settled.playerState);
+      const { resultIndex, resultTier } = await program.account.playerState.fetch(
+        settled.playerState
+      );
+
+      const rent = await lamports(settled.playerState);
+      const userBalanceBefore = await lamports(user.publicKey);
+      const receipt = (
+        await eventsOf(await closePull(machine, settled.playerState))
+      ).find((event) => event.name === "playerStateClosed");
+      expect((await lamports(user.publicKey)) - userBalanceBefore).to.equal(
+        rent
+      );
+      expect(receipt.data.nonce.toString()).to.equal(settled.nonce.toString());
+      expect(receipt.data.pricePaid.toString()).to.equal(solPrice.toString());
+      expect(receipt.data.isRefunded).to.be.false;
+      expect(receipt.data.resultIndex).to.equal(resultIndex);
+      expect(receipt.data.resultTier).to.equal(resultTier);
+      expect(receipt.data.winningEncryptedKey).to.equal(winningKey);
+
+      await program.methods
+        .setHalted(true)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      await refundSol(
+        machine,
+        solConfig,
+        user.publicKey,
+        refunded.playerState,
+        admin
+      );
+      await program.methods
+        .setHalted(false)
+        .accountsPartial({ gachaState: machine, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      const refundReceipt = (
+        await eventsOf(await closePull(machine, refunded.playerState))
+      ).find((event) => event.name === "playerStateClosed");
+      expect(refundReceipt.data.isRefunded).to.be.true;
+      expect(refundReceipt.data.winningEncryptedKey).to.equal("");
+
+      // Multi-pulls close the same way once every key is claimed
+      const { multiPullState } = await pullManySol(
+        machine,
+        solConfig,
+        user,
+        2
+      );
+      await waitForNextSlot();
+      await settleMultiPull(
+        machine,
+        solConfig,
+        user.publicKey,
+        multiPullState,
+        user
+      );
+      const winningKeys = await claimMultiPull(
+        machine,
+        multiPullState,
+        [0, 1]
+      );
+      const { resultIndices } = await program.account.multiPullState.fetch(
+        multiPullState
+      );
+      const multiReceipt = (
+        await eventsOf(
+          await program.methods
+            .closeMultiPullState()
+            .accountsPartial({
+              multiPullState,
+              gachaState: machine,
+              user: user.publicKey,
+            })
+            .signers([user])
+            .rpc({ commitment: "confirmed" })
+        )
+      ).find((event) => event.name === "multiPullStateClosed");
+      expect(multiReceipt.data.keyIndices).to.deep.equal(resultIndices);
+      expect(multiReceipt.data.winningEncryptedKeys).to.deep.equal(winningKeys);
+      expect(
+        await program.account.multiPullState.fetchNullable(multiPullState)
+      ).to.be.null;
+    });
+
+    it("should claim keys committed as a Merkle root with proofs built off-chain", async () => {
+      const merkleKeys = Array.from({ length: 5 }, (_, i) => `e2e_merkle_${i}`);
+      const tree = buildKeyMerkleTree(merkleKeys);
+      const { gacha, config } = await setupDeterministicMachine(5, [1]);
+      await program.methods
+        .finalizeWithRoot(
+          Array.from(tree.root),
+          merkleKeys.length,
+          [merkleKeys.length],
+          null
+        )
+        .accountsPartial({
+          gachaState: gacha,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+      const { playerState } = await pullSol(gacha, config, user);
+      const { multiPullState } = await pullManySol(gacha, config, user, 3);
+      await waitForNextSlot();
+      await settlePull(gacha, config, user.publicKey, playerState, user);
+      await settleMultiPull(
+        gacha,
+        config,
+        user.publicKey,
+        multiPullState,
+        user
+      );
+
+      const claimWithProof = (keyIndex: number) =>
+        program.methods
+          .claimKeyWithProof(
+            merkleKeys[keyIndex],
+            tree.getProof(keyIndex).map((node) => Array.from(node))
+          )
+          .accountsPartial({ playerState, gachaState: gacha })
+          .rpc();
+
+      // A valid leaf of another index does not prove the drawn key
+      const { resultIndex } = await program.account.playerState.fetch(
+        playerState
+      );
+      try {
+        await claimWithProof((resultIndex + 1) % merkleKeys.length);
+        expect.fail("Should only accept the key at the drawn index");
+      } catch (error) {
+        expect(error.toString()).to.include("InvalidKeyProof");
+      }
+      await claimWithProof(resultIndex);
+      expect(
+        (await program.account.playerState.fetch(playerState))
+          .winningEncryptedKey
+      ).to.equal(merkleKeys[resultIndex]);
+
+      const { resultIndices } = await program.account.multiPullState.fetch(
+        multiPullState
+      );
+      for (const [position, keyIndex] of resultIndices.entries()) {
+        await program.methods
+          .claimManyKeyWithProof(
+            position,
+            merkleKeys[keyIndex],
+            tree.getProof(keyIndex).map((node) => Array.from(node))
+          )
+          .accountsPartial({ multiPullState, gachaState: gacha })
+          .rpc();
+      }
+      expect(
+        (await program.account.multiPullState.fetch(multiPullState))
+          .winningEncryptedKeys
+      ).to.deep.equal(resultIndices.map((keyIndex) => merkleKeys[keyIndex]));
+    });
+
+    it("should settle commit-reveal pulls with the draw recomputed from the revealed seed", async () => {
+      // Hash chain s0 -> s1 -> s2: s2 is committed, s1 settles the first epoch
+      const s0 = Buffer.alloc(32, 42);
+      const s1 = sha256(s0);
+      const s2 = sha256(s1);
+      const { gacha, config } = await createDeterministicMachine(
+        6,
+        [
+          Array.from({ length: 6 }, (_, i) => `e2e_cr_common_${i}`),
+          Array.from({ length: 2 }, (_, i) => `e2e_cr_rare_${i}`),
+        ],
+        [3, 1],
+        s2
+      );
+
+      const sealed = await pullSol(gacha, config, user, Buffer.alloc(32, 1));
+      const unsealed = await pullSol(gacha, config, user, Buffer.alloc(32, 2));
+      await waitForNextSlot();
+      await program.methods
+        .sealPull()
+        .accountsPartial({
+          playerState: sealed.playerState,
+          gachaState: gacha,
+          slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+        })
+        .rpc();
+
+      try {
+        await settlePull(
+          gacha,
+          config,
+          user.publicKey,
+          sealed.playerState,
+          user
+        );
+        expect.fail("Should wait for the server seed of the pull's epoch");
+      } catch (error) {
+        expect(error.toString()).to.include("ServerSeedNotRevealed");
+      }
+      await program.methods
+        .revealServerSeed(Array.from(s1))
+        .accountsPartial({ gachaState: gacha, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      const gachaStateAccount = await program.account.gachaState.fetch(gacha);
+      expect(Buffer.from(gachaStateAccount.seedCommitment)).to.deep.equal(s1);
+      expect(gachaStateAccount.seedEpoch).to.equal(1);
+
+      // Sealed by `seal_pull`, then by the settlement itself
+      for (const [pull, slotHashes] of [
+        [sealed, null],
+        [unsealed, SYSVAR_SLOT_HASHES_PUBKEY],
+      ] as const) {
+        const snapshot = await drawSnapshot(gacha, user.publicKey);
+        await settlePull(
+          gacha,
+          config,
+          user.publicKey,
+          pull.playerState,
+          user,
+          slotHashes
+        );
+
+        const expected = await commitRevealDraw(
+          gacha,
+          pull.playerState,
+          s1,
+          snapshot
+        );
+        const playerStateAccount = await program.account.playerState.fetch(
+          pull.playerState
+        );
+        expect(playerStateAccount.commitment.slotHash).to.not.deep.equal(
+          Array(32).fill(0)
+        );
+        expect(playerStateAccount.resultTier).to.equal(expected.tier);
+        expect(playerStateAccount.resultIndex).to.equal(expected.keyIndex);
+      }
+    });
+
+    it("should pull instantly only as the last top-level instruction", async () => {
+      const instantKeys = [
+        Array.from({ length: 4 }, (_, i) => `e2e_instant_common_${i}`),
+        Array.from({ length: 2 }, (_, i) => `e2e_instant_rare_${i}`),
+      ];
+      const { gacha, config } = await createDeterministicMachine(
+        7,
+        instantKeys,
+        [3, 1]
+      );
+      await program.methods
+        .setInstantMode(new anchor.BN(1_000_000)) // $1
+        .accountsPartial({ gachaState: gacha, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      const pullInstant = async () => {
+        const { pullCount: nonce } = await program.account.gachaState.fetch(
+          gacha
+        );
+        const playerState = pda(
+          Buffer.from("player_state"),
+          gacha.toBuffer(),
+          user.publicKey.toBuffer(),
+          le8(nonce)
+        );
+        const builder = program.methods
+          .pullInstant(solPrice)
+          .accountsPartial({
+            playerState,
+            userStats: userStatsOf(gacha, user.publicKey),
+            gachaState: gacha,
+            paymentConfig: config,
+            user: user.publicKey,
+            paymentMint: solMint,
+            userPaymentAccount: user.publicKey,
+            paymentVault: config,
+            slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+            instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+            priceFeed: null,
+            systemProgram: SystemProgram.programId,
+            tokenProgram: null,
+          })
+          .signers([user]);
+        return { nonce, playerState, builder };
+      };
+      const memo = new TransactionInstruction({
+        programId: new PublicKey("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"),
+        keys: [],
+        data: Buffer.from("check the draw"),
+      });
+
+      // Nothing may follow the pull, and only known programs may precede it
+      try {
+        await (await pullInstant()).builder.postInstructions([memo]).rpc();
+        expect.fail("Should be the last instruction of the transaction");
+      } catch (error) {
+        expect(error.toString()).to.include("InstantPullNotTopLevel");
+      }
+      try {
+        await (await pullInstant()).builder.preInstructions([memo]).rpc();
+        expect.fail("Should only follow allowed programs");
+      } catch (error) {
+        expect(error.toString()).to.include("InstantPullUnsafeTransaction");
+      }
+      // Calls through CPI fail with InstantPullNotTopLevel, see the program's unit tests
+
+      const snapshot = await drawSnapshot(gacha, user.publicKey);
+      const { nonce, playerState, builder } = await pullInstant();
+      await builder
+        .preInstructions([
+          ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 }),
+        ])
+        .rpc();
+
+      // Settled in the same instruction, from the recorded slot hash
+      const playerStateAccount = await program.account.playerState.fetch(
+        playerState
+      );
+      const expected = predictDraw(
+        snapshot.tiers,
+        snapshot.pityThreshold,
+        snapshot.stats,
+        sha256(
+          Buffer.from("gacha-machine/instant"),
+          Buffer.from(playerStateAccount.commitment.slotHash),
+          gacha.toBuffer(),
+          user.publicKey.toBuffer(),
+          le8(nonce)
+        )
+      );
+      expect(playerStateAccount.isSettled).to.be.true;
+      expect(playerStateAccount.resultTier).to.equal(expected.tier);
+      expect(playerStateAccount.resultIndex).to.equal(expected.keyIndex);
+      const gachaStateAccount = await program.account.gachaState.fetch(gacha);
+      expect(gachaStateAccount.pullCount.toNumber()).to.equal(1);
+      expect(gachaStateAccount.settleCount.toNumber()).to.equal(1);
+      expect(
+        (
+          await program.account.paymentConfig.fetch(config)
+        ).settledAmount.toString()
+      ).to.equal(solPrice.toString());
+
+      expect(await claimPull(gacha, playerState)).to.equal(
+        ([] as string[]).concat(...instantKeys)[expected.keyIndex]
+      );
+    });
+
+    it("should close a machine whose pulls were all refunded", async () => {
+      const { gacha, config } = await createDeterministicMachine(
+        8,
+        [["e2e_refunded_0", "e2e_refunded_1"]],
+        [1]
+      );
+      const { playerState } = await pullSol(gacha, config, user);
+
+      const closeKeyPage = () =>
+        program.methods
+          .closeKeyPage()
+          .accountsPartial({
+            keyPage: keyPageOf(gacha, 0),
+            gachaState: gacha,
+            admin: admin.publicKey,
+          })
+          .signers([admin])
+          .rpc();
+      try {
+        await closeKeyPage();
+        expect.fail("Should not close a machine with a pending pull");
+      } catch (error) {
+        expect(error.toString()).to.include("GachaNotComplete");
+      }
+
+      await program.methods
+        .setHalted(true)
+        .accountsPartial({ gachaState: gacha, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      await refundSol(gacha, config, user.publicKey, playerState, admin);
+
+      // No key was settled, but nothing is pending either
+      await closeKeyPage();
+      await program.methods
+        .closeMachine()
+        .accountsPartial({ gachaState: gacha, admin: admin.publicKey })
+        .remainingAccounts([
+          { pubkey: config, isWritable: true, isSigner: false },
+        ])
+        .signers([admin])
+        .rpc();
+      expect(await program.account.gachaState.fetchNullable(gacha)).to.be.null;
+    });
+
+    it("should close player states and the machine once every key is claimed", async () => {
+      const { gacha, config } = await createDeterministicMachine(
+        4,
+        [["e2e_last_0", "e2e_last_1", "e2e_last_2"]],
+        [1]
+      );
+
+      const pulls = [];
+      for (let i = 0; i < 3; i++) {
+        pulls.push(await pullSol(gacha, config, user));
+      }
+      await waitForNextSlot();
+      for (const { playerState } of pulls) {
+        await settlePull(gacha, config, user.publicKey, playerState, user);
+        await claimPull(gacha, playerState);
+      }
+
+      const closePlayerState = (playerState: PublicKey) =>
+        closePull(gacha, playerState);
+
+      const rent = await lamports(pulls[0].playerState);
+      const userBalanceBefore = await lamports(user.publicKey);
+      await closePlayerState(pulls[0].playerState);
+      expect((await lamports(user.publicKey)) - userBalanceBefore).to.equal(
+        rent
+      );
+      expect(
+        await program.account.playerState.fetchNullable(pulls[0].playerState)
+      ).to.be.null;
+
+      // Key pages and payments have to be drained before the machine closes
+      const closeMachine = () =>
+        program.methods
+          .closeMachine()
+          .accountsPartial({ gachaState: gacha, admin: admin.publicKey })
+          .remainingAccounts([
+            { pubkey: config, isWritable: true, isSigner: false },
+          ])
+          .signers([admin])
+          .rpc();
+      try {
+        await closeMachine();
+        expect.fail("Should not close a machine with open key pages");
+      } catch (error) {
+        expect(error.toString()).to.include("KeyPagesNotClosed");
+      }
+      await program.methods
+        .closeKeyPage()
+        .accountsPartial({
+          keyPage: keyPageOf(gacha, 0),
+          gachaState: gacha,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      try {
+        await closeMachine();
+        expect.fail("Should not close a machine holding payments");
+      } catch (error) {
+        expect(error.toString()).to.include("VaultNotEmpty");
+      }
+
+      await withdrawSol(gacha, config, admin, solPrice.muln(3));
+      await closeMachine();
+
+      expect(await program.account.gachaState.fetchNullable(gacha)).to.be.null;
+      expect(await program.account.paymentConfig.fetchNullable(config)).to.be
+        .null;
+
+      // Receipts of a closed machine can still be closed
+      await closePlayerState(pulls[1].playerState);
+      expect(
+        await program.account.playerState.fetchNullable(pulls[1].playerState)
+      ).to.be.null;
+    });
   });
 
   describe("error handling", () => {
     it("should handle key pool limits", async () => {
-      // This test would add MAX_KEYS (500) keys to test the limit
+      // This test would fill a key page (KEYS_PER_PAGE = 64) to test the limit
       // Skipping for performance in unit tests, but this would be the approach:
       /*
-      for (let i = 0; i < 500; i++) {
+      for (let i = 0; i < 64; i++) {
         await program.methods
-          .addKey(`key_${i}`)
+          .addKey(`key_${i}`, 0)
           .accounts({...})
           .signers([admin])
           .rpc();
@@ -607,10 +3324,10 @@ describe("gacha_machine", () => {
 
       // This should fail
       try {
-        await program.methods.addKey("overflow_key")...
+        await program.methods.addKey("overflow_key", 0)...
         expect.fail("Should have failed at key limit");
       } catch (error) {
-        expect(error.toString()).to.include("KeyPoolFull");
+        expect(error.toString()).to.include("KeyPageFull");
       }
       */
     });
@@ -638,7 +3355,7 @@ describe("gacha_machine", () => {
         gachaState
       );
       expect(gachaStateAccount.isFinalized).to.be.true;
-      expect(gachaStateAccount.encryptedKeys.length).to.be.greaterThan(0);
+      expect(gachaStateAccount.totalKeys).to.be.greaterThan(0);
     });
 
     it("should handle multiple payment configurations", async () => {